@@ -0,0 +1,146 @@
+//! Saving a system to disk, so a long experiment can pick up where it left
+//! off if the server goes down.
+//!
+//! Sensor readings don't survive this. They're handed straight back to
+//! whoever called `sense`, and that caller is gone after a restart, so a
+//! sense that hadn't run yet still moves its droplet once it's restored, but
+//! nobody gets the reading.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::command::{Command, CommandRequest, RunStatus, SavedCommand};
+use crate::grid::{gridview::GridSubView, parse::ParsedGrid, Droplet, DropletId, GridView};
+use crate::plan::graph::GraphError;
+use crate::process::{ProcessId, PuddleError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedProcess {
+    pub name: String,
+    pub next_droplet_id: usize,
+    /// The user the process belongs to, if the server checks who's who.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// Everything needed to pick a system back up. Commands that already ran
+/// aren't kept, just the droplets they left on the board.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub grid: ParsedGrid,
+    pub droplets: Vec<Droplet>,
+    /// The commands that haven't run yet, in an order they can be added
+    /// back in.
+    pub commands: Vec<SavedCommand>,
+    pub processes: IndexMap<ProcessId, SavedProcess>,
+    #[serde(default)]
+    pub quotas: IndexMap<ProcessId, usize>,
+    #[serde(default)]
+    pub failures: IndexMap<ProcessId, String>,
+}
+
+impl Checkpoint {
+    pub fn load(path: impl AsRef<Path>) -> Result<Checkpoint, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let checkpoint = serde_json::from_reader(file)?;
+        Ok(checkpoint)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        // write somewhere else first, so a crash doesn't leave half a file
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        serde_json::to_writer(File::create(&tmp)?, self)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum RestoreError {
+    DifferentGrid,
+    PuddleError(PuddleError),
+    GraphError(GraphError),
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::RestoreError::*;
+        match self {
+            DifferentGrid => write!(f, "checkpoint was made on a different grid"),
+            PuddleError(err) => write!(f, "{}", err),
+            GraphError(err) => write!(f, "couldn't rebuild the command graph: {:?}", err),
+        }
+    }
+}
+
+impl Error for RestoreError {}
+
+/// Stands in for whatever made a droplet before the checkpoint, so the
+/// commands that use it have something to hang off of. It's already done,
+/// so it never runs.
+#[derive(Debug)]
+pub(crate) struct Restored {
+    pub output: DropletId,
+}
+
+impl Command for Restored {
+    fn output_droplets(&self) -> Vec<DropletId> {
+        vec![self.output]
+    }
+
+    fn request(&self, _gridview: &GridView) -> CommandRequest {
+        unreachable!("Restored droplets are already on the board")
+    }
+
+    fn run(&mut self, _gridview: &mut GridSubView) -> RunStatus {
+        unreachable!("Restored droplets are already on the board")
+    }
+
+    fn save(&self) -> SavedCommand {
+        unreachable!("Restored droplets are already on the board")
+    }
+}
+
+/// The checkpoint file is named by this variable. If it's not set, nothing
+/// is saved or restored.
+const CHECKPOINT_VAR: &str = "PUDDLE_CHECKPOINT";
+
+pub(crate) fn load_checkpoint() -> Option<Checkpoint> {
+    let path = env::var(CHECKPOINT_VAR).ok()?;
+
+    if !Path::new(&path).exists() {
+        info!("No checkpoint at {}, starting fresh", path);
+        return None;
+    }
+
+    match Checkpoint::load(&path) {
+        Ok(checkpoint) => {
+            info!("Loaded checkpoint from {}", path);
+            Some(checkpoint)
+        }
+        Err(err) => {
+            error!("Failed to load checkpoint from {}. {}", path, err);
+            None
+        }
+    }
+}
+
+pub(crate) fn checkpoint_enabled() -> bool {
+    env::var(CHECKPOINT_VAR).is_ok()
+}
+
+pub(crate) fn save_checkpoint(checkpoint: &Checkpoint) {
+    if let Ok(path) = env::var(CHECKPOINT_VAR) {
+        match checkpoint.save(&path) {
+            Ok(()) => debug!("Saved checkpoint to {}", path),
+            Err(err) => error!("Failed to save checkpoint to {}. {}", path, err),
+        }
+    }
+}
@@ -1,12 +1,15 @@
 use std::fmt;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+
 use crate::plan::PlanError;
 
 use crate::grid::{
     gridview::{GridSubView, GridView},
     location::yx,
-    Blob, Droplet, DropletId, Grid, Location, Peripheral, SimpleBlob,
+    Blob, Droplet, DropletId, Footprint, Grid, Location, Peripheral, SimpleBlob,
 };
 
 use crate::process::PuddleResult;
@@ -37,6 +40,24 @@ pub trait Command: fmt::Debug + Send {
         false
     }
 
+    /// The peripheral this command needs, if any. Only the kind (and name)
+    /// of the peripheral matters, not the channels.
+    fn peripheral(&self) -> Option<Peripheral> {
+        None
+    }
+
+    /// Guesses the dimensions of the output droplets before anything runs,
+    /// given guesses for the inputs. Used for static analysis.
+    fn output_dimensions(&self, input_dims: &[Location]) -> Vec<Location> {
+        // by default, droplets pass through unchanged
+        let n_outputs = self.output_droplets().len();
+        if input_dims.len() == n_outputs {
+            input_dims.to_vec()
+        } else {
+            vec![yx(1, 1); n_outputs]
+        }
+    }
+
     fn request(&self, gridview: &GridView) -> CommandRequest;
 
     // FIXME this is definitely a hack for combining droplets
@@ -51,10 +72,137 @@ pub trait Command: fmt::Debug + Send {
     fn abort(&mut self, err: PlanError) {
         error!("Aborting command {:?} with {:#?}", self, err);
     }
+
+    /// What the command did besides moving droplets around, once it's
+    /// done. These are recorded so a replay can check them, see `record`.
+    fn action(&self) -> Option<Action> {
+        None
+    }
+
+    /// Describes the command as data, so it can be written down and made
+    /// again later. Only needs to work for commands that haven't run yet.
+    fn save(&self) -> SavedCommand;
 }
 
 pub type BoxedCommand = Box<dyn Command>;
 
+/// Something a command did to a droplet through a peripheral.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    Heat {
+        droplet: DropletId,
+        temperature: f32,
+        duration: Duration,
+    },
+    Sense {
+        droplet: DropletId,
+        sensor: String,
+        reading: SensorReading,
+    },
+}
+
+/// A command that hasn't run yet, written down as data.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SavedCommand {
+    Create {
+        location: Option<Location>,
+        volume: f64,
+        footprint: Footprint,
+        out: DropletId,
+    },
+    Move {
+        droplet: DropletId,
+        to: Location,
+        out: DropletId,
+    },
+    Combine {
+        droplets: (DropletId, DropletId),
+        /// Whether the first droplet stays put, see `Combine::combine_into`.
+        pinned: bool,
+        out: DropletId,
+    },
+    Agitate {
+        droplet: DropletId,
+        out: DropletId,
+    },
+    Split {
+        droplet: DropletId,
+        out: (DropletId, DropletId),
+    },
+    Heat {
+        droplet: DropletId,
+        temperature: f32,
+        duration: Duration,
+        out: DropletId,
+    },
+    Sense {
+        sensor: String,
+        droplet: DropletId,
+        out: DropletId,
+    },
+    Input {
+        substance: String,
+        volume: f64,
+        dimensions: Location,
+        out: DropletId,
+    },
+    Output {
+        port: String,
+        droplet: DropletId,
+    },
+}
+
+impl SavedCommand {
+    pub fn load(self) -> PuddleResult<BoxedCommand> {
+        use self::SavedCommand as S;
+        let cmd: BoxedCommand = match self {
+            S::Create {
+                location,
+                volume,
+                footprint,
+                out,
+            } => Box::new(Create::with_footprint(location, volume, footprint, out)?),
+            S::Move { droplet, to, out } => Box::new(Move::new(droplet, to, out)?),
+            S::Combine {
+                droplets: (d0, d1),
+                pinned: false,
+                out,
+            } => Box::new(Combine::new(d0, d1, out)?),
+            S::Combine {
+                droplets: (d0, d1),
+                pinned: true,
+                out,
+            } => Box::new(Combine::combine_into(d0, d1, out)?),
+            S::Agitate { droplet, out } => Box::new(Agitate::new(droplet, out)?),
+            S::Split {
+                droplet,
+                out: (out0, out1),
+            } => Box::new(Split::new(droplet, out0, out1)?),
+            S::Heat {
+                droplet,
+                temperature,
+                duration,
+                out,
+            } => Box::new(Heat::new(droplet, out, temperature, duration)?),
+            S::Sense {
+                sensor,
+                droplet,
+                out,
+            } => Box::new(Sense::new(sensor, droplet, out)?),
+            S::Input {
+                substance,
+                volume,
+                dimensions,
+                out,
+            } => Box::new(Input::new(substance, volume, dimensions, out)?),
+            S::Output { port, droplet } => Box::new(Output::new(port, droplet)?),
+        };
+        Ok(cmd)
+    }
+}
+
 //
 //  Create
 //
@@ -64,7 +212,7 @@ pub struct Create {
     inputs: Vec<DropletId>,
     outputs: Vec<DropletId>,
     location: Option<Location>,
-    dimensions: Location,
+    footprint: Footprint,
     volume: f64,
 }
 
@@ -75,12 +223,22 @@ impl Create {
         vol: f64,
         dim: Option<Location>,
         out_id: DropletId,
+    ) -> PuddleResult<Create> {
+        let footprint = Footprint::rectangle(dim.unwrap_or_else(|| yx(1, 1)));
+        Create::with_footprint(loc, vol, footprint, out_id)
+    }
+
+    pub fn with_footprint(
+        loc: Option<Location>,
+        vol: f64,
+        footprint: Footprint,
+        out_id: DropletId,
     ) -> PuddleResult<Create> {
         Ok(Create {
             inputs: vec![],
             outputs: vec![out_id],
             location: loc,
-            dimensions: dim.unwrap_or_else(|| yx(1, 1)),
+            footprint,
             volume: vol,
         })
     }
@@ -95,26 +253,40 @@ impl Command for Create {
         self.outputs.clone()
     }
 
+    fn output_dimensions(&self, _input_dims: &[Location]) -> Vec<Location> {
+        vec![self.footprint.dimensions()]
+    }
+
     fn request(&self, _gridview: &GridView) -> CommandRequest {
-        let grid = Grid::rectangle(self.dimensions.y as usize, self.dimensions.x as usize);
+        let grid = Grid::from_footprint(&self.footprint);
+        let anchor = self.footprint.anchor();
 
         CommandRequest {
             name: format!("create -> {:?}", self.outputs[0]),
             shape: grid,
             input_locations: vec![],
-            offset: self.location,
+            offset: self.location.map(|loc| loc - anchor),
         }
     }
 
     fn run(&mut self, gridview: &mut GridSubView) -> RunStatus {
-        gridview.insert(Droplet::new(
+        gridview.insert(Droplet::with_footprint(
             self.outputs[0],
             self.volume,
-            yx(0, 0),
-            self.dimensions,
+            self.footprint.anchor(),
+            self.footprint.clone(),
         ));
         RunStatus::Done
     }
+
+    fn save(&self) -> SavedCommand {
+        SavedCommand::Create {
+            location: self.location,
+            volume: self.volume,
+            footprint: self.footprint.clone(),
+            out: self.outputs[0],
+        }
+    }
 }
 
 //
@@ -149,12 +321,13 @@ impl Command for Move {
 
     fn request(&self, gridview: &GridView) -> CommandRequest {
         let old_id = self.inputs[0];
-        let dim = gridview.droplets[&old_id].dimensions;
+        let footprint = &gridview.droplets[&old_id].footprint;
+        let anchor = footprint.anchor();
         CommandRequest {
             name: format!("move({:?}, {:?})", self.inputs[0], self.outputs[0]),
-            shape: Grid::rectangle(dim.y as usize, dim.x as usize),
-            input_locations: vec![yx(0, 0)],
-            offset: Some(self.destination[0]),
+            shape: Grid::from_footprint(footprint),
+            input_locations: vec![anchor],
+            offset: Some(self.destination[0] - anchor),
         }
     }
 
@@ -167,6 +340,14 @@ impl Command for Move {
         gridview.insert(d);
         RunStatus::Done
     }
+
+    fn save(&self) -> SavedCommand {
+        SavedCommand::Move {
+            droplet: self.inputs[0],
+            to: self.destination[0],
+            out: self.outputs[0],
+        }
+    }
 }
 
 //
@@ -205,16 +386,38 @@ impl Combine {
     fn combined(&self, d0: &Droplet, d1: &Droplet) -> SimpleBlob {
         // FIXME this is a hack
         // right now we only support vertical stacking
+        let (dim0, dim1) = (d0.dimensions(), d1.dimensions());
         if self.pin_d0 {
-            assert!(d0.location.y > d1.dimensions.y);
+            assert!(d0.location.y > dim1.y);
+        }
+        // the combined droplet is a rectangle, so its anchor is the upper
+        // left corner of d0's bounding box, moved up to make room for d1
+        let corner0 = d0.location - d0.footprint.anchor();
+        let corner = corner0 - yx(dim1.y, 0);
+        let volume = d0.volume + d1.volume;
+
+        // irregular droplets keep their shapes, d1's sitting right on top of
+        // d0's, unless the two pieces wouldn't touch
+        if !d0.footprint.is_rectangle() || !d1.footprint.is_rectangle() {
+            let corner1 = d1.location - d1.footprint.anchor();
+            let mut locs: Vec<_> = d0.locations().collect();
+            locs.extend(d1.locations().map(|l| l - corner1 + corner));
+            if let Some((location, footprint)) = Footprint::from_locations(&locs) {
+                return SimpleBlob {
+                    location,
+                    footprint,
+                    volume,
+                };
+            }
         }
+
         SimpleBlob {
-            location: d0.location - yx(d1.dimensions.y, 0),
-            dimensions: Location {
-                y: (d0.dimensions.y + d1.dimensions.y),
-                x: d0.dimensions.x.max(d1.dimensions.x),
-            },
-            volume: d0.volume + d1.volume,
+            location: corner,
+            footprint: Footprint::rectangle(Location {
+                y: (dim0.y + dim1.y),
+                x: dim0.x.max(dim1.x),
+            }),
+            volume,
         }
     }
 }
@@ -228,6 +431,12 @@ impl Command for Combine {
         self.outputs.clone()
     }
 
+    fn output_dimensions(&self, input_dims: &[Location]) -> Vec<Location> {
+        // see `combined`, the droplets are stacked vertically
+        let (dim0, dim1) = (input_dims[0], input_dims[1]);
+        vec![yx(dim0.y + dim1.y, dim0.x.max(dim1.x))]
+    }
+
     // FIXME remove bypass
     // fn bypass(&self, gridview: &GridView) -> bool {
     //     let droplets = &gridview.snapshot().droplets;
@@ -262,14 +471,12 @@ impl Command for Combine {
         let d1 = &gridview.droplets[id1];
 
         let combined = self.combined(d0, d1);
+        let dim = combined.footprint.dimensions();
 
         if self.pin_d0 {
             CommandRequest {
                 name: format!("combine({:?}, {:?}) pin", d0.id, d1.id),
-                shape: Grid::rectangle(
-                    combined.dimensions.y as usize,
-                    combined.dimensions.x as usize,
-                ),
+                shape: Grid::rectangle(dim.y as usize, dim.x as usize),
                 input_locations: vec![d0.location, combined.location],
                 offset: None,
             }
@@ -278,13 +485,13 @@ impl Command for Combine {
                 name: format!("combine({:?}, {:?})", d0.id, d1.id),
                 shape: Grid::rectangle(
                     // we need the plus 1 to ensure a gap
-                    combined.dimensions.y as usize + 1,
-                    combined.dimensions.x as usize,
+                    dim.y as usize + 1,
+                    dim.x as usize,
                 ),
                 input_locations: vec![
                     // we need the plus 1 to ensure a gap
-                    yx(d1.dimensions.y + 1, 0),
-                    yx(0, 0),
+                    yx(d1.dimensions().y + 1, 0) + d0.footprint.anchor(),
+                    d1.footprint.anchor(),
                 ],
                 offset: None,
             }
@@ -307,6 +514,14 @@ impl Command for Combine {
         gridview.insert(combined.to_droplet(out));
         RunStatus::Done
     }
+
+    fn save(&self) -> SavedCommand {
+        SavedCommand::Combine {
+            droplets: (self.inputs[0], self.inputs[1]),
+            pinned: self.pin_d0,
+            out: self.outputs[0],
+        }
+    }
 }
 
 //
@@ -347,14 +562,15 @@ impl Command for Agitate {
 
     fn request(&self, gridview: &GridView) -> CommandRequest {
         let droplet = &gridview.droplets[&self.inputs[0]];
+        let dim = droplet.dimensions();
 
         CommandRequest {
             name: format!("agitate({:?})", self.inputs[0]),
             shape: Grid::rectangle(
-                droplet.dimensions.y as usize + AGITATE_PADDING,
-                droplet.dimensions.x as usize + AGITATE_PADDING,
+                dim.y as usize + AGITATE_PADDING,
+                dim.x as usize + AGITATE_PADDING,
             ),
-            input_locations: vec![yx(0, 0)],
+            input_locations: vec![droplet.footprint.anchor()],
             offset: None,
         }
     }
@@ -388,6 +604,13 @@ impl Command for Agitate {
             RunStatus::KeepGoing
         }
     }
+
+    fn save(&self) -> SavedCommand {
+        SavedCommand::Agitate {
+            droplet: self.inputs[0],
+            out: self.outputs[0],
+        }
+    }
 }
 
 //
@@ -422,6 +645,11 @@ impl Command for Split {
         self.outputs.clone()
     }
 
+    fn output_dimensions(&self, input_dims: &[Location]) -> Vec<Location> {
+        let dim = input_dims[0];
+        vec![yx((dim.y + 1) / 2, dim.x); 2]
+    }
+
     // FIXME skip bypass
     // fn bypass(&self, gridview: &GridView) -> bool {
     //     let droplets = &gridview.snapshot().droplets;
@@ -439,11 +667,11 @@ impl Command for Split {
     fn request(&self, gridview: &GridView) -> CommandRequest {
         let d0 = &gridview.droplets[&self.inputs[0]];
         // we only split in the y right now, so we don't need x padding
-        let x_dim = d0.dimensions.x as usize;
-        let y_dim = (d0.dimensions.y as usize) + SPLIT_PADDING;
+        let x_dim = d0.dimensions().x as usize;
+        let y_dim = (d0.dimensions().y as usize) + SPLIT_PADDING;
         let grid = Grid::rectangle(y_dim, x_dim);
 
-        let input_locations = vec![yx(2, 0)];
+        let input_locations = vec![yx(2, 0) + d0.footprint.anchor()];
 
         CommandRequest {
             name: format!("split({:?})", self.inputs[0]),
@@ -464,7 +692,7 @@ impl Command for Split {
             let y_dim = {
                 // limit the scope of d0 borrow
                 let d0 = gridview.get(&self.inputs[0]);
-                (d0.dimensions.y as usize) + SPLIT_PADDING
+                (d0.dimensions().y as usize) + SPLIT_PADDING
             };
 
             let d = gridview.remove(&inp);
@@ -472,16 +700,18 @@ impl Command for Split {
 
             // TODO: this should be related to volume in some fashion
             // currently, take the ceiling of the division of the split by two
+            let d_dim = d.dimensions();
             let dim = Location {
-                x: d.dimensions.x,
-                y: (d.dimensions.y + 1) / 2,
+                x: d_dim.x,
+                y: (d_dim.y + 1) / 2,
             };
 
             let loc0 = yx(1, 0);
             let loc1 = yx(y_dim as i32 - (dim.y + 1), 0);
 
-            gridview.insert(Droplet::new(out0, vol, loc0, dim));
-            gridview.insert(Droplet::new(out1, vol, loc1, dim));
+            let [(anchor0, fp0), (anchor1, fp1)] = split_footprint(&d);
+            gridview.insert(Droplet::with_footprint(out0, vol, loc0 + anchor0, fp0));
+            gridview.insert(Droplet::with_footprint(out1, vol, loc1 + anchor1, fp1));
 
             RunStatus::KeepGoing
         } else {
@@ -491,6 +721,39 @@ impl Command for Split {
             RunStatus::Done
         }
     }
+
+    fn save(&self) -> SavedCommand {
+        SavedCommand::Split {
+            droplet: self.inputs[0],
+            out: (self.outputs[0], self.outputs[1]),
+        }
+    }
+}
+
+/// Cuts a droplet's shape into top and bottom halves, each as tall as
+/// `Split` makes them, returning where each half's anchor sits relative to
+/// the corner of its half. Rectangles split into rectangles; an irregular
+/// shape keeps its rows on each side of the cut, unless they'd fall apart,
+/// in which case that half just fills its bounding box.
+fn split_footprint(d: &Droplet) -> [(Location, Footprint); 2] {
+    let d_dim = d.dimensions();
+    let dim = yx((d_dim.y + 1) / 2, d_dim.x);
+    let rectangle = (yx(0, 0), Footprint::rectangle(dim));
+    if d.footprint.is_rectangle() {
+        return [rectangle.clone(), rectangle];
+    }
+
+    let corner = d.location - d.footprint.anchor();
+    let half = |start: i32| {
+        let locs: Vec<_> = d
+            .locations()
+            .map(|l| l - corner - yx(start, 0))
+            .filter(|l| 0 <= l.y && l.y < dim.y)
+            .collect();
+        Footprint::from_locations(&locs).unwrap_or_else(|| rectangle.clone())
+    };
+
+    [half(0), half(d_dim.y - dim.y)]
 }
 
 #[derive(Debug)]
@@ -528,11 +791,18 @@ impl Command for Heat {
         self.outputs.clone()
     }
 
+    fn peripheral(&self) -> Option<Peripheral> {
+        Some(Peripheral::Heater {
+            pwm_channel: 0,
+            spi_channel: 0,
+        })
+    }
+
     fn request(&self, gridview: &GridView) -> CommandRequest {
         let d = &gridview.droplets[&self.inputs[0]];
         // we only split in the x right now, so we don't need y padding
-        let x_dim = d.dimensions.x as usize;
-        let y_dim = d.dimensions.y as usize;
+        let x_dim = d.dimensions().x as usize;
+        let y_dim = d.dimensions().y as usize;
 
         // right now we can only heat droplets that are 1x1
         // assert_eq!(y_dim, 1);
@@ -567,6 +837,139 @@ impl Command for Heat {
         gridview.insert(d);
         RunStatus::Done
     }
+
+    fn action(&self) -> Option<Action> {
+        Some(Action::Heat {
+            droplet: self.inputs[0],
+            temperature: self.temperature,
+            duration: self.duration,
+        })
+    }
+
+    fn save(&self) -> SavedCommand {
+        SavedCommand::Heat {
+            droplet: self.inputs[0],
+            temperature: self.temperature,
+            duration: self.duration,
+            out: self.outputs[0],
+        }
+    }
+}
+
+/// What a sensor said about a droplet.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SensorReading {
+    pub value: f64,
+    /// There's no real sensor behind this reading. The simulator doesn't
+    /// have any, so it reports the droplet's volume as the value.
+    pub simulated: bool,
+}
+
+/// Where a `Sense` command puts its reading, shared with whoever asked.
+pub type Reading = Arc<Mutex<Option<SensorReading>>>;
+
+#[derive(Debug)]
+pub struct Sense {
+    sensor: String,
+    inputs: Vec<DropletId>,
+    outputs: Vec<DropletId>,
+    reading: Reading,
+}
+
+impl Sense {
+    pub fn new(sensor: String, id: DropletId, out_id: DropletId) -> PuddleResult<Sense> {
+        Ok(Sense {
+            sensor,
+            inputs: vec![id],
+            outputs: vec![out_id],
+            reading: Reading::default(),
+        })
+    }
+
+    /// The reading will be filled in once the command has run.
+    pub fn reading(&self) -> Reading {
+        Arc::clone(&self.reading)
+    }
+}
+
+impl Command for Sense {
+    fn input_droplets(&self) -> Vec<DropletId> {
+        self.inputs.clone()
+    }
+
+    fn output_droplets(&self) -> Vec<DropletId> {
+        self.outputs.clone()
+    }
+
+    fn peripheral(&self) -> Option<Peripheral> {
+        Some(Peripheral::Sensor {
+            spi_channel: 0,
+            name: self.sensor.clone(),
+        })
+    }
+
+    fn request(&self, gridview: &GridView) -> CommandRequest {
+        let d = &gridview.droplets[&self.inputs[0]];
+
+        // like output, the sensor just has to be under the anchor
+        let anchor = d.footprint.anchor();
+        let mut grid = Grid::from_footprint(&d.footprint);
+        grid.get_cell_mut(anchor).unwrap().peripheral = self.peripheral();
+
+        CommandRequest {
+            name: format!("sense({:?}, {})", d.id, self.sensor),
+            shape: grid,
+            input_locations: vec![anchor],
+            offset: None,
+        }
+    }
+
+    fn run(&mut self, gridview: &mut GridSubView) -> RunStatus {
+        // the sensor is under the anchor, see `request`
+        let anchor = gridview.get(&self.inputs[0]).footprint.anchor();
+        let reading = if gridview.has_hardware() {
+            let value = gridview.read_sensor(anchor);
+            if value.is_none() {
+                warn!("Couldn't read sensor {}", self.sensor);
+            }
+            value.map(|value| SensorReading {
+                value,
+                simulated: false,
+            })
+        } else {
+            // there's no real sensor behind the simulator, so just report
+            // the droplet's volume, and say so
+            let volume = gridview.get(&self.inputs[0]).volume;
+            Some(SensorReading {
+                value: volume,
+                simulated: true,
+            })
+        };
+        *self.reading.lock().unwrap() = reading;
+
+        let mut d = gridview.remove(&self.inputs[0]);
+        // NOTE this is a rare place it's ok to change an id, like move
+        d.id = self.outputs[0];
+        gridview.insert(d);
+        RunStatus::Done
+    }
+
+    fn action(&self) -> Option<Action> {
+        let reading = (*self.reading.lock().unwrap())?;
+        Some(Action::Sense {
+            droplet: self.inputs[0],
+            sensor: self.sensor.clone(),
+            reading,
+        })
+    }
+
+    fn save(&self) -> SavedCommand {
+        SavedCommand::Sense {
+            sensor: self.sensor.clone(),
+            droplet: self.inputs[0],
+            out: self.outputs[0],
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -604,6 +1007,17 @@ impl Command for Input {
         self.outputs.clone()
     }
 
+    fn peripheral(&self) -> Option<Peripheral> {
+        Some(Peripheral::Input {
+            pwm_channel: 0,
+            name: self.substance.clone(),
+        })
+    }
+
+    fn output_dimensions(&self, _input_dims: &[Location]) -> Vec<Location> {
+        vec![self.dimensions]
+    }
+
     fn request(&self, _gridview: &GridView) -> CommandRequest {
         assert_eq!(self.outputs.len(), 1);
         // FIXME limitation here
@@ -632,6 +1046,15 @@ impl Command for Input {
         ));
         RunStatus::Done
     }
+
+    fn save(&self) -> SavedCommand {
+        SavedCommand::Input {
+            substance: self.substance.clone(),
+            volume: self.volume,
+            dimensions: self.dimensions,
+            out: self.outputs[0],
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -662,14 +1085,22 @@ impl Command for Output {
         vec![]
     }
 
+    fn peripheral(&self) -> Option<Peripheral> {
+        Some(Peripheral::Output {
+            pwm_channel: 0,
+            name: self.name.clone(),
+        })
+    }
+
     fn request(&self, gridview: &GridView) -> CommandRequest {
         assert_eq!(self.inputs.len(), 1);
         let d = &gridview.droplets[&self.inputs[0]];
 
         // FIXME do we have a limitation here?
-        // assert_eq!(d.dimensions, yx(1, 1));
-        let mut grid = Grid::rectangle(d.dimensions.y as usize, d.dimensions.x as usize);
-        grid.get_cell_mut(yx(0, 0)).unwrap().peripheral = Some(Peripheral::Output {
+        // assert_eq!(d.dimensions(), yx(1, 1));
+        let anchor = d.footprint.anchor();
+        let mut grid = Grid::from_footprint(&d.footprint);
+        grid.get_cell_mut(anchor).unwrap().peripheral = Some(Peripheral::Output {
             pwm_channel: 0,
             name: self.name.clone(),
         });
@@ -677,7 +1108,7 @@ impl Command for Output {
         CommandRequest {
             name: format!("output({:?})", d.id),
             shape: grid,
-            input_locations: vec![yx(0, 0)],
+            input_locations: vec![anchor],
             offset: None,
         }
     }
@@ -687,12 +1118,20 @@ impl Command for Output {
         gridview.remove(&self.inputs[0]);
         RunStatus::Done
     }
+
+    fn save(&self) -> SavedCommand {
+        SavedCommand::Output {
+            port: self.name.clone(),
+            droplet: self.inputs[0],
+        }
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
 
     use super::*;
+    use crate::process::ProcessId;
 
     #[derive(Debug)]
     pub struct Dummy {
@@ -707,6 +1146,12 @@ pub mod tests {
                 outs: outs.iter().map(|u| (*u).into()).collect(),
             }
         }
+        pub fn process(mut self, pid: ProcessId) -> Dummy {
+            for id in self.ins.iter_mut().chain(&mut self.outs) {
+                id.process_id = pid;
+            }
+            self
+        }
         pub fn boxed(self) -> BoxedCommand {
             Box::new(self)
         }
@@ -728,5 +1173,9 @@ pub mod tests {
         fn run(&mut self, _gridview: &mut GridSubView) -> RunStatus {
             unimplemented!()
         }
+
+        fn save(&self) -> SavedCommand {
+            unimplemented!()
+        }
     }
 }
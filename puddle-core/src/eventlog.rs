@@ -0,0 +1,125 @@
+//! A log of what the executor does, written as it happens.
+//!
+//! Each line is one JSON [`Entry`]: the tick it happened on, and the
+//! [`ExecEvent`] itself. Nothing is buffered past a line, so the log is
+//! good up to the moment a run crashes.
+
+use std::env;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::exec::StepInfo;
+use crate::grid::{DropletId, Location, Peripheral};
+use crate::process::ProcessId;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ExecEvent {
+    /// Where the planner put a command. Commands are named by their index
+    /// in the command graph.
+    Placed {
+        cmd: usize,
+        process: Option<ProcessId>,
+        location: Location,
+        dimensions: Location,
+    },
+    /// A droplet's path to where its command was placed.
+    Route {
+        droplet: DropletId,
+        path: Vec<Location>,
+    },
+    CommandStart {
+        cmd: usize,
+        name: String,
+    },
+    CommandFinish {
+        cmd: usize,
+        name: String,
+    },
+    /// The command was stopped before it finished.
+    CommandAborted {
+        cmd: usize,
+        name: String,
+    },
+    /// A command was given the real peripheral under its placement.
+    Peripheral {
+        cmd: usize,
+        location: Location,
+        peripheral: Peripheral,
+    },
+    /// Something couldn't be planned. If it was a process's fault, the
+    /// process is stopped.
+    Error {
+        process: Option<ProcessId>,
+        message: String,
+    },
+    Step {
+        step: StepInfo,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub tick: usize,
+    #[serde(flatten)]
+    pub event: ExecEvent,
+}
+
+/// Writes events to a file, or nowhere if logging is off.
+#[derive(Default)]
+pub struct EventLog {
+    writer: Option<LineWriter<File>>,
+}
+
+/// If this variable is set, the event log is written to the file it names.
+const EVENT_LOG_VAR: &str = "PUDDLE_EVENT_LOG";
+
+impl EventLog {
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<EventLog> {
+        let file = File::create(path)?;
+        let writer = Some(LineWriter::new(file));
+        Ok(EventLog { writer })
+    }
+
+    pub fn from_env() -> EventLog {
+        let path = match env::var(EVENT_LOG_VAR) {
+            Ok(path) => path,
+            Err(_) => return EventLog::default(),
+        };
+        match EventLog::create(&path) {
+            Ok(log) => {
+                info!("Logging events to {}", path);
+                log
+            }
+            Err(err) => {
+                error!("Failed to log events to {}. {}", path, err);
+                EventLog::default()
+            }
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Writes the event, only building it if the log is on.
+    pub fn emit(&mut self, tick: usize, event: impl FnOnce() -> ExecEvent) {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        let entry = Entry {
+            tick,
+            event: event(),
+        };
+        let result = serde_json::to_writer(&mut *writer, &entry)
+            .map_err(|e| e.into())
+            .and_then(|()| writeln!(writer));
+        if let Err(err) = result {
+            error!("Failed to log {:?}. {}", entry, err);
+        }
+    }
+}
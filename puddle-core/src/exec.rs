@@ -1,77 +1,391 @@
+use std::env;
+use std::error::Error;
 use std::fs::File;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Instant, SystemTime};
 
 use crate::command::RunStatus;
+use crate::eventlog::{EventLog, ExecEvent};
 use crate::grid::{DropletId, DropletInfo, Grid, GridView, Location};
+use crate::hardware::SharedHardware;
+use crate::metrics::Metrics;
 use crate::plan::{
     graph::{CmdIndex, Graph},
-    Path, PlanPhase, PlannedCommand,
+    sched::command_process,
+    Path, PlanError, PlanPhase, PlannedCommand,
 };
+use crate::process::ProcessId;
+use crate::record::Event;
+use crate::util::duration_seconds;
 
-use indexmap::IndexMap;
-use serde::Serialize;
+use indexmap::{IndexMap, IndexSet};
+use serde::{Deserialize, Serialize};
 
 pub struct Executor {
     pub gridview: GridView,
     pub running_commands: IndexMap<CmdIndex, PlannedCommand>,
+    pub controls: Arc<Controls>,
+    pub watchers: Arc<Watchers>,
+    pub metrics: Arc<Metrics>,
+    /// Commands the system hasn't planned yet, as of the last time it said.
+    pub(crate) unplanned: usize,
+    /// The phase that's running, if execution stopped partway through one.
+    phase: Option<ActivePhase>,
+    /// Planned commands waiting on their droplets to get there.
+    routing: usize,
     ticks: usize,
-    log: Logger,
+    /// Every step so far, if anyone asked for them. See `keep_steps`.
+    steps: Option<Vec<StepInfo>>,
+    pub events: EventLog,
+    /// Outcomes that haven't been recorded yet, if the system is recording.
+    pub(crate) outcomes: Option<Vec<Event>>,
+    /// The real board, if there is one. Otherwise it's all simulated.
+    hardware: Option<SharedHardware>,
 }
 
-#[derive(Serialize, Clone)]
+/// A phase that's been started but not finished. Execution can stop
+/// between any two ticks and pick this back up later.
+struct ActivePhase {
+    routes: IndexMap<DropletId, Path>,
+    /// How far along their routes the droplets are.
+    route_step: usize,
+    /// Commands that start once the routes are done.
+    waiting: Vec<PlannedCommand>,
+    /// Every command in the phase, finished or not.
+    commands: Vec<CmdIndex>,
+}
+
+/// How far the executor got before it had to stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    Done,
+    /// Execution was paused partway through. Nothing was lost, the executor
+    /// picks up where it left off next time it's run.
+    Paused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RunState {
+    Running,
+    Paused,
+    /// Run this many more ticks, then pause.
+    Stepping(usize),
+}
+
+/// Pauses and resumes the executor between ticks. These don't need the
+/// system lock, so they work in the middle of a flush.
+pub struct Controls {
+    state: Mutex<RunState>,
+    changed: Condvar,
+    /// Processes to stop at the next tick, see `kill`.
+    killed: Mutex<IndexSet<ProcessId>>,
+}
+
+impl Default for Controls {
+    fn default() -> Controls {
+        Controls {
+            state: Mutex::new(RunState::Running),
+            changed: Condvar::new(),
+            killed: Mutex::default(),
+        }
+    }
+}
+
+impl Controls {
+    pub fn state(&self) -> RunState {
+        *self.state.lock().unwrap()
+    }
+
+    fn set(&self, state: RunState) {
+        info!("Executor is now {:?}", state);
+        *self.state.lock().unwrap() = state;
+        self.changed.notify_all();
+    }
+
+    pub fn pause(&self) {
+        self.set(RunState::Paused)
+    }
+
+    pub fn resume(&self) {
+        self.set(RunState::Running)
+    }
+
+    /// Runs `n` more ticks and then pauses.
+    pub fn step(&self, n: usize) {
+        match n {
+            0 => self.set(RunState::Paused),
+            n => self.set(RunState::Stepping(n)),
+        }
+    }
+
+    /// Aborts the process's commands at the next tick, and stops the
+    /// planner from starting any more of them, even in the middle of a
+    /// flush. The system forgets this once it's killed the process itself.
+    pub fn kill(&self, pid: ProcessId) {
+        info!("Stopping process {} at the next tick", pid);
+        self.killed.lock().unwrap().insert(pid);
+    }
+
+    pub(crate) fn forget_kill(&self, pid: ProcessId) {
+        self.killed.lock().unwrap().swap_remove(&pid);
+    }
+
+    pub(crate) fn killed(&self) -> Vec<ProcessId> {
+        self.killed.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Whether the executor is allowed to take another tick. If it's
+    /// stepping, this counts the tick.
+    fn try_tick(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            RunState::Running => true,
+            RunState::Stepping(n) => {
+                *state = match n {
+                    1 => RunState::Paused,
+                    n => RunState::Stepping(n - 1),
+                };
+                self.changed.notify_all();
+                true
+            }
+            RunState::Paused => false,
+        }
+    }
+
+    /// Blocks until execution isn't paused anymore. Don't hold the system
+    /// lock while waiting on this, or nobody else can get in.
+    pub fn wait_while_paused(&self) {
+        let mut state = self.state.lock().unwrap();
+        while *state == RunState::Paused {
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+}
+
+/// A step as it's sent to whoever is watching the board.
+#[derive(Debug, Serialize, Clone)]
+pub struct LiveStep {
+    pub tick: usize,
+    /// Seconds since the Unix epoch.
+    pub time: f64,
+    #[serde(flatten)]
+    pub step: StepInfo,
+}
+
+/// What the board looks like right now, as of the last tick.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Snapshot {
+    pub tick: usize,
+    pub droplets: Vec<DropletInfo>,
+    pub modules: Vec<ModuleInfo>,
+    /// Commands that have been added but haven't finished.
+    pub pending: usize,
+}
+
+/// Everyone watching the board live, and what they last saw. Like
+/// `Controls`, this doesn't need the system lock.
+#[derive(Default)]
+pub struct Watchers {
+    senders: Mutex<Vec<Sender<Arc<LiveStep>>>>,
+    latest: Mutex<Snapshot>,
+}
+
+impl Watchers {
+    /// Doesn't wait for a flush, so it's safe to call while one is running.
+    pub fn snapshot(&self) -> Snapshot {
+        self.latest.lock().unwrap().clone()
+    }
+
+    fn show(&self, snapshot: Snapshot) {
+        *self.latest.lock().unwrap() = snapshot;
+    }
+
+    /// Gets every step from here on. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> Receiver<Arc<LiveStep>> {
+        let (tx, rx) = channel();
+        self.senders.lock().unwrap().push(tx);
+        rx
+    }
+
+    fn publish(&self, tick: usize, step: &StepInfo) {
+        let mut senders = self.senders.lock().unwrap();
+        if senders.is_empty() {
+            return;
+        }
+        let since_epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let live = Arc::new(LiveStep {
+            tick,
+            time: duration_seconds(&since_epoch),
+            step: step.clone(),
+        });
+        senders.retain(|tx| tx.send(Arc::clone(&live)).is_ok());
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ModuleInfo {
     name: String,
     location: Location,
     dimensions: Location,
 }
 
-#[derive(Serialize, Clone)]
+/// Where a command sits on the board, if it takes up any of it.
+fn module_info(planned: &PlannedCommand) -> Option<ModuleInfo> {
+    let location = *planned.placement.mapping.values().min()?;
+    Some(ModuleInfo {
+        name: planned.request.name.clone(),
+        location,
+        dimensions: Location {
+            y: planned.request.shape.max_height() as i32,
+            x: planned.request.shape.max_width() as i32,
+        },
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct StepInfo {
     droplets: Vec<DropletInfo>,
     modules: Vec<ModuleInfo>,
 }
 
-struct Logger {
-    steps: Vec<StepInfo>,
-}
-
-pub enum ExecResponse {
-    Ok,
-}
-
 impl Executor {
     pub fn new(grid: Grid) -> Executor {
         info!("Creating an Executor");
         Executor {
             gridview: GridView::new(grid),
             running_commands: IndexMap::default(),
+            controls: Arc::new(Controls::default()),
+            watchers: Arc::new(Watchers::default()),
+            metrics: Arc::new(Metrics::default()),
+            unplanned: 0,
+            phase: None,
+            routing: 0,
             ticks: 0,
-            log: Logger { steps: vec![] },
+            steps: env::var(STEP_LOG_VAR).ok().map(|_| Vec::new()),
+            events: EventLog::from_env(),
+            outcomes: None,
+            hardware: None,
         }
     }
 
+    /// Drives real hardware along with the simulation from here on.
+    pub fn set_hardware(&mut self, hardware: SharedHardware) {
+        hardware.lock().unwrap().output_pins(&self.gridview);
+        self.hardware = Some(hardware);
+    }
+
+    pub(crate) fn has_hardware(&self) -> bool {
+        self.hardware.is_some()
+    }
+
+    /// Why the hardware can't be driven, if there's hardware and it can't.
+    pub(crate) fn hardware_error(&self) -> Option<String> {
+        let status = self.hardware.as_ref()?.lock().unwrap().status();
+        if status.hv507_initialized {
+            return None;
+        }
+        Some(
+            status
+                .error
+                .unwrap_or_else(|| "HV507 isn't initialized".into()),
+        )
+    }
+
+    /// Brings the hardware in line with the gridview, if there's hardware.
+    pub(crate) fn output_pins(&mut self) {
+        if let Some(hardware) = &self.hardware {
+            hardware.lock().unwrap().output_pins(&self.gridview);
+        }
+    }
+
+    /// Holds on to every step from here on, for `get_logs`. This is off
+    /// unless the step log is, since a long run takes a lot of steps; the
+    /// event log is the better way to keep them.
+    pub fn keep_steps(&mut self) {
+        self.steps.get_or_insert_with(Vec::new);
+    }
+
     pub fn get_logs(&self) -> &[StepInfo] {
-        &self.log.steps
+        self.steps.as_deref().unwrap_or(&[])
     }
 
-    fn add_to_log(&mut self) {
+    fn step_info(&self) -> StepInfo {
         let modules: Vec<_> = self
             .running_commands
             .values()
-            .map(|planned| ModuleInfo {
-                name: planned.request.name.clone(),
-                location: *planned.placement.mapping.values().min().unwrap(),
-                dimensions: Location {
-                    y: planned.request.shape.max_height() as i32,
-                    x: planned.request.shape.max_width() as i32,
-                },
-            })
+            .filter_map(module_info)
             .collect();
 
         let droplets = self.gridview.droplet_info(None);
-        self.log.steps.push(StepInfo { modules, droplets })
+        StepInfo { modules, droplets }
+    }
+
+    /// Updates what the watchers see without taking a step.
+    pub(crate) fn show(&self) {
+        let StepInfo { droplets, modules } = self.step_info();
+        self.metrics.show(self.ticks, droplets.len());
+        self.watchers.show(Snapshot {
+            tick: self.ticks,
+            droplets,
+            modules,
+            pending: self.unplanned + self.routing + self.running_commands.len(),
+        });
+    }
+
+    fn add_to_log(&mut self) {
+        let step = self.step_info();
+        self.show();
+        self.watchers.publish(self.ticks, &step);
+        if self.events.is_enabled() {
+            let step = step.clone();
+            self.events.emit(self.ticks, || ExecEvent::Step { step });
+        }
+        if let Some(outcomes) = &mut self.outcomes {
+            let step = step.clone();
+            outcomes.push(Event::Step { step });
+        }
+        if let Some(steps) = &mut self.steps {
+            steps.push(step)
+        }
+    }
+
+    fn start_command(&mut self, planned: &PlannedCommand, graph: &Graph) {
+        let cmd = planned.cmd_id.index();
+        let ticks = self.ticks;
+        if let Some(module) = module_info(planned) {
+            self.events.emit(ticks, || ExecEvent::Placed {
+                cmd,
+                process: command_process(graph, planned.cmd_id),
+                location: module.location,
+                dimensions: module.dimensions,
+            });
+        }
+        self.events.emit(ticks, || ExecEvent::CommandStart {
+            cmd,
+            name: planned.request.name.clone(),
+        });
+
+        let grid = &self.gridview.grid;
+        for &location in planned.placement.mapping.values() {
+            let electrode = grid.get_cell(location);
+            if let Some(peripheral) = electrode.and_then(|e| e.peripheral.as_ref()) {
+                self.events.emit(ticks, || ExecEvent::Peripheral {
+                    cmd,
+                    location,
+                    peripheral: peripheral.clone(),
+                });
+            }
+        }
     }
 
     fn run_all_commands(&mut self, graph: &mut Graph) {
+        for pid in self.controls.killed() {
+            self.abort_process(pid, graph);
+        }
+
         let mut done = Vec::new();
 
         debug!("Run step, {} active commands", self.running_commands.len());
@@ -84,7 +398,10 @@ impl Executor {
                 .expect("node not in graph")
                 .as_mut()
                 .expect("node unbound");
-            let subview = &mut self.gridview.subview(&planned_cmd.placement);
+            let subview = &mut self
+                .gridview
+                .subview(&planned_cmd.placement)
+                .with_hardware(self.hardware.as_ref());
 
             // write down if they are done
             debug!("Running command: {:?}", cmd);
@@ -93,7 +410,15 @@ impl Executor {
                     info!("Finalizing a command");
 
                     cmd.finalize(subview);
+                    if let (Some(outcomes), Some(action)) = (&mut self.outcomes, cmd.action()) {
+                        outcomes.push(Event::Action { action });
+                    }
                     done.push(planned_cmd.cmd_id);
+                    let name = planned_cmd.request.name.clone();
+                    let cmd = cmd_id.index();
+                    let ticks = self.ticks;
+                    self.events
+                        .emit(ticks, || ExecEvent::CommandFinish { cmd, name });
                 }
                 RunStatus::KeepGoing => (),
             }
@@ -109,50 +434,162 @@ impl Executor {
 
     fn commit(&mut self) {
         self.ticks += 1;
+        self.gridview.actuate_droplets();
+        self.output_pins();
         self.add_to_log();
     }
 
-    fn take_routes(&mut self, paths: &IndexMap<DropletId, Path>, graph: &mut Graph) {
-        let max_len = paths.values().map(Vec::len).max().unwrap_or(0);
+    /// Starts running a phase. Nothing happens until `resume` is called.
+    pub fn start(&mut self, phase: PlanPhase) {
+        info!("Run step");
+        assert!(self.phase.is_none(), "a phase is already running");
+        let PlanPhase {
+            routes,
+            planned_commands,
+        } = phase;
+
+        for (&droplet, path) in routes.iter() {
+            let path = path.clone();
+            self.events
+                .emit(self.ticks, || ExecEvent::Route { droplet, path });
+        }
 
         // make sure that all droplets start where they are at this time step
-        for (id, path) in paths.iter() {
+        for (id, path) in routes.iter() {
             let droplet = &self.gridview.droplets[id];
             assert_eq!(droplet.location, path[0]);
         }
 
-        for i in 1..max_len {
-            for (id, path) in paths.iter() {
-                if i < path.len() {
-                    let droplet = self.gridview.droplets.get_mut(id).unwrap();
-                    assert!(droplet.location.distance_to(path[i]) <= 1);
-                    droplet.location = path[i];
+        let commands = planned_commands.iter().map(|p| p.cmd_id).collect();
+        self.routing = planned_commands.len();
+        self.phase = Some(ActivePhase {
+            routes,
+            route_step: 1,
+            waiting: planned_commands,
+            commands,
+        });
+    }
+
+    /// Whether a phase has been started and hasn't finished.
+    pub fn in_phase(&self) -> bool {
+        self.phase.is_some()
+    }
+
+    /// Whether the command is part of the phase that's running.
+    pub fn in_current_phase(&self, cmd_id: CmdIndex) -> bool {
+        let mut phase = self.phase.iter();
+        phase.any(|phase| phase.commands.contains(&cmd_id))
+    }
+
+    /// Runs the current phase until it's done or execution is paused.
+    pub fn resume(&mut self, graph: &mut Graph) -> Progress {
+        self.resume_until(graph, None)
+            .expect("ran out of time without a deadline")
+    }
+
+    /// Like `resume`, but also stops before the first tick after `until`,
+    /// returning `None`. The phase picks up where it left off next time.
+    pub(crate) fn resume_until(
+        &mut self,
+        graph: &mut Graph,
+        until: Option<Instant>,
+    ) -> Option<Progress> {
+        loop {
+            let phase = match &mut self.phase {
+                Some(phase) => phase,
+                None => return Some(Progress::Done),
+            };
+            if until.filter(|&until| Instant::now() >= until).is_some() {
+                return None;
+            }
+
+            // this could be inefficient if one route is much much longer than another
+            let max_len = phase.routes.values().map(Vec::len).max().unwrap_or(0);
+            if phase.route_step < max_len {
+                if !self.controls.try_tick() {
+                    return Some(Progress::Paused);
+                }
+                let i = phase.route_step;
+                for (id, path) in phase.routes.iter() {
+                    if i < path.len() {
+                        let droplet = self.gridview.droplets.get_mut(id).unwrap();
+                        let grid = &self.gridview.grid;
+                        assert!(grid.can_step(&droplet.footprint, droplet.location, path[i]));
+                        droplet.location = path[i];
+                    }
+                }
+                phase.route_step += 1;
+                self.run_all_commands(graph);
+                continue;
+            }
+
+            // add all the planned commands, unless they were killed on the way
+            let waiting = std::mem::take(&mut phase.waiting);
+            self.routing = 0;
+            let killed = self.controls.killed();
+            for planned_cmd in waiting {
+                let pid = command_process(graph, planned_cmd.cmd_id);
+                if pid.filter(|pid| killed.contains(pid)).is_some() {
+                    self.abort(planned_cmd, graph);
+                    continue;
                 }
+                self.start_command(&planned_cmd, graph);
+                let was_there = self
+                    .running_commands
+                    .insert(planned_cmd.cmd_id, planned_cmd);
+                assert!(was_there.is_none());
+            }
+
+            // drive all commands to completion, a tick at a time
+            if self.running_commands.is_empty() {
+                self.phase = None;
+                return Some(Progress::Done);
+            }
+            if !self.controls.try_tick() {
+                return Some(Progress::Paused);
             }
             self.run_all_commands(graph);
         }
     }
 
-    pub fn run(&mut self, phase: PlanPhase, graph: &mut Graph) -> ExecResponse {
-        info!("Run step");
-
-        // this could be inefficient if one route is much much longer than another
-        self.take_routes(&phase.routes, graph);
-
-        // add all the planned commands
-        for planned_cmd in phase.planned_commands {
-            let was_there = self
-                .running_commands
-                .insert(planned_cmd.cmd_id, planned_cmd);
-            assert!(was_there.is_none());
+    /// Stops running the process's commands, leaving their droplets
+    /// wherever they are.
+    pub fn abort_process(&mut self, pid: ProcessId, graph: &mut Graph) {
+        let aborted: Vec<_> = self
+            .running_commands
+            .keys()
+            .filter(|&&cmd_id| command_process(graph, cmd_id) == Some(pid))
+            .cloned()
+            .collect();
+        for cmd_id in aborted {
+            let planned = self.running_commands.swap_remove(&cmd_id).unwrap();
+            self.abort(planned, graph);
         }
 
-        // just drive all commands to completion for now
-        while !self.running_commands.is_empty() {
-            self.run_all_commands(graph);
+        // and the ones that haven't started yet
+        let waiting = match &mut self.phase {
+            Some(phase) => std::mem::take(&mut phase.waiting),
+            None => Vec::new(),
+        };
+        for planned in waiting {
+            if command_process(graph, planned.cmd_id) == Some(pid) {
+                self.routing -= 1;
+                self.abort(planned, graph);
+            } else if let Some(phase) = &mut self.phase {
+                phase.waiting.push(planned);
+            }
         }
+    }
 
-        ExecResponse::Ok
+    fn abort(&mut self, planned: PlannedCommand, graph: &mut Graph) {
+        let cmd_id = planned.cmd_id;
+        let name = planned.request.name;
+        let cmd = cmd_id.index();
+        self.events
+            .emit(self.ticks, || ExecEvent::CommandAborted { cmd, name });
+        if let Some(cmd) = graph.graph[cmd_id].as_mut() {
+            cmd.abort(PlanError::Cancelled);
+        }
     }
 
     pub fn ticks(&self) -> usize {
@@ -160,29 +597,29 @@ impl Executor {
     }
 }
 
-impl Logger {
-    fn log_out_to_file(&self) -> Result<(), Box<std::error::Error>> {
-        use std::env::{var, VarError};
+/// If this variable is set, every step is kept and written to the file it
+/// names as one pretty JSON array when the executor goes away. The event
+/// log streams the same steps as they happen, see `eventlog`.
+const STEP_LOG_VAR: &str = "PUDDLE_EXEC_LOG";
 
-        let path = match var("PUDDLE_EXEC_LOG") {
-            Ok(path) => path,
-            Err(VarError::NotPresent) => return Ok(()),
-            err @ Err(_) => err?,
+impl Executor {
+    fn save_steps(&self) -> Result<(), Box<dyn Error>> {
+        let (path, steps) = match (env::var(STEP_LOG_VAR), &self.steps) {
+            (Ok(path), Some(steps)) => (path, steps),
+            _ => return Ok(()),
         };
         let file = File::create(&path)?;
-        serde_json::to_writer_pretty(file, &self.steps)?;
+        serde_json::to_writer_pretty(file, steps)?;
 
-        println!("Logged to file {}", path);
         info!("Logged to file {}", path);
         Ok(())
     }
 }
 
-impl Drop for Logger {
+impl Drop for Executor {
     fn drop(&mut self) {
-        match self.log_out_to_file() {
-            Ok(_) => (),
-            Err(err) => error!("Failed to log to file. {}", err),
+        if let Err(err) = self.save_steps() {
+            error!("Failed to log to file. {}", err);
         }
     }
 }
@@ -4,12 +4,15 @@ use std::sync::atomic::Ordering::Relaxed;
 
 use serde::{Deserialize, Serialize};
 
-use super::{Location, Rectangle};
+use super::{Footprint, Location};
 use crate::process::ProcessId;
-use indexmap::IndexSet;
 
 static NEXT_COLLISION_GROUP: AtomicUsize = AtomicUsize::new(0);
 
+fn new_collision_group() -> usize {
+    NEXT_COLLISION_GROUP.fetch_add(1, Relaxed)
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Hash, Ord, Clone, Copy)] // std
 #[derive(Serialize, Deserialize)] // serde
 pub struct DropletId {
@@ -34,16 +37,18 @@ impl From<usize> for DropletId {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Droplet {
     // The droplet's id should never be modified once it has been created. They
     // are globally unique by construction.
     pub id: DropletId,
     pub location: Location,
-    pub dimensions: Location,
+    pub footprint: Footprint,
     pub volume: f64,
 
     // all this stuff is used for routing
+    // groups are only unique within a run, so loaded droplets get new ones
+    #[serde(skip, default = "new_collision_group")]
     pub collision_group: usize,
     pub pinned: bool,
 }
@@ -56,6 +61,7 @@ pub struct DropletInfo {
     pub location: Location,
     pub volume: f64,
     pub dimensions: Location,
+    pub footprint: Footprint,
 }
 
 impl Droplet {
@@ -64,32 +70,52 @@ impl Droplet {
         if dimensions.y <= 0 || dimensions.x <= 0 {
             panic!("Dimensions for a droplet must be positive integers")
         }
+        Droplet::with_footprint(id, volume, location, Footprint::rectangle(dimensions))
+    }
+
+    /// Creates a new Droplet that occupies an arbitrary set of electrodes.
+    /// The location is the anchor of the footprint.
+    pub fn with_footprint(
+        id: DropletId,
+        volume: f64,
+        location: Location,
+        footprint: Footprint,
+    ) -> Droplet {
+        // connectivity depends on the topology, see `Grid::footprint_is_connected`
+        if footprint.is_empty() {
+            panic!("Footprint for a droplet can't be empty")
+        }
         Droplet {
             id,
             location,
-            dimensions,
+            footprint,
             volume: volume,
-            collision_group: NEXT_COLLISION_GROUP.fetch_add(1, Relaxed),
+            collision_group: new_collision_group(),
             pinned: false,
         }
     }
 
-    fn rectangle(&self) -> Rectangle {
-        Rectangle {
-            location: self.location,
-            dimensions: self.dimensions,
-        }
+    /// The dimensions of the droplet's bounding box.
+    pub fn dimensions(&self) -> Location {
+        self.footprint.dimensions()
+    }
+
+    /// The electrodes this droplet currently covers.
+    pub fn locations(&self) -> impl Iterator<Item = Location> + '_ {
+        self.footprint.locations(self.location)
     }
 
     pub fn collision_distance(&self, other: &Droplet) -> i32 {
-        self.rectangle().collision_distance(&other.rectangle())
+        self.footprint
+            .collision_distance(self.location, &other.footprint, other.location)
     }
 
     pub fn info(&self) -> DropletInfo {
         DropletInfo {
             id: self.id,
             location: self.location,
-            dimensions: self.dimensions,
+            dimensions: self.dimensions(),
+            footprint: self.footprint.clone(),
             volume: self.volume,
         }
     }
@@ -97,7 +123,7 @@ impl Droplet {
     pub fn to_blob(&self) -> SimpleBlob {
         SimpleBlob {
             location: self.location,
-            dimensions: self.dimensions,
+            footprint: self.footprint.clone(),
             volume: self.volume,
         }
     }
@@ -114,10 +140,10 @@ impl Default for Droplet {
         Droplet {
             id: bad_id,
             location: bad_loc,
-            dimensions: bad_loc,
+            footprint: Footprint::rectangle(bad_loc),
             pinned: false,
             volume: 1.0,
-            collision_group: NEXT_COLLISION_GROUP.fetch_add(1, Relaxed),
+            collision_group: new_collision_group(),
         }
     }
 }
@@ -125,7 +151,7 @@ impl Default for Droplet {
 #[derive(Debug, Clone)]
 pub struct SimpleBlob {
     pub location: Location,
-    pub dimensions: Location,
+    pub footprint: Footprint,
     pub volume: f64,
 }
 
@@ -134,59 +160,37 @@ pub trait Blob: Clone {
     fn to_simple_blob(&self) -> SimpleBlob;
     fn to_droplet(&self, id: DropletId) -> Droplet {
         let simple_blob = self.to_simple_blob();
-        Droplet::new(
+        Droplet::with_footprint(
             id,
             simple_blob.volume,
             simple_blob.location,
-            simple_blob.dimensions,
+            simple_blob.footprint,
         )
     }
 }
 
 impl SimpleBlob {
     pub fn from_locations(locs: &[Location]) -> Option<SimpleBlob> {
-        let location = Location {
-            y: locs.iter().map(|l| l.y).min().unwrap_or(0),
-            x: locs.iter().map(|l| l.x).min().unwrap_or(0),
-        };
-        let far_corner = Location {
-            y: locs.iter().map(|l| l.y).max().unwrap_or(0) + 1,
-            x: locs.iter().map(|l| l.x).max().unwrap_or(0) + 1,
-        };
-        let dimensions = far_corner - location;
-
-        let set1: IndexSet<Location> = locs.iter().cloned().collect();
-        let mut set2 = IndexSet::new();
-
-        // build set2 with all the locations the rectangle should have
-        for i in 0..dimensions.y {
-            for j in 0..dimensions.x {
-                set2.insert(Location {
-                    y: location.y + i,
-                    x: location.x + j,
-                });
-            }
-        }
+        let (location, footprint) = Footprint::from_locations(locs)?;
 
-        // using dimensions as volume for now
-        let volume: f64 = (dimensions.x * dimensions.y).into();
-
-        if set1 == set2 {
-            Some(SimpleBlob {
-                location,
-                dimensions,
-                volume,
-            })
-        } else {
-            None
-        }
+        // using the number of electrodes as volume for now
+        let volume = footprint.len() as f64;
+
+        Some(SimpleBlob {
+            location,
+            footprint,
+            volume,
+        })
     }
 }
 
 impl Blob for SimpleBlob {
     fn get_similarity(&self, droplet: &Droplet) -> i32 {
         self.location.distance_to(droplet.location) as i32
-            + self.dimensions.distance_to(droplet.dimensions) as i32
+            + self
+                .footprint
+                .dimensions()
+                .distance_to(droplet.dimensions()) as i32
             + ((self.volume - droplet.volume) as i32).abs()
     }
 
@@ -197,7 +201,8 @@ impl Blob for SimpleBlob {
 
 #[cfg(test)]
 pub mod tests {
-    use super::{Droplet, DropletId, Location};
+    use super::{Droplet, DropletId, Footprint, Location, SimpleBlob};
+    use crate::grid::location::yx;
 
     #[test]
     #[should_panic]
@@ -216,7 +221,7 @@ pub mod tests {
     fn droplet_with_shape(loc: (i32, i32), dim: (i32, i32)) -> Droplet {
         Droplet {
             location: Location { y: loc.0, x: loc.1 },
-            dimensions: Location { y: dim.0, x: dim.1 },
+            footprint: Footprint::rectangle(Location { y: dim.0, x: dim.1 }),
             ..Droplet::default()
         }
     }
@@ -237,4 +242,30 @@ pub mod tests {
         let b = droplet_with_shape((0, 8), (3, 1));
         assert_eq!(a.collision_distance(&b), 0);
     }
+
+    #[test]
+    fn test_irregular_blob() {
+        // aaa
+        // a..
+        let locs = &[yx(2, 1), yx(2, 2), yx(2, 3), yx(3, 1)];
+        let blob = SimpleBlob::from_locations(locs).unwrap();
+        assert_eq!(blob.location, yx(2, 1));
+        assert_eq!(blob.footprint.dimensions(), yx(2, 3));
+        assert_eq!(blob.footprint.len(), 4);
+
+        // .b.
+        // .bb
+        let b = Droplet::with_footprint(
+            3.into(),
+            1.0,
+            yx(3, 3),
+            Footprint::from_offsets(vec![yx(0, 0), yx(1, 0), yx(1, 1)]),
+        );
+        let a = Droplet::with_footprint(1.into(), 1.0, blob.location, blob.footprint);
+        assert_eq!(a.collision_distance(&b), 0);
+        assert_eq!(b.collision_distance(&a), 0);
+
+        // disconnected sets aren't droplets
+        assert!(SimpleBlob::from_locations(&[yx(0, 0), yx(0, 2)]).is_none());
+    }
 }
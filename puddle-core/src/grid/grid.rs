@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use super::Location;
+use super::{Footprint, Location, Topology};
 use indexmap::IndexSet;
 
 use crate::grid::{location::yx, parse::ParsedGrid};
@@ -17,31 +17,44 @@ pub enum Peripheral {
     Heater { pwm_channel: u8, spi_channel: u8 },
     Input { pwm_channel: u8, name: String },
     Output { pwm_channel: u8, name: String },
+    Sensor { spi_channel: u8, name: String },
 }
 
 impl Electrode {
     pub fn is_compatible(&self, other: &Self) -> bool {
-        let (mine, theirs) = match (&self.peripheral, &other.peripheral) {
-            (None, None) => return true,
-            (Some(p1), Some(p2)) => (p1, p2),
-            _ => return false,
-        };
+        match (&self.peripheral, &other.peripheral) {
+            (None, None) => true,
+            (Some(p1), Some(p2)) => p1.is_compatible(p2),
+            _ => false,
+        }
+    }
+}
 
+impl Peripheral {
+    /// Whether these are the same kind of peripheral, ignoring the channels.
+    pub fn is_compatible(&self, other: &Self) -> bool {
         use self::Peripheral::*;
-        match (mine, theirs) {
+        match (self, other) {
             (Input { name: n1, .. }, Input { name: n2, .. }) => n1 == n2,
             (Output { name: n1, .. }, Output { name: n2, .. }) => n1 == n2,
             (Heater { .. }, Heater { .. }) => true,
+            (Sensor { name: n1, .. }, Sensor { name: n2, .. }) => n1 == n2,
             _ => false,
         }
     }
 }
 
 #[derive(Debug, Default, PartialEq, Eq, Clone, Deserialize)]
-#[serde(from = "ParsedGrid")]
+#[serde(try_from = "ParsedGrid")]
 #[serde(into = "ParsedGrid")]
 pub struct Grid {
     pub vec: Vec<Vec<Option<Electrode>>>,
+    /// Electrodes that are known to be broken, these are never used
+    pub dead_electrodes: IndexSet<Location>,
+    pub topology: Topology,
+    /// How many actuations an electrode on this board lasts, see
+    /// `ElectrodeHealth`. This wins over whatever the health file says.
+    pub endurance: Option<u64>,
 }
 
 #[rustfmt::skip]
@@ -98,6 +111,28 @@ impl Grid {
         Grid::from_function(always_cell, h, w)
     }
 
+    /// Makes a grid in the shape of the footprint's bounding box, with
+    /// electrodes only where the footprint has them. The anchor of the
+    /// footprint ends up at `footprint.anchor()`.
+    pub fn from_footprint(footprint: &Footprint) -> Self {
+        let mut pin = 0;
+        let top_left = footprint.top_left();
+        let footprint_cell = |loc: Location| {
+            if footprint.contains(loc + top_left) {
+                let cell = Some(Electrode {
+                    pin: pin,
+                    peripheral: None,
+                });
+                pin += 1;
+                cell
+            } else {
+                None
+            }
+        };
+        let dim = footprint.dimensions();
+        Grid::from_function(footprint_cell, dim.y as usize, dim.x as usize)
+    }
+
     pub fn max_height(&self) -> usize {
         self.vec.len()
     }
@@ -148,7 +183,16 @@ impl Grid {
             })
             .collect();
 
-        Grid { vec }
+        Grid {
+            vec,
+            dead_electrodes: IndexSet::default(),
+            topology: Topology::default(),
+            endurance: None,
+        }
+    }
+
+    pub fn is_dead(&self, loc: Location) -> bool {
+        self.dead_electrodes.contains(&loc)
     }
 
     // from here on out, functions only return valid locations
@@ -201,6 +245,95 @@ impl Grid {
         vec
     }
 
+    /// Returns the electrodes that a droplet at `loc` can move to in one step.
+    pub fn neighbors(&self, loc: Location) -> Vec<Location> {
+        if self.topology.is_square() {
+            return self.neighbors4(loc);
+        }
+        let adjacent = self.topology.adjacent(loc);
+        self.locations_from_offsets(Location { y: 0, x: 0 }, adjacent.iter())
+    }
+
+    /// Returns the electrodes close enough to `loc` that droplets on the two
+    /// would merge. On a square grid, this includes the diagonals.
+    pub fn touching(&self, loc: Location) -> Vec<Location> {
+        if self.topology.is_square() {
+            self.neighbors8(loc)
+        } else {
+            self.neighbors(loc)
+        }
+    }
+
+    /// Returns the offsets a droplet at `loc` could move by in one step,
+    /// including staying put. These aren't necessarily valid locations.
+    pub fn step_offsets(&self, footprint: &Footprint, loc: Location) -> Vec<Location> {
+        if self.topology.is_square() {
+            return NEIGHBORS_5.to_vec();
+        }
+        let mut offsets = vec![Location { y: 0, x: 0 }];
+        offsets.extend(
+            self.neighbors(loc)
+                .into_iter()
+                .filter(|&n| self.can_step(footprint, loc, n))
+                .map(|n| n - loc),
+        );
+        offsets
+    }
+
+    /// Whether a droplet can move from `from` to `to` in one step. Every
+    /// electrode under it has to move to a neighbor, so on a hex grid a
+    /// droplet that covers both even and odd rows can't move diagonally.
+    pub fn can_step(&self, footprint: &Footprint, from: Location, to: Location) -> bool {
+        if from == to {
+            return true;
+        }
+        if !self.neighbors(from).contains(&to) {
+            return false;
+        }
+        // on a square grid, the neighbors are the same for every electrode
+        let offset = to - from;
+        self.topology.is_square()
+            || footprint
+                .locations(from)
+                .all(|loc| self.topology.adjacent(loc).contains(&(loc + offset)))
+    }
+
+    /// Whether a droplet with this footprint holds together on this grid.
+    /// Footprints keep their offsets as they move, and on a hex grid the
+    /// neighbors depend on the row, so there the footprint has to hold
+    /// together on both even and odd rows. Explicit topologies can only be
+    /// checked at a known `anchor`.
+    pub fn footprint_is_connected(&self, footprint: &Footprint, anchor: Option<Location>) -> bool {
+        let adjacent = |loc| self.topology.adjacent(loc);
+        match self.topology {
+            Topology::Square => footprint.is_connected(),
+            Topology::Hex => [yx(0, 0), yx(1, 0)]
+                .iter()
+                .all(|&a| footprint.is_connected_by(a, adjacent)),
+            Topology::Explicit(_) => match anchor {
+                Some(anchor) => footprint.is_connected_by(anchor, adjacent),
+                None => footprint.is_connected(),
+            },
+        }
+    }
+
+    /// Whether droplets with these footprints would merge.
+    pub fn footprints_touch(
+        &self,
+        footprint1: &Footprint,
+        loc1: Location,
+        footprint2: &Footprint,
+        loc2: Location,
+    ) -> bool {
+        if self.topology.is_square() {
+            return footprint1.collision_distance(loc1, footprint2, loc2) <= 0;
+        }
+        let locs2: IndexSet<Location> = footprint2.locations(loc2).collect();
+        footprint1
+            .locations(loc1)
+            .any(|l| locs2.contains(&l) || self.touching(l).iter().any(|n| locs2.contains(n)))
+    }
+
     /// Returns a Vec representing the neighbors of the location combined with
     /// the dimensions of the droplet.
     pub fn neighbors_dimensions(&self, loc: Location, dimensions: Location) -> Vec<Location> {
@@ -227,7 +360,7 @@ impl Grid {
         }
 
         for (loc, _) in self.locations() {
-            for n in self.neighbors4(loc) {
+            for n in self.neighbors(loc) {
                 graph.add_edge(loc, n, ());
             }
         }
@@ -1,4 +1,5 @@
-use crate::grid::{Droplet, DropletId, DropletInfo, Electrode, Grid, Location};
+use crate::grid::{Droplet, DropletId, DropletInfo, Electrode, ElectrodeHealth, Grid, Location};
+use crate::hardware::SharedHardware;
 use crate::plan::place::Placement;
 use crate::process::ProcessId;
 use indexmap::{IndexMap, IndexSet};
@@ -7,6 +8,7 @@ use indexmap::{IndexMap, IndexSet};
 pub struct GridView {
     pub grid: Grid,
     pub droplets: IndexMap<DropletId, Droplet>,
+    pub health: ElectrodeHealth,
 }
 
 use std::fmt;
@@ -15,6 +17,7 @@ impl fmt::Debug for GridView {
         fmt.debug_struct("GridView")
             .field("grid", &"...hiding grid...")
             .field("droplets", &self.droplets)
+            .field("health", &"...hiding health...")
             .finish()
     }
 }
@@ -35,8 +38,25 @@ impl GridView {
             .collect()
     }
 
+    /// Whether a droplet can be put on this location. Dead and worn out
+    /// electrodes are still on the grid, but they shouldn't be used.
+    pub fn is_usable(&self, loc: Location) -> bool {
+        self.grid.get_cell(loc).is_some()
+            && !self.grid.is_dead(loc)
+            && !self.health.is_worn_out(loc)
+    }
+
+    /// Counts an actuation for every electrode underneath a droplet.
+    pub fn actuate_droplets(&mut self) {
+        for droplet in self.droplets.values() {
+            for loc in droplet.locations() {
+                self.health.actuate(loc);
+            }
+        }
+    }
+
     /// Returns an invalid droplet, if any.
-    fn get_collision(&self) -> Option<(i32, Droplet, Droplet)> {
+    fn get_collision(&self) -> Option<(Droplet, Droplet)> {
         for (id1, droplet1) in &self.droplets {
             for (id2, droplet2) in &self.droplets {
                 if id1 == id2 {
@@ -45,9 +65,14 @@ impl GridView {
                 if droplet1.collision_group == droplet2.collision_group {
                     continue;
                 }
-                let distance = droplet1.collision_distance(droplet2);
-                if distance <= 0 {
-                    return Some((distance, droplet1.clone(), droplet2.clone()));
+                let touching = self.grid.footprints_touch(
+                    &droplet1.footprint,
+                    droplet1.location,
+                    &droplet2.footprint,
+                    droplet2.location,
+                );
+                if touching {
+                    return Some((droplet1.clone(), droplet2.clone()));
                 }
             }
         }
@@ -55,7 +80,7 @@ impl GridView {
     }
 
     pub fn check_no_collision(&self) {
-        if let Some((_distance, d1, d2)) = self.get_collision() {
+        if let Some((d1, d2)) = self.get_collision() {
             panic!("Collision!!!!! between {:#?} and {:#?}", d1, d2)
         }
     }
@@ -64,6 +89,7 @@ impl GridView {
         GridSubView {
             backing_gridview: self,
             placement,
+            hardware: None,
         }
     }
 }
@@ -71,9 +97,28 @@ impl GridView {
 pub struct GridSubView<'a> {
     backing_gridview: &'a mut GridView,
     placement: &'a Placement,
+    hardware: Option<&'a SharedHardware>,
 }
 
 impl<'a> GridSubView<'a> {
+    /// Lets commands use the real board's peripherals, see `read_sensor`.
+    pub(crate) fn with_hardware(mut self, hardware: Option<&'a SharedHardware>) -> Self {
+        self.hardware = hardware;
+        self
+    }
+
+    pub fn has_hardware(&self) -> bool {
+        self.hardware.is_some()
+    }
+
+    /// Reads the sensor on the electrode at `loc` on the real board. None if
+    /// there's no hardware, no sensor there, or it couldn't be read.
+    pub fn read_sensor(&self, loc: Location) -> Option<f64> {
+        let sensor = self.get_electrode(loc)?.peripheral.as_ref()?;
+        let hardware = self.hardware?;
+        hardware.lock().unwrap().read_sensor(sensor)
+    }
+
     pub fn get_electrode(&self, loc: Location) -> Option<&Electrode> {
         let actual_loc = self.placement.mapping.get(&loc)?;
         self.backing_gridview.grid.get_cell(*actual_loc)
@@ -143,15 +188,9 @@ impl<'a> GridSubView<'a> {
         let droplet = self.get(id);
         let mapped_to: IndexSet<_> = self.placement.mapping.values().collect();
         // TODO this is pretty slow
-        for i in 0..droplet.dimensions.y {
-            for j in 0..droplet.dimensions.x {
-                let loc = Location {
-                    y: droplet.location.y + i,
-                    x: droplet.location.x + j,
-                };
-                if !mapped_to.contains(&loc) {
-                    panic!("{} was unmapped!, placement: {:#?}", loc, self.placement);
-                }
+        for loc in droplet.locations() {
+            if !mapped_to.contains(&loc) {
+                panic!("{} was unmapped!, placement: {:#?}", loc, self.placement);
             }
         }
     }
@@ -161,32 +200,45 @@ impl<'a> GridSubView<'a> {
         self.check_droplet(id);
     }
 
+    /// Moves a droplet one step, `to` must be adjacent to where it is now.
+    pub fn move_to(&mut self, id: DropletId, to: Location) {
+        let droplet = self.get(&id);
+        let from = droplet.location;
+        let grid = &self.backing_gridview.grid;
+        if !grid.can_step(&droplet.footprint, from, to) {
+            panic!("Can't move {:?} from {} to {}, not adjacent", id, from, to);
+        }
+        self.update(&id, |droplet| droplet.location = to)
+    }
+
+    /// Returns where a droplet could move to in one step.
+    pub fn neighbors(&self, id: &DropletId) -> Vec<Location> {
+        let location = self.get(id).location;
+        self.backing_gridview.grid.neighbors(location)
+    }
+
     pub fn move_west(&mut self, id: DropletId) {
         trace!("Moving droplet {:?} west", id);
-        self.update(&id, |droplet| {
-            droplet.location = droplet.location.west();
-        })
+        let to = self.get(&id).location.west();
+        self.move_to(id, to)
     }
 
     pub fn move_east(&mut self, id: DropletId) {
         trace!("Moving droplet {:?} east", id);
-        self.update(&id, |droplet| {
-            droplet.location = droplet.location.east();
-        })
+        let to = self.get(&id).location.east();
+        self.move_to(id, to)
     }
 
     pub fn move_north(&mut self, id: DropletId) {
         trace!("Moving droplet {:?} north", id);
-        self.update(&id, |droplet| {
-            droplet.location = droplet.location.north();
-        })
+        let to = self.get(&id).location.north();
+        self.move_to(id, to)
     }
 
     pub fn move_south(&mut self, id: DropletId) {
         trace!("Moving droplet {:?} south", id);
-        self.update(&id, |droplet| {
-            droplet.location = droplet.location.south();
-        })
+        let to = self.get(&id).location.south();
+        self.move_to(id, to)
     }
 
     pub fn droplet_info(&self, pid_option: Option<ProcessId>) -> Vec<DropletInfo> {
@@ -279,5 +331,4 @@ pub mod tests {
         // try to move b to an invalid location outside the placement
         sub.update(&c2id('b'), |b| b.location = yx(0, 2))
     }
-
 }
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::grid::{Grid, Location};
+
+/// Tracks how much each electrode has been used.
+///
+/// Electrodes degrade with use, so we count every tick that an electrode
+/// spends under a droplet. Once an electrode has been actuated `endurance`
+/// times, it's considered worn out and the planner won't use it anymore.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ElectrodeHealth {
+    /// Number of actuations after which an electrode is considered worn out.
+    /// If this isn't set, electrodes never wear out.
+    #[serde(default)]
+    pub endurance: Option<u64>,
+    /// Actuation counters, laid out in rows just like the board.
+    #[serde(default)]
+    pub actuations: Vec<Vec<u64>>,
+}
+
+impl ElectrodeHealth {
+    pub fn load(path: impl AsRef<Path>) -> Result<ElectrodeHealth, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let health = serde_json::from_reader(file)?;
+        Ok(health)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn actuations(&self, loc: Location) -> u64 {
+        if loc.x < 0 || loc.y < 0 {
+            return 0;
+        }
+        self.actuations
+            .get(loc.y as usize)
+            .and_then(|row| row.get(loc.x as usize))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    pub fn actuate(&mut self, loc: Location) {
+        *self.counter(loc) += 1;
+    }
+
+    fn counter(&mut self, loc: Location) -> &mut u64 {
+        assert!(loc.x >= 0 && loc.y >= 0);
+        let (i, j) = (loc.y as usize, loc.x as usize);
+        if self.actuations.len() <= i {
+            self.actuations.resize(i + 1, Vec::new());
+        }
+        let row = &mut self.actuations[i];
+        if row.len() <= j {
+            row.resize(j + 1, 0);
+        }
+        &mut row[j]
+    }
+
+    /// Lays the counts out for a new grid. Wear belongs to the electrode,
+    /// not the spot on the board, so each count follows its electrode's pin
+    /// to wherever the new grid puts it. Pins the old grid didn't have
+    /// start from zero.
+    pub fn remap(&self, old: &Grid, new: &Grid) -> ElectrodeHealth {
+        let by_pin: HashMap<u32, u64> = old
+            .locations()
+            .map(|(loc, electrode)| (electrode.pin, self.actuations(loc)))
+            .collect();
+        let mut health = ElectrodeHealth {
+            endurance: self.endurance,
+            actuations: Vec::new(),
+        };
+        for (loc, electrode) in new.locations() {
+            match by_pin.get(&electrode.pin) {
+                Some(&n) if n > 0 => *health.counter(loc) = n,
+                _ => (),
+            }
+        }
+        health
+    }
+
+    /// How far along this electrode is towards its endurance, from 0 to 1.
+    pub fn wear(&self, loc: Location) -> f64 {
+        match self.endurance {
+            Some(0) => 1.0,
+            Some(endurance) => (self.actuations(loc) as f64 / endurance as f64).min(1.0),
+            None => 0.0,
+        }
+    }
+
+    pub fn is_worn_out(&self, loc: Location) -> bool {
+        match self.endurance {
+            Some(endurance) => self.actuations(loc) >= endurance,
+            None => false,
+        }
+    }
+
+    pub fn worn_out<'a>(&'a self) -> impl Iterator<Item = Location> + 'a {
+        self.actuations
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, row)| {
+                (0..row.len())
+                    .map(move |j| Location {
+                        y: i as i32,
+                        x: j as i32,
+                    })
+                    .filter(move |loc| self.is_worn_out(*loc))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::location::yx;
+
+    #[test]
+    fn test_wear_out() {
+        let mut health = ElectrodeHealth {
+            endurance: Some(2),
+            ..ElectrodeHealth::default()
+        };
+
+        health.actuate(yx(1, 3));
+        assert_eq!(health.actuations(yx(1, 3)), 1);
+        assert_eq!(health.actuations(yx(5, 5)), 0);
+        assert!(!health.is_worn_out(yx(1, 3)));
+
+        health.actuate(yx(1, 3));
+        assert!(health.is_worn_out(yx(1, 3)));
+        assert_eq!(health.worn_out().collect::<Vec<_>>(), vec![yx(1, 3)]);
+
+        let s = serde_json::to_string(&health).unwrap();
+        let health2: ElectrodeHealth = serde_json::from_str(&s).unwrap();
+        assert_eq!(health, health2);
+    }
+
+    #[test]
+    fn test_remap_follows_pins() {
+        let old = Grid::rectangle(2, 3);
+        let mut health = ElectrodeHealth::default();
+        health.actuate(yx(0, 1));
+        health.actuate(yx(1, 2));
+        health.actuate(yx(1, 2));
+
+        // the same electrodes, flipped upside down, and one of them gone
+        let mut new = Grid::rectangle(2, 3);
+        for (loc, electrode) in old.locations() {
+            let flipped = yx(1 - loc.y, loc.x);
+            *new.get_cell_mut(flipped).unwrap() = electrode;
+        }
+        let pin = old.get_cell(yx(0, 1)).unwrap().pin;
+        new.get_cell_mut(yx(1, 1)).unwrap().pin = pin + 100;
+
+        let remapped = health.remap(&old, &new);
+        assert_eq!(remapped.actuations(yx(0, 2)), 2);
+        assert_eq!(remapped.actuations(yx(1, 1)), 0);
+        assert_eq!(remapped.actuations(yx(0, 1)), 0);
+    }
+}
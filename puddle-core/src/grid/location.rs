@@ -150,6 +150,177 @@ impl Rectangle {
     }
 }
 
+/// The set of electrodes a droplet occupies, as offsets from an anchor.
+///
+/// The anchor is always an occupied electrode: offsets are kept sorted and
+/// normalized so that the smallest one is `(0, 0)`. For rectangles, this means
+/// the anchor is the upper left corner, just like a `Rectangle`.
+#[derive(PartialEq, Eq, Hash, Clone)] // std
+#[derive(Serialize, Deserialize)] // serde
+#[serde(from = "Vec<Location>", into = "Vec<Location>")]
+pub struct Footprint {
+    offsets: Vec<Location>,
+}
+
+impl fmt::Debug for Footprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(&self.offsets).finish()
+    }
+}
+
+impl From<Vec<Location>> for Footprint {
+    fn from(offsets: Vec<Location>) -> Footprint {
+        Footprint::from_offsets(offsets)
+    }
+}
+
+impl From<Footprint> for Vec<Location> {
+    fn from(footprint: Footprint) -> Vec<Location> {
+        footprint.offsets
+    }
+}
+
+impl Footprint {
+    pub fn rectangle(dimensions: Location) -> Footprint {
+        let offsets = Rectangle::new(yx(0, 0), dimensions).locations().collect();
+        Footprint { offsets }
+    }
+
+    /// Builds a footprint from arbitrary offsets, moving the anchor to the
+    /// smallest one. Duplicates are removed.
+    pub fn from_offsets(mut offsets: Vec<Location>) -> Footprint {
+        offsets.sort();
+        offsets.dedup();
+        if let Some(&min) = offsets.first() {
+            for off in offsets.iter_mut() {
+                *off = *off - min;
+            }
+        }
+        Footprint { offsets }
+    }
+
+    /// Returns the anchor location and the footprint of the given absolute
+    /// locations, or `None` if they are empty or not 4-connected.
+    pub fn from_locations(locs: &[Location]) -> Option<(Location, Footprint)> {
+        let anchor = *locs.iter().min()?;
+        let footprint = Footprint::from_offsets(locs.iter().map(|&l| l - anchor).collect());
+        if footprint.is_connected() {
+            Some((anchor, footprint))
+        } else {
+            None
+        }
+    }
+
+    pub fn offsets(&self) -> &[Location] {
+        &self.offsets
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    pub fn contains(&self, offset: Location) -> bool {
+        self.offsets.binary_search(&offset).is_ok()
+    }
+
+    /// The upper left corner of the bounding box, relative to the anchor.
+    pub fn top_left(&self) -> Location {
+        yx(
+            self.offsets.iter().map(|l| l.y).min().unwrap_or(0),
+            self.offsets.iter().map(|l| l.x).min().unwrap_or(0),
+        )
+    }
+
+    /// The position of the anchor relative to the upper left corner of the
+    /// bounding box. This is `(0, 0)` for rectangles.
+    pub fn anchor(&self) -> Location {
+        yx(0, 0) - self.top_left()
+    }
+
+    /// The height and width of the bounding box.
+    pub fn dimensions(&self) -> Location {
+        if self.offsets.is_empty() {
+            return yx(0, 0);
+        }
+        let far_corner = yx(
+            self.offsets.iter().map(|l| l.y).max().unwrap(),
+            self.offsets.iter().map(|l| l.x).max().unwrap(),
+        );
+        far_corner - self.top_left() + yx(1, 1)
+    }
+
+    pub fn is_rectangle(&self) -> bool {
+        let dim = self.dimensions();
+        self.top_left() == yx(0, 0) && self.offsets.len() == (dim.y * dim.x) as usize
+    }
+
+    /// Whether the footprint is connected on a square grid, where each
+    /// electrode touches the four around it. On other topologies, use
+    /// `Grid::footprint_is_connected`.
+    pub fn is_connected(&self) -> bool {
+        self.is_connected_by(yx(0, 0), |loc| {
+            vec![loc.north(), loc.west(), loc.south(), loc.east()]
+        })
+    }
+
+    /// Whether the footprint is connected when its anchor is at `anchor`,
+    /// given the locations adjacent to each location.
+    pub fn is_connected_by<F>(&self, anchor: Location, adjacent: F) -> bool
+    where
+        F: Fn(Location) -> Vec<Location>,
+    {
+        let mut seen = vec![false; self.offsets.len()];
+        let mut todo = Vec::new();
+        if !self.offsets.is_empty() {
+            seen[0] = true;
+            todo.push(self.offsets[0]);
+        }
+
+        while let Some(off) = todo.pop() {
+            for nbr in adjacent(anchor + off) {
+                if let Ok(i) = self.offsets.binary_search(&(nbr - anchor)) {
+                    if !seen[i] {
+                        seen[i] = true;
+                        todo.push(nbr - anchor);
+                    }
+                }
+            }
+        }
+
+        !seen.is_empty() && seen.iter().all(|&s| s)
+    }
+
+    /// The absolute locations covered when the anchor is at `anchor`.
+    pub fn locations(&self, anchor: Location) -> impl Iterator<Item = Location> + Clone + '_ {
+        self.offsets.iter().map(move |&off| anchor + off)
+    }
+
+    /// The number of empty cells between two placed footprints, counting
+    /// diagonals. Anything less than or equal to 0 is a collision.
+    pub fn collision_distance(&self, loc: Location, other: &Footprint, other_loc: Location) -> i32 {
+        // rectangles are the common case, so avoid the quadratic check
+        if self.is_rectangle() && other.is_rectangle() {
+            let r1 = Rectangle::new(loc, self.dimensions());
+            let r2 = Rectangle::new(other_loc, other.dimensions());
+            return r1.collision_distance(&r2);
+        }
+
+        let chebyshev = |a: Location, b: Location| {
+            let d = a - b;
+            d.y.abs().max(d.x.abs())
+        };
+
+        self.locations(loc)
+            .flat_map(|a| other.locations(other_loc).map(move |b| chebyshev(a, b) - 1))
+            .min()
+            .unwrap_or(i32::MAX)
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -202,4 +373,59 @@ pub mod tests {
             -1,
         );
     }
+
+    #[test]
+    fn test_footprint_from_locations() {
+        // .a
+        // aa
+        let locs = &[yx(3, 5), yx(4, 4), yx(4, 5)];
+        let (anchor, fp) = Footprint::from_locations(locs).unwrap();
+
+        assert_eq!(anchor, yx(3, 5));
+        assert_eq!(fp.offsets(), &[yx(0, 0), yx(1, -1), yx(1, 0)]);
+        assert_eq!(fp.dimensions(), yx(2, 2));
+        assert_eq!(fp.anchor(), yx(0, 1));
+        assert!(!fp.is_rectangle());
+
+        let mut round_trip: Vec<_> = fp.locations(anchor).collect();
+        round_trip.sort();
+        assert_eq!(round_trip, locs);
+
+        // diagonals don't count as connected
+        assert!(Footprint::from_locations(&[yx(0, 0), yx(1, 1)]).is_none());
+        assert!(Footprint::from_locations(&[]).is_none());
+
+        let rect = Footprint::rectangle(yx(2, 3));
+        assert!(rect.is_rectangle());
+        assert_eq!(rect.anchor(), yx(0, 0));
+        assert_eq!(rect.dimensions(), yx(2, 3));
+    }
+
+    #[test]
+    fn test_footprint_distance() {
+        // aa.
+        // a.b
+        // ..b
+        let l_shape = Footprint::from_offsets(vec![yx(0, 0), yx(0, 1), yx(1, 0)]);
+        let bar = Footprint::rectangle(yx(2, 1));
+
+        // the bounding boxes overlap, but the footprints don't
+        assert_eq!(l_shape.collision_distance(yx(0, 0), &bar, yx(1, 2)), 0);
+        assert_eq!(bar.collision_distance(yx(1, 2), &l_shape, yx(0, 0)), 0);
+        assert_eq!(l_shape.collision_distance(yx(0, 0), &bar, yx(1, 3)), 1);
+
+        // overlap
+        assert!(l_shape.collision_distance(yx(0, 0), &bar, yx(0, 1)) < 0);
+
+        // rectangles agree with the Rectangle calculation
+        check_dist(
+            Rectangle::new(yx(0, 4), yx(2, 2)),
+            Rectangle::new(yx(4, 3), yx(1, 4)),
+            Footprint::rectangle(yx(2, 2)).collision_distance(
+                yx(0, 4),
+                &Footprint::rectangle(yx(1, 4)),
+                yx(4, 3),
+            ),
+        );
+    }
 }
@@ -1,10 +1,15 @@
 pub mod droplet;
 pub mod grid;
 pub mod gridview;
+pub mod health;
 pub mod location;
 pub mod parse;
+pub mod topology;
+pub mod validate;
 
 pub use self::droplet::*;
 pub use self::grid::{Electrode, Grid, Peripheral};
 pub use self::gridview::GridView;
-pub use self::location::{Location, Rectangle};
+pub use self::health::ElectrodeHealth;
+pub use self::location::{Footprint, Location, Rectangle};
+pub use self::topology::Topology;
@@ -1,16 +1,20 @@
+use std::convert::TryFrom;
+
+use indexmap::{IndexMap, IndexSet};
 use serde::{Deserialize, Serialize};
 
 use crate::grid::grid::*;
-use crate::grid::Location;
+use crate::grid::validate::{Diagnostic, InvalidGrid, Problem};
+use crate::grid::{Location, Topology};
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Mark {
     #[serde(rename = " ")]
     #[serde(alias = "_")]
     Empty,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ParsedElectrode {
     Index(u32),
@@ -20,22 +24,118 @@ pub enum ParsedElectrode {
 use self::Mark::*;
 use self::ParsedElectrode::*;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ParsedGrid {
     pub board: Vec<Vec<ParsedElectrode>>,
     #[serde(default)]
     pub peripherals: Vec<LocatedPeripheral>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dead_electrodes: Vec<Location>,
+    #[serde(default, skip_serializing_if = "ParsedTopology::is_square")]
+    pub topology: ParsedTopology,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endurance: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct LocatedPeripheral {
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ParsedTopology {
+    #[default]
+    Square,
+    Hex,
+    Explicit {
+        neighbors: Vec<LocatedNeighbors>,
+    },
+}
+
+impl ParsedTopology {
+    fn is_square(&self) -> bool {
+        *self == ParsedTopology::Square
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocatedNeighbors {
     location: Location,
+    neighbors: Vec<Location>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocatedPeripheral {
+    pub location: Location,
     #[serde(flatten)]
-    peripheral: Peripheral,
+    pub peripheral: Peripheral,
 }
 
-impl From<ParsedGrid> for Grid {
-    fn from(pg: ParsedGrid) -> Grid {
+impl ParsedGrid {
+    fn is_cell(&self, loc: Location) -> bool {
+        if loc.y < 0 || loc.x < 0 {
+            return false;
+        }
+        let cell = self
+            .board
+            .get(loc.y as usize)
+            .and_then(|row| row.get(loc.x as usize));
+        match cell {
+            Some(Index(_)) => true,
+            Some(Marked(Empty)) | None => false,
+        }
+    }
+
+    /// Finds the problems that keep this from becoming a Grid.
+    fn errors(&self) -> Vec<Diagnostic> {
+        let mut errors = Vec::new();
+
+        let mut peripheral_locs = IndexMap::<Location, usize>::new();
+        for loc_periph in &self.peripherals {
+            let loc = loc_periph.location;
+            if !self.is_cell(loc) {
+                errors.push(Diagnostic::error(Problem::PeripheralOnEmptyCell, vec![loc]));
+            }
+            *peripheral_locs.entry(loc).or_default() += 1;
+        }
+        for (&loc, &n) in &peripheral_locs {
+            if n > 1 {
+                errors.push(Diagnostic::error(
+                    Problem::OverlappingPeripherals,
+                    vec![loc],
+                ));
+            }
+        }
+
+        for &loc in &self.dead_electrodes {
+            if !self.is_cell(loc) {
+                errors.push(Diagnostic::error(
+                    Problem::DeadElectrodeOnEmptyCell,
+                    vec![loc],
+                ));
+            }
+        }
+
+        if let ParsedTopology::Explicit { neighbors } = &self.topology {
+            let mut bad_locs = IndexSet::new();
+            for ln in neighbors {
+                let locs = std::iter::once(&ln.location).chain(&ln.neighbors);
+                bad_locs.extend(locs.filter(|loc| !self.is_cell(**loc)));
+            }
+            for loc in bad_locs {
+                errors.push(Diagnostic::error(Problem::NeighborOnEmptyCell, vec![loc]));
+            }
+        }
+
+        errors
+    }
+
+    /// Finds everything wrong with this grid, including the warnings that
+    /// `Grid::validate` would give if it was turned into one.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.errors();
+        diagnostics.extend(self.to_grid().validate());
+        diagnostics
+    }
+
+    /// Makes a grid, skipping over anything that doesn't fit on the board.
+    fn to_grid(&self) -> Grid {
         let mut f = |pe: &ParsedElectrode| match pe {
             Marked(Empty) => None,
             Index(n) => Some(Electrode {
@@ -44,24 +144,59 @@ impl From<ParsedGrid> for Grid {
             }),
         };
 
+        let topology = match &self.topology {
+            ParsedTopology::Square => Topology::Square,
+            ParsedTopology::Hex => Topology::Hex,
+            ParsedTopology::Explicit { neighbors } => {
+                Topology::explicit(neighbors.iter().filter(|ln| self.is_cell(ln.location)).map(
+                    |ln| {
+                        let nbrs = ln.neighbors.iter().cloned();
+                        (ln.location, nbrs.filter(|n| self.is_cell(*n)).collect())
+                    },
+                ))
+            }
+        };
+
         let mut grid = Grid {
-            vec: pg
+            vec: self
                 .board
                 .iter()
                 .map(|row| row.iter().map(&mut f).collect())
                 .collect(),
+            dead_electrodes: self
+                .dead_electrodes
+                .iter()
+                .cloned()
+                .filter(|loc| self.is_cell(*loc))
+                .collect(),
+            topology,
+            endurance: self.endurance,
         };
 
-        for loc_periph in pg.peripherals.iter() {
-            let electrode = grid.get_cell_mut(loc_periph.location).unwrap();
-            assert_eq!(electrode.peripheral, None);
-            electrode.peripheral = Some(loc_periph.peripheral.clone());
+        for loc_periph in self.peripherals.iter() {
+            if let Some(electrode) = grid.get_cell_mut(loc_periph.location) {
+                if electrode.peripheral.is_none() {
+                    electrode.peripheral = Some(loc_periph.peripheral.clone());
+                }
+            }
         }
 
         grid
     }
 }
 
+impl TryFrom<ParsedGrid> for Grid {
+    type Error = InvalidGrid;
+    fn try_from(pg: ParsedGrid) -> Result<Grid, InvalidGrid> {
+        let diagnostics = pg.errors();
+        if diagnostics.is_empty() {
+            Ok(pg.to_grid())
+        } else {
+            Err(InvalidGrid { diagnostics })
+        }
+    }
+}
+
 impl From<Grid> for ParsedGrid {
     fn from(grid: Grid) -> ParsedGrid {
         let mut peripherals = Vec::default();
@@ -91,7 +226,27 @@ impl From<Grid> for ParsedGrid {
                     .collect()
             })
             .collect();
-        ParsedGrid { board, peripherals }
+        let dead_electrodes = grid.dead_electrodes.iter().cloned().collect();
+        let topology = match grid.topology {
+            Topology::Square => ParsedTopology::Square,
+            Topology::Hex => ParsedTopology::Hex,
+            Topology::Explicit(neighbors) => ParsedTopology::Explicit {
+                neighbors: neighbors
+                    .into_iter()
+                    .map(|(location, neighbors)| LocatedNeighbors {
+                        location,
+                        neighbors,
+                    })
+                    .collect(),
+            },
+        };
+        ParsedGrid {
+            board,
+            peripherals,
+            dead_electrodes,
+            topology,
+            endurance: grid.endurance,
+        }
     }
 }
 
@@ -194,6 +349,88 @@ pub mod tests {
             serde_yaml::from_str(r#"board: [[_, " ", 0], [2, 3, 4]]"#).expect("parse failed");
     }
 
+    #[test]
+    fn test_parse_dead_electrodes() {
+        let s = r#"
+board: [[0, 1, 2], [3, 4, 5]]
+dead_electrodes: [{y: 1, x: 2}]
+"#;
+        let grid: Grid = serde_yaml::from_str(s).expect("parse failed");
+        assert!(grid.is_dead(yx(1, 2)));
+        assert!(!grid.is_dead(yx(0, 0)));
+        check_round_trip(grid, "dead electrodes");
+    }
+
+    #[test]
+    fn test_parse_endurance() {
+        let s = r#"
+board: [[0, 1, 2], [3, 4, 5]]
+endurance: 1000
+"#;
+        let grid: Grid = serde_yaml::from_str(s).expect("parse failed");
+        assert_eq!(grid.endurance, Some(1000));
+        check_round_trip(grid, "endurance");
+    }
+
+    #[test]
+    fn test_parse_topology() {
+        let hex: Grid = serde_yaml::from_str("{board: [[0, 1]], topology: {type: Hex}}").unwrap();
+        assert_eq!(hex.topology, Topology::Hex);
+        check_round_trip(hex, "hex topology");
+
+        let s = r#"
+board: [[0, 1, 2]]
+topology:
+  type: Explicit
+  neighbors:
+    - location: {y: 0, x: 0}
+      neighbors: [{y: 0, x: 2}]
+"#;
+        let grid: Grid = serde_yaml::from_str(s).expect("parse failed");
+        assert_eq!(grid.neighbors(yx(0, 2)), vec![yx(0, 0)]);
+        assert_eq!(grid.neighbors(yx(0, 1)), vec![]);
+        check_round_trip(grid, "explicit topology");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let s = r#"
+board: [[0, 1], [2, _]]
+peripherals:
+  - location: {y: 1, x: 1}
+    type: Heater
+    pwm_channel: 0
+    spi_channel: 0
+  - location: {y: 0, x: 0}
+    type: Heater
+    pwm_channel: 1
+    spi_channel: 1
+  - location: {y: 0, x: 0}
+    type: Heater
+    pwm_channel: 2
+    spi_channel: 2
+dead_electrodes: [{y: 5, x: 5}]
+"#;
+        let pg: ParsedGrid = serde_yaml::from_str(s).expect("parse failed");
+        let problems: Vec<_> = pg
+            .validate()
+            .into_iter()
+            .map(|d| (d.problem, d.locations))
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                (Problem::PeripheralOnEmptyCell, vec![yx(1, 1)]),
+                (Problem::OverlappingPeripherals, vec![yx(0, 0)]),
+                (Problem::DeadElectrodeOnEmptyCell, vec![yx(5, 5)]),
+            ]
+        );
+
+        // it shouldn't panic, just fail to make a grid
+        let result: Result<Grid, _> = serde_yaml::from_str(s);
+        assert!(result.is_err());
+    }
+
     fn check_round_trip(grid: Grid, desc: &str) {
         let pg: ParsedGrid = grid.clone().into();
         let s = serde_yaml::to_string(&pg).expect("serialization failed");
@@ -222,7 +459,13 @@ pub mod tests {
             debug!("Testing {:?}", entry);
             let path = entry.expect("glob failed");
             let reader = File::open(path.clone()).expect("file not found");
-            let grid = serde_yaml::from_reader(reader).expect("parse failed");
+            let pg: ParsedGrid = serde_yaml::from_reader(reader).expect("parse failed");
+            let diagnostics = pg.validate();
+            for d in &diagnostics {
+                debug!("{}", d);
+            }
+            assert!(diagnostics.iter().all(|d| !d.is_error()));
+            let grid = Grid::try_from(pg).expect("invalid grid");
             check_round_trip(grid, path.to_str().unwrap());
             successes += 1;
         }
@@ -243,10 +486,10 @@ pub mod tests {
         let (grid, blobs) = parse_strings(&strs);
 
         assert_eq!(blobs[&'a'].location, yx(0, 5));
-        assert_eq!(blobs[&'a'].dimensions, yx(2, 2));
+        assert_eq!(blobs[&'a'].footprint.dimensions(), yx(2, 2));
 
         assert_eq!(blobs[&'b'].location, yx(2, 1));
-        assert_eq!(blobs[&'b'].dimensions, yx(1, 2));
+        assert_eq!(blobs[&'b'].footprint.dimensions(), yx(1, 2));
 
         assert_eq!(grid.max_height(), 4);
         assert_eq!(grid.max_width(), 12);
@@ -0,0 +1,133 @@
+use indexmap::IndexMap;
+
+use crate::grid::location::yx;
+use crate::grid::Location;
+
+/// How the electrodes of a board are connected to each other.
+///
+/// Locations are always `(y, x)` pairs, the topology just decides which of
+/// them are adjacent. Droplets can move to an adjacent electrode in one step,
+/// and two droplets on adjacent electrodes will merge.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub enum Topology {
+    /// Square electrodes. Droplets move in the four cardinal directions, but
+    /// diagonal neighbors are still close enough to merge.
+    #[default]
+    Square,
+    /// Hexagonal electrodes in "odd-r" offset coordinates, meaning odd rows
+    /// are shifted half an electrode to the right.
+    Hex,
+    /// Arbitrary electrodes that list their neighbors explicitly.
+    Explicit(IndexMap<Location, Vec<Location>>),
+}
+
+#[rustfmt::skip]
+const HEX_EVEN_ROW: [Location; 6] = [
+    yx(-1, -1), yx(-1, 0),
+    yx( 0, -1), yx( 0, 1),
+    yx( 1, -1), yx( 1, 0),
+];
+
+#[rustfmt::skip]
+const HEX_ODD_ROW: [Location; 6] = [
+    yx(-1, 0), yx(-1, 1),
+    yx( 0, -1), yx( 0, 1),
+    yx( 1, 0), yx( 1, 1),
+];
+
+impl Topology {
+    pub fn is_square(&self) -> bool {
+        *self == Topology::Square
+    }
+
+    /// Returns the adjacent locations for non-square topologies, regardless
+    /// of whether there's actually an electrode there.
+    pub(crate) fn adjacent(&self, loc: Location) -> Vec<Location> {
+        match self {
+            Topology::Square => panic!("Square topology uses the fixed neighborhoods"),
+            Topology::Hex => {
+                let offsets = if loc.y & 1 == 0 {
+                    &HEX_EVEN_ROW
+                } else {
+                    &HEX_ODD_ROW
+                };
+                offsets.iter().map(|off| loc + *off).collect()
+            }
+            Topology::Explicit(neighbors) => neighbors.get(&loc).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// A lower bound on the number of steps it takes to get from `a` to `b`.
+    pub fn distance(&self, a: Location, b: Location) -> u32 {
+        match self {
+            Topology::Square => a.distance_to(b),
+            Topology::Hex => {
+                // convert to cube coordinates, where distance is easy
+                let to_axial = |l: Location| (l.x - (l.y - (l.y & 1)) / 2, l.y);
+                let (q1, r1) = to_axial(a);
+                let (q2, r2) = to_axial(b);
+                let (dq, dr) = (q1 - q2, r1 - r2);
+                ((dq.abs() + dr.abs() + (dq + dr).abs()) / 2) as u32
+            }
+            // we don't know anything about the geometry here
+            Topology::Explicit(_) => 0,
+        }
+    }
+
+    /// Builds an explicit topology from a list of adjacencies, making sure
+    /// that it's symmetric.
+    pub fn explicit<I>(adjacencies: I) -> Topology
+    where
+        I: IntoIterator<Item = (Location, Vec<Location>)>,
+    {
+        let mut map: IndexMap<Location, Vec<Location>> = IndexMap::new();
+        for (loc, nbrs) in adjacencies {
+            for n in nbrs {
+                map.entry(loc).or_default().push(n);
+                map.entry(n).or_default().push(loc);
+            }
+        }
+        for nbrs in map.values_mut() {
+            nbrs.sort();
+            nbrs.dedup();
+        }
+        Topology::Explicit(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_adjacency() {
+        let hex = Topology::Hex;
+        let center = yx(2, 2);
+        for n in hex.adjacent(center) {
+            assert_eq!(hex.distance(center, n), 1);
+            // adjacency should go both ways
+            assert!(hex.adjacent(n).contains(&center));
+        }
+        assert_eq!(hex.distance(yx(0, 0), yx(2, 2)), 3);
+        assert_eq!(hex.distance(yx(0, 0), yx(0, 3)), 3);
+    }
+
+    #[test]
+    fn test_hex_footprint_connected() {
+        use crate::grid::Footprint;
+
+        // touches on even rows, but not on odd ones
+        let fp = Footprint::from_offsets(vec![yx(0, 1), yx(1, 0)]);
+        let adjacent = |loc| Topology::Hex.adjacent(loc);
+        assert!(fp.is_connected_by(yx(0, 0), adjacent));
+        assert!(!fp.is_connected_by(yx(1, 0), adjacent));
+        assert!(!fp.is_connected());
+    }
+
+    #[test]
+    fn test_explicit_symmetric() {
+        let topo = Topology::explicit(vec![(yx(0, 0), vec![yx(0, 1), yx(5, 5)])]);
+        assert_eq!(topo.adjacent(yx(5, 5)), vec![yx(0, 0)]);
+        assert_eq!(topo.adjacent(yx(0, 0)), vec![yx(0, 1), yx(5, 5)]);
+    }
+}
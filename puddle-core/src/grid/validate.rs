@@ -0,0 +1,245 @@
+use std::fmt;
+
+use indexmap::{IndexMap, IndexSet};
+use serde::Serialize;
+
+use crate::grid::{Grid, Location};
+
+/// The HV507 drivers on the purpledrop have 128 outputs, so pins have to be
+/// below this to actually be driven.
+pub const HV507_OUTPUTS: u32 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type")]
+pub enum Problem {
+    PeripheralOnEmptyCell,
+    OverlappingPeripherals,
+    DeadElectrodeOnEmptyCell,
+    NeighborOnEmptyCell,
+    DuplicatePin { pin: u32 },
+    PinsOutOfRange { pins: Vec<u32> },
+    DisconnectedRegion,
+}
+
+/// Something wrong with a grid, and where it is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub problem: Problem,
+    pub locations: Vec<Location>,
+}
+
+impl Diagnostic {
+    pub fn error(problem: Problem, locations: Vec<Location>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            problem,
+            locations,
+        }
+    }
+
+    pub fn warning(problem: Problem, locations: Vec<Location>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            problem,
+            locations,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::Problem::*;
+        match self.severity {
+            Severity::Warning => write!(f, "warning: ")?,
+            Severity::Error => write!(f, "error: ")?,
+        }
+        match &self.problem {
+            PeripheralOnEmptyCell => write!(f, "peripheral is not on an electrode")?,
+            OverlappingPeripherals => write!(f, "more than one peripheral on an electrode")?,
+            DeadElectrodeOnEmptyCell => write!(f, "dead electrode is not on the board")?,
+            NeighborOnEmptyCell => write!(f, "topology mentions a location not on the board")?,
+            DuplicatePin { pin } => write!(f, "pin {} is used by more than one electrode", pin)?,
+            PinsOutOfRange { pins } => write!(
+                f,
+                "pins {} are beyond the {} outputs of the HV507",
+                abbreviate(pins),
+                HV507_OUTPUTS
+            )?,
+            DisconnectedRegion => write!(f, "region is disconnected from the rest of the board")?,
+        }
+        write!(f, " at {}", abbreviate(&self.locations))
+    }
+}
+
+/// Lists the first few things, there can be a lot of them on big grids.
+fn abbreviate<T: fmt::Display>(items: &[T]) -> String {
+    const MAX_ITEMS: usize = 6;
+    let mut strs: Vec<_> = items.iter().take(MAX_ITEMS).map(T::to_string).collect();
+    if items.len() > MAX_ITEMS {
+        strs.push(format!("and {} more", items.len() - MAX_ITEMS));
+    }
+    strs.join(", ")
+}
+
+/// The errors that kept a grid from being built.
+#[derive(Debug)]
+pub struct InvalidGrid {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for InvalidGrid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invalid grid:")?;
+        for d in &self.diagnostics {
+            writeln!(f, "  {}", d)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidGrid {}
+
+impl Grid {
+    /// Looks for things that make this grid hard or impossible to use. This
+    /// only finds warnings for now, since anything worse can't be a Grid.
+    /// Some of them are errors on real hardware, see `check_pins`.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        let mut pins: IndexMap<u32, Vec<Location>> = IndexMap::new();
+        for (loc, electrode) in self.locations() {
+            pins.entry(electrode.pin).or_default().push(loc);
+        }
+        for (&pin, locs) in &pins {
+            if locs.len() > 1 {
+                let problem = Problem::DuplicatePin { pin };
+                diagnostics.push(Diagnostic::warning(problem, locs.clone()));
+            }
+        }
+
+        // big simulated grids will have lots of these, so lump them together
+        let (out_of_range, locs): (Vec<u32>, Vec<&Vec<Location>>) =
+            pins.iter().filter(|(&pin, _)| pin >= HV507_OUTPUTS).unzip();
+        if !out_of_range.is_empty() {
+            let problem = Problem::PinsOutOfRange { pins: out_of_range };
+            let locs = locs.into_iter().flatten().cloned().collect();
+            diagnostics.push(Diagnostic::warning(problem, locs));
+        }
+
+        // everything but the biggest region is disconnected
+        let mut regions = self.regions();
+        regions.sort_by_key(|r| std::cmp::Reverse(r.len()));
+        for region in regions.into_iter().skip(1) {
+            let problem = Problem::DisconnectedRegion;
+            diagnostics.push(Diagnostic::warning(problem, region));
+        }
+
+        diagnostics
+    }
+
+    /// Checks that real hardware can drive this grid. A pin past the HV507's
+    /// outputs can't be driven at all, and electrodes that share a pin move
+    /// droplets that weren't asked to move, so those warnings are errors.
+    pub fn check_pins(&self) -> Result<(), InvalidGrid> {
+        let diagnostics: Vec<_> = self
+            .validate()
+            .into_iter()
+            .filter(|d| {
+                matches!(
+                    d.problem,
+                    Problem::DuplicatePin { .. } | Problem::PinsOutOfRange { .. }
+                )
+            })
+            .map(|d| Diagnostic::error(d.problem, d.locations))
+            .collect();
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidGrid { diagnostics })
+        }
+    }
+
+    /// Returns the connected regions of the grid, according to its topology.
+    fn regions(&self) -> Vec<Vec<Location>> {
+        let mut seen = IndexSet::new();
+        let mut regions = Vec::new();
+
+        for (start, _) in self.locations() {
+            if seen.contains(&start) {
+                continue;
+            }
+            seen.insert(start);
+            let mut region = vec![start];
+            let mut i = 0;
+            while i < region.len() {
+                for n in self.neighbors(region[i]) {
+                    if seen.insert(n) {
+                        region.push(n);
+                    }
+                }
+                i += 1;
+            }
+            region.sort();
+            regions.push(region);
+        }
+
+        regions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::location::yx;
+
+    #[test]
+    fn test_grid_warnings() {
+        let mut grid = Grid::rectangle(2, 3);
+        // duplicate a pin and put one out of range
+        grid.get_cell_mut(yx(0, 0)).unwrap().pin = 5;
+        grid.get_cell_mut(yx(1, 1)).unwrap().pin = 200;
+        // and cut off the corner
+        grid.vec[0][1] = None;
+        grid.vec[1][0] = None;
+
+        let diagnostics = grid.validate();
+        assert!(diagnostics.iter().all(|d| !d.is_error()));
+
+        let problems: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.problem.clone(), d.locations.clone()))
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                (Problem::DuplicatePin { pin: 5 }, vec![yx(0, 0), yx(1, 2)]),
+                (Problem::PinsOutOfRange { pins: vec![200] }, vec![yx(1, 1)]),
+                (Problem::DisconnectedRegion, vec![yx(0, 0)]),
+            ]
+        );
+
+        // hardware can't drive the pins, but a disconnected region is fine
+        let errors = grid.check_pins().unwrap_err().diagnostics;
+        assert!(errors.iter().all(|d| d.is_error()));
+        let problems: Vec<_> = errors.into_iter().map(|d| d.problem).collect();
+        assert_eq!(
+            problems,
+            vec![
+                Problem::DuplicatePin { pin: 5 },
+                Problem::PinsOutOfRange { pins: vec![200] },
+            ]
+        );
+        assert!(Grid::rectangle(2, 3).check_pins().is_ok());
+    }
+}
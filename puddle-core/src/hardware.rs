@@ -0,0 +1,26 @@
+//! Real devices for the executor to drive. The core only simulates the
+//! board, a backend like `puddle-pi` plugs in here to move real droplets.
+
+use std::sync::{Arc, Mutex};
+
+use crate::grid::{GridView, Peripheral};
+use crate::status::HardwareStatus;
+
+pub trait Hardware: Send {
+    /// Turns on the electrodes under the droplets and turns off the rest.
+    /// The grid says which pin each electrode is on, so this is also what
+    /// puts a new grid's pins into effect.
+    fn output_pins(&mut self, gv: &GridView);
+
+    /// Checks on the devices, for `Manager::status`.
+    fn status(&mut self) -> HardwareStatus;
+
+    /// Reads the sensor, for the `Sense` command. None means it couldn't be
+    /// read, or the board doesn't have it.
+    fn read_sensor(&mut self, sensor: &Peripheral) -> Option<f64>;
+}
+
+/// Hardware the executor drives and the status checks look at. It's only
+/// locked for one tick's output at a time, so checking on it doesn't wait
+/// for a flush.
+pub type SharedHardware = Arc<Mutex<Box<dyn Hardware>>>;
@@ -0,0 +1,728 @@
+use indexmap::IndexMap;
+
+use crate::command::{self, BoxedCommand};
+use crate::grid::location::yx;
+use crate::grid::{DropletId, Location};
+use crate::lang::parse::{BinOp, Expr, ExprKind, Function, Pattern, Program, Stmt, StmtKind};
+use crate::lang::{ErrorKind, LangError, LangResult};
+use crate::protocol::CompiledProtocol;
+use crate::util::seconds_duration;
+
+const MAX_DEPTH: usize = 64;
+const MAX_ITERATIONS: i64 = 10_000;
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    Str(String),
+    Droplet(DropletId),
+    Tuple(Vec<Value>),
+    /// An argument of a function that's being checked before it's called,
+    /// so we don't know what it is yet. The number picks out its `Probe`.
+    Any(usize),
+}
+
+impl Value {
+    fn unit() -> Value {
+        Value::Tuple(Vec::new())
+    }
+
+    /// Whether this value holds droplets, and so has to be used exactly once.
+    fn is_linear(&self) -> bool {
+        match self {
+            Value::Droplet(_) => true,
+            Value::Tuple(vs) => vs.iter().any(Value::is_linear),
+            Value::Number(_) | Value::Str(_) | Value::Any(_) => false,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "a number",
+            Value::Str(_) => "a string",
+            Value::Droplet(_) => "a droplet",
+            Value::Tuple(_) => "a tuple",
+            Value::Any(_) => "anything",
+        }
+    }
+}
+
+struct Binding {
+    value: Value,
+    line: usize,
+}
+
+/// A function argument of unknown type. Once it's used as a droplet, it has
+/// to stay that way, so using it again is an error like any other droplet.
+struct Probe {
+    name: String,
+    used: Option<usize>,
+}
+
+/// The variables of a function body, or of the top level.
+#[derive(Default)]
+struct Scope {
+    vars: IndexMap<String, Binding>,
+    /// Droplet variables that have been used, and where.
+    used: IndexMap<String, usize>,
+}
+
+struct Compiler<'a> {
+    functions: IndexMap<String, &'a Function>,
+    params: IndexMap<String, f64>,
+    overrides: &'a IndexMap<String, f64>,
+    new_id: &'a mut dyn FnMut() -> DropletId,
+    commands: Vec<(usize, BoxedCommand)>,
+    depth: usize,
+    /// Set while checking function definitions, see `check_function`.
+    checking: bool,
+    probes: Vec<Probe>,
+    /// Names declared with `param` anywhere at the top level, which
+    /// functions can refer to before they're evaluated.
+    param_names: Vec<String>,
+}
+
+fn err<T>(line: usize, kind: ErrorKind) -> LangResult<T> {
+    Err(LangError::new(line, kind))
+}
+
+fn type_error<T>(line: usize, expected: &'static str, found: &Value) -> LangResult<T> {
+    let found = found.type_name();
+    err(line, ErrorKind::Type { expected, found })
+}
+
+// when checking, anything could be a number, and 1 won't divide by zero
+fn number(line: usize, v: Value) -> LangResult<f64> {
+    match v {
+        Value::Number(n) => Ok(n),
+        Value::Any(_) => Ok(1.0),
+        v => type_error(line, "a number", &v),
+    }
+}
+
+fn loop_bound(line: usize, v: Value) -> LangResult<i64> {
+    let n = number(line, v)?.ceil();
+    // i64::MAX rounds up to 2^63, which is already too big
+    if n.is_finite() && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Ok(n as i64)
+    } else {
+        err(line, ErrorKind::BadLoopBound(n))
+    }
+}
+
+fn string(line: usize, v: Value) -> LangResult<String> {
+    match v {
+        Value::Str(s) => Ok(s),
+        Value::Any(_) => Ok(String::new()),
+        v => type_error(line, "a string", &v),
+    }
+}
+
+fn location(line: usize, v: Value) -> LangResult<Location> {
+    let expected = "a (y, x) location";
+    let coord = |v: &Value| match v {
+        Value::Number(n) if n.fract() == 0.0 => Some(*n as i32),
+        Value::Any(_) => Some(0),
+        _ => None,
+    };
+    match v {
+        Value::Tuple(ref vs) if vs.len() == 2 => match (coord(&vs[0]), coord(&vs[1])) {
+            (Some(y), Some(x)) => Ok(yx(y, x)),
+            _ => type_error(line, expected, &v),
+        },
+        Value::Any(_) => Ok(yx(0, 0)),
+        v => type_error(line, expected, &v),
+    }
+}
+
+impl Compiler<'_> {
+    fn add(&mut self, line: usize, cmd: impl command::Command + 'static) {
+        self.commands.push((line, Box::new(cmd)))
+    }
+
+    fn new_id(&mut self) -> DropletId {
+        if self.checking {
+            // these never make it into a graph, so they don't need real ids
+            let id = self.probes.len() + self.commands.len();
+            return DropletId { id, process_id: 0 };
+        }
+        (self.new_id)()
+    }
+
+    fn droplet(&mut self, line: usize, v: Value) -> LangResult<DropletId> {
+        match v {
+            Value::Droplet(d) => Ok(d),
+            Value::Any(i) => {
+                let probe = &mut self.probes[i];
+                if let Some(used_line) = probe.used {
+                    let kind = ErrorKind::AlreadyUsed {
+                        name: probe.name.clone(),
+                        line: used_line,
+                    };
+                    return err(line, kind);
+                }
+                probe.used = Some(line);
+                Ok(self.new_id())
+            }
+            v => type_error(line, "a droplet", &v),
+        }
+    }
+
+    /// A value standing in for an argument matching `pat`.
+    fn probe(&mut self, pat: &Pattern) -> Value {
+        match pat {
+            Pattern::Name(name) => {
+                self.probes.push(Probe {
+                    name: name.clone(),
+                    used: None,
+                });
+                Value::Any(self.probes.len() - 1)
+            }
+            Pattern::Tuple(pats) => Value::Tuple(pats.iter().map(|p| self.probe(p)).collect()),
+        }
+    }
+
+    /// Runs a function on stand-in arguments, so that its body gets checked
+    /// even if it's never called, or only called in a loop that doesn't run.
+    /// Nothing it adds is kept.
+    fn check_function(&mut self, line: usize, func: &Function) -> LangResult<()> {
+        let n_commands = self.commands.len();
+        self.checking = true;
+        let args = func.args.iter().map(|p| self.probe(p)).collect();
+        let result = self.call_function(line, func, args);
+        self.checking = false;
+        self.probes.clear();
+        self.commands.truncate(n_commands);
+        result.map(|_| ())
+    }
+
+    fn bind(
+        &mut self,
+        scope: &mut Scope,
+        line: usize,
+        pat: &Pattern,
+        value: Value,
+    ) -> LangResult<()> {
+        match pat {
+            Pattern::Name(name) => {
+                if let Some(old) = scope.vars.get(name) {
+                    if old.value.is_linear() {
+                        let kind = ErrorKind::AlreadyBound {
+                            name: name.clone(),
+                            line: old.line,
+                        };
+                        return err(line, kind);
+                    }
+                }
+                scope.used.swap_remove(name);
+                scope.vars.insert(name.clone(), Binding { value, line });
+                Ok(())
+            }
+            Pattern::Tuple(pats) => match value {
+                Value::Any(_) => {
+                    // the pieces might be droplets, so they're separate
+                    for p in pats {
+                        let piece = self.probe(p);
+                        self.bind(scope, line, p, piece)?;
+                    }
+                    Ok(())
+                }
+                Value::Tuple(vs) if vs.len() == pats.len() => {
+                    for (p, v) in pats.iter().zip(vs) {
+                        self.bind(scope, line, p, v)?;
+                    }
+                    Ok(())
+                }
+                v => {
+                    let kind = ErrorKind::Type {
+                        expected: "a tuple matching the pattern",
+                        found: v.type_name(),
+                    };
+                    err(line, kind)
+                }
+            },
+        }
+    }
+
+    fn var(&self, scope: &mut Scope, line: usize, name: &str) -> LangResult<Value> {
+        let linear = match scope.vars.get(name) {
+            Some(b) => b.value.is_linear(),
+            None => {
+                if let Some(&used_line) = scope.used.get(name) {
+                    let kind = ErrorKind::AlreadyUsed {
+                        name: name.into(),
+                        line: used_line,
+                    };
+                    return err(line, kind);
+                }
+                return match self.params.get(name) {
+                    Some(&n) => Ok(Value::Number(n)),
+                    None if self.checking && self.param_names.iter().any(|p| p == name) => {
+                        Ok(Value::Number(1.0))
+                    }
+                    None => err(line, ErrorKind::Unbound(name.into())),
+                };
+            }
+        };
+
+        if linear {
+            // droplets are moved out of the variable
+            let b = scope.vars.swap_remove(name).unwrap();
+            scope.used.insert(name.into(), line);
+            Ok(b.value)
+        } else {
+            Ok(scope.vars[name].value.clone())
+        }
+    }
+
+    fn expr(&mut self, scope: &mut Scope, e: &Expr) -> LangResult<Value> {
+        let line = e.line;
+        let value = match &e.kind {
+            ExprKind::Number(n) => Value::Number(*n),
+            ExprKind::Str(s) => Value::Str(s.clone()),
+            ExprKind::Var(name) => self.var(scope, line, name)?,
+            ExprKind::Tuple(es) => {
+                let vs = es
+                    .iter()
+                    .map(|e| self.expr(scope, e))
+                    .collect::<LangResult<_>>()?;
+                Value::Tuple(vs)
+            }
+            ExprKind::Neg(e) => Value::Number(-number(line, self.expr(scope, e)?)?),
+            ExprKind::BinOp(op, lhs, rhs) => {
+                let lhs = number(line, self.expr(scope, lhs)?)?;
+                let rhs = number(line, self.expr(scope, rhs)?)?;
+                Value::Number(match op {
+                    BinOp::Add => lhs + rhs,
+                    BinOp::Sub => lhs - rhs,
+                    BinOp::Mul => lhs * rhs,
+                    BinOp::Div => lhs / rhs,
+                })
+            }
+            ExprKind::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|e| self.expr(scope, e))
+                    .collect::<LangResult<_>>()?;
+                self.call(line, name, args)?
+            }
+        };
+        Ok(value)
+    }
+
+    fn call(&mut self, line: usize, name: &str, args: Vec<Value>) -> LangResult<Value> {
+        if let Some(&func) = self.functions.get(name) {
+            return self.call_function(line, func, args);
+        }
+
+        let check_arity = |min: usize, max: usize| {
+            if args.len() < min || args.len() > max {
+                let kind = ErrorKind::Arity {
+                    function: name.into(),
+                    expected: if args.len() < min { min } else { max },
+                    found: args.len(),
+                };
+                return err(line, kind);
+            }
+            Ok(())
+        };
+
+        let puddle = |e| LangError::new(line, ErrorKind::PuddleError(e));
+
+        match name {
+            "create" => check_arity(1, 3)?,
+            "input" | "heat" => check_arity(3, 3)?,
+            "output" | "move" | "mix" => check_arity(2, 2)?,
+            "split" => check_arity(1, 1)?,
+            _ => return err(line, ErrorKind::UnknownFunction(name.into())),
+        }
+        let mut args = args.into_iter();
+        let mut arg = || args.next().unwrap();
+
+        let value = match name {
+            "create" => {
+                let volume = number(line, arg())?;
+                let mut rest = args.map(|v| location(line, v));
+                let loc = rest.next().transpose()?;
+                let dim = rest.next().transpose()?;
+                let out = self.new_id();
+                let cmd = command::Create::new(loc, volume, dim, out).map_err(puddle)?;
+                self.add(line, cmd);
+                Value::Droplet(out)
+            }
+            "input" => {
+                let port = string(line, arg())?;
+                let volume = number(line, arg())?;
+                let dim = location(line, arg())?;
+                let out = self.new_id();
+                let cmd = command::Input::new(port, volume, dim, out).map_err(puddle)?;
+                self.add(line, cmd);
+                Value::Droplet(out)
+            }
+            "output" => {
+                let d = self.droplet(line, arg())?;
+                let port = string(line, arg())?;
+                let cmd = command::Output::new(port, d).map_err(puddle)?;
+                self.add(line, cmd);
+                Value::unit()
+            }
+            "move" => {
+                let d = self.droplet(line, arg())?;
+                let loc = location(line, arg())?;
+                let out = self.new_id();
+                let cmd = command::Move::new(d, loc, out).map_err(puddle)?;
+                self.add(line, cmd);
+                Value::Droplet(out)
+            }
+            "mix" => {
+                let d1 = self.droplet(line, arg())?;
+                let d2 = self.droplet(line, arg())?;
+                let combined = self.new_id();
+                let cmd = command::Combine::new(d1, d2, combined).map_err(puddle)?;
+                self.add(line, cmd);
+                let out = self.new_id();
+                let cmd = command::Agitate::new(combined, out).map_err(puddle)?;
+                self.add(line, cmd);
+                Value::Droplet(out)
+            }
+            "split" => {
+                let d = self.droplet(line, arg())?;
+                let (out1, out2) = (self.new_id(), self.new_id());
+                let cmd = command::Split::new(d, out1, out2).map_err(puddle)?;
+                self.add(line, cmd);
+                Value::Tuple(vec![Value::Droplet(out1), Value::Droplet(out2)])
+            }
+            "heat" => {
+                let d = self.droplet(line, arg())?;
+                let temperature = number(line, arg())? as f32;
+                let duration = seconds_duration(number(line, arg())?);
+                let out = self.new_id();
+                let cmd = command::Heat::new(d, out, temperature, duration).map_err(puddle)?;
+                self.add(line, cmd);
+                Value::Droplet(out)
+            }
+            _ => unreachable!(),
+        };
+        Ok(value)
+    }
+
+    fn call_function(
+        &mut self,
+        line: usize,
+        func: &Function,
+        args: Vec<Value>,
+    ) -> LangResult<Value> {
+        if args.len() != func.args.len() {
+            let kind = ErrorKind::Arity {
+                function: func.name.clone(),
+                expected: func.args.len(),
+                found: args.len(),
+            };
+            return err(line, kind);
+        }
+        if self.depth >= MAX_DEPTH {
+            return err(line, ErrorKind::TooDeep);
+        }
+
+        let mut scope = Scope::default();
+        for (pat, value) in func.args.iter().zip(args) {
+            self.bind(&mut scope, line, pat, value)?;
+        }
+
+        self.depth += 1;
+        let result = self.stmts(&mut scope, &func.body);
+        self.depth -= 1;
+        let value = result?.unwrap_or_else(Value::unit);
+
+        // everything left over is a droplet that got lost
+        for (name, b) in &scope.vars {
+            if b.value.is_linear() {
+                return err(b.line, ErrorKind::NeverUsed(name.clone()));
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Runs statements, returning the value of a `return` if there was one.
+    fn stmts(&mut self, scope: &mut Scope, stmts: &[Stmt]) -> LangResult<Option<Value>> {
+        for stmt in stmts {
+            let line = stmt.line;
+            match &stmt.kind {
+                StmtKind::Param(name, default) => {
+                    let value = match self.overrides.get(name) {
+                        Some(&n) => n,
+                        None => number(line, self.expr(scope, default)?)?,
+                    };
+                    self.params.insert(name.clone(), value);
+                }
+                // these were collected before running anything
+                StmtKind::Fun(_) => (),
+                StmtKind::Let(pat, e) => {
+                    let value = self.expr(scope, e)?;
+                    self.bind(scope, line, pat, value)?;
+                }
+                StmtKind::For(var, start, end, body) => {
+                    let start = loop_bound(line, self.expr(scope, start)?)?;
+                    let end = loop_bound(line, self.expr(scope, end)?)?;
+                    match end.checked_sub(start) {
+                        Some(n) if n <= MAX_ITERATIONS => (),
+                        _ => return err(line, ErrorKind::TooManyIterations),
+                    }
+                    // one pass is enough to check a loop, two shows whether it
+                    // reuses something from outside
+                    let range = if self.checking { 0..2 } else { start..end };
+                    for i in range {
+                        let pat = Pattern::Name(var.clone());
+                        self.bind(scope, line, &pat, Value::Number(i as f64))?;
+                        if let Some(ret) = self.stmts(scope, body)? {
+                            return Ok(Some(ret));
+                        }
+                    }
+                }
+                StmtKind::Return(e) => return Ok(Some(self.expr(scope, e)?)),
+                StmtKind::Expr(e) => {
+                    if self.expr(scope, e)?.is_linear() {
+                        return err(line, ErrorKind::Discarded);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Names the droplets in `value`, with `.0`, `.1` and so on for tuples.
+fn collect_droplets(name: String, value: Value, droplets: &mut IndexMap<String, DropletId>) {
+    match value {
+        Value::Droplet(d) => {
+            droplets.insert(name, d);
+        }
+        Value::Tuple(vs) => {
+            for (i, v) in vs.into_iter().enumerate() {
+                collect_droplets(format!("{}.{}", name, i), v, droplets);
+            }
+        }
+        Value::Number(_) | Value::Str(_) | Value::Any(_) => (),
+    }
+}
+
+pub fn compile(
+    program: &Program,
+    params: &IndexMap<String, f64>,
+    new_id: &mut dyn FnMut() -> DropletId,
+) -> LangResult<CompiledProtocol> {
+    let mut functions = IndexMap::new();
+    for stmt in &program.stmts {
+        if let StmtKind::Fun(func) = &stmt.kind {
+            if functions.insert(func.name.clone(), func).is_some() {
+                return err(stmt.line, ErrorKind::DuplicateFunction(func.name.clone()));
+            }
+        }
+    }
+    let param_names = program
+        .stmts
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Param(name, _) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut compiler = Compiler {
+        functions,
+        params: IndexMap::new(),
+        overrides: params,
+        new_id,
+        commands: Vec::new(),
+        depth: 0,
+        checking: false,
+        probes: Vec::new(),
+        param_names,
+    };
+
+    for stmt in &program.stmts {
+        if let StmtKind::Fun(func) = &stmt.kind {
+            compiler.check_function(stmt.line, func)?;
+        }
+    }
+
+    let mut scope = Scope::default();
+    let ret = compiler.stmts(&mut scope, &program.stmts)?;
+    // the parser doesn't allow top-level returns
+    assert!(ret.is_none());
+
+    let mut droplets = IndexMap::new();
+    for (name, b) in scope.vars {
+        collect_droplets(name, b.value, &mut droplets);
+    }
+
+    Ok(CompiledProtocol {
+        commands: compiler.commands,
+        droplets,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang;
+    use crate::plan::graph::Graph;
+    use matches::assert_matches;
+
+    fn compile(src: &str) -> LangResult<CompiledProtocol> {
+        let mut next = 0;
+        let mut new_id = || {
+            next += 1;
+            DropletId {
+                id: next,
+                process_id: 0,
+            }
+        };
+        lang::compile(src, &IndexMap::new(), &mut new_id)
+    }
+
+    fn error(src: &str) -> LangError {
+        compile(src).unwrap_err()
+    }
+
+    #[test]
+    fn test_compile_to_graph() {
+        let src = "
+            param n = 2
+            fun mix_split(a, b) {
+                let ab = mix(a, b)
+                return split(ab)
+            }
+            let a = create(1, (1, 1))
+            let b = create(1)
+            let (c, d) = mix_split(a, b)
+            for i in 0..n {
+                let c = move(c, (i * 2, 0))
+            }
+            let e = split(d)
+        ";
+        let compiled = compile(src).unwrap();
+
+        // 2 creates, mix is 2 commands, 1 split, 2 moves, another split
+        let lines: Vec<_> = compiled.commands.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, vec![7, 8, 4, 4, 5, 11, 11, 13]);
+
+        let names: Vec<_> = compiled.droplets.keys().cloned().collect();
+        assert_eq!(names, vec!["c", "e.0", "e.1"]);
+
+        // since it typechecked, the graph should take it
+        let mut graph = Graph::default();
+        for (_, cmd) in compiled.commands {
+            graph.add_command(cmd).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_linearity_errors() {
+        let e = error("let a = create(1)\nlet b = a\nlet c = a");
+        assert_eq!(e.line, 3);
+        assert_matches!(e.kind, ErrorKind::AlreadyUsed { line: 2, .. });
+
+        let e = error("let a = create(1)\nlet a = create(1)");
+        assert_eq!(e.line, 2);
+        assert_matches!(e.kind, ErrorKind::AlreadyBound { line: 1, .. });
+
+        let e = error("fun f(a, b) {\n return a\n}\nlet c = f(create(1), create(1))");
+        assert_eq!(e.line, 4);
+        assert_matches!(e.kind, ErrorKind::NeverUsed(ref name) if name == "b");
+
+        let e = error("let a = create(1)\nsplit(a)");
+        assert_eq!(e.line, 2);
+        assert_matches!(e.kind, ErrorKind::Discarded);
+
+        // a loop that doesn't rebind its droplet uses it twice
+        let e = error("let a = create(1)\nfor i in 0..2 {\n let b = move(a, (i, i))\n}");
+        assert_eq!(e.line, 3);
+        assert_matches!(e.kind, ErrorKind::AlreadyUsed { line: 3, .. });
+    }
+
+    #[test]
+    fn test_functions_checked_when_defined() {
+        // f is never called, but it uses its droplet twice
+        let e = error("fun f(a) {\n let b = move(a, (1, 1))\n return mix(a, b)\n}");
+        assert_eq!(e.line, 3);
+        assert_matches!(e.kind, ErrorKind::AlreadyUsed { line: 2, ref name } if name == "a");
+
+        // only called in a loop that never runs
+        let e = error("fun f(a) {\n let b = create(1)\n return a\n}\nfor i in 0..0 {\n f(i)\n}");
+        assert_eq!(e.line, 2);
+        assert_matches!(e.kind, ErrorKind::NeverUsed(ref name) if name == "b");
+
+        // a loop in the body that would reuse a droplet
+        let e = error("fun f(a, n) {\n for i in 0..n {\n  let b = move(a, (i, 0))\n }\n}");
+        assert_eq!(e.line, 3);
+        assert_matches!(e.kind, ErrorKind::AlreadyUsed { line: 3, .. });
+
+        // arguments that aren't droplets can be used as often as needed, and
+        // params can be used before they're declared
+        let src = "
+            fun f(a, (y, x)) {
+                let a = move(a, (y, x))
+                let a = move(a, (y + volume, x))
+                return a
+            }
+            param volume = 1
+            let a = f(create(volume), (1, 2))
+        ";
+        let compiled = compile(src).unwrap();
+        assert_eq!(compiled.commands.len(), 3);
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let e = error("let a = create(1)\nlet b = create(a)");
+        assert_eq!(e.line, 2);
+        assert_matches!(
+            e.kind,
+            ErrorKind::Type {
+                expected: "a number",
+                ..
+            }
+        );
+
+        let e = error("let a = move(create(1), (1, 1.5))");
+        assert_matches!(e.kind, ErrorKind::Type { .. });
+
+        let e = error("let a = mix(create(1))");
+        assert_matches!(
+            e.kind,
+            ErrorKind::Arity {
+                expected: 2,
+                found: 1,
+                ..
+            }
+        );
+
+        let e = error("fun f(x) { return f(x) }\nlet y = f(1)");
+        assert_matches!(e.kind, ErrorKind::TooDeep);
+
+        let e = error("let x = y");
+        assert_matches!(e.kind, ErrorKind::Unbound(_));
+    }
+
+    #[test]
+    fn test_loop_bounds() {
+        let e = error("for i in -1/0 .. 1/0 {\n}");
+        assert_eq!(e.line, 1);
+        assert_matches!(e.kind, ErrorKind::BadLoopBound(_));
+
+        let e = error("for i in 0 .. 0/0 {\n}");
+        assert_matches!(e.kind, ErrorKind::BadLoopBound(_));
+
+        let e = error("for i in 0 .. 99999999999 * 99999999999 {\n}");
+        assert_matches!(e.kind, ErrorKind::BadLoopBound(_));
+
+        // both fit, but the distance between them doesn't
+        let e = error("for i in -9000000000000000000 .. 9000000000000000000 {\n}");
+        assert_matches!(e.kind, ErrorKind::TooManyIterations);
+
+        let e = error("for i in 0 .. 10001 {\n}");
+        assert_matches!(e.kind, ErrorKind::TooManyIterations);
+    }
+}
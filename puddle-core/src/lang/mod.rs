@@ -0,0 +1,149 @@
+//! A small language for writing protocols.
+//!
+//! Programs are a list of statements, one per line (or separated by `;`):
+//!
+//! ```text
+//! param volume = 1.0
+//!
+//! fun mix_split(a, b) {
+//!     let ab = mix(a, b)
+//!     return split(ab)
+//! }
+//!
+//! let a = create(volume, (1, 1))
+//! let b = create(volume, (1, 7))
+//! let (c, d) = mix_split(a, b)
+//! for i in 0..3 {
+//!     let c = move(c, (7, 2 + i))
+//! }
+//! ```
+//!
+//! Values are numbers, strings, droplets, and tuples of those. Droplets are
+//! linear: every droplet has to be used exactly once, so using one twice, or
+//! losing one by rebinding its name or forgetting it in a function, is a
+//! compile error. These are the same mistakes that the command graph rejects
+//! with [`GraphError::AlreadyBound`](crate::plan::graph::GraphError), but
+//! here they come with a line number. Function bodies are checked where
+//! they're defined, so a mistake in one shows up even if it's never called.
+//! Droplets that are still bound at the end of the program are returned by
+//! name.
+//!
+//! The built in functions are `create(volume, [location, [dimensions]])`,
+//! `input(port, volume, dimensions)`, `output(droplet, port)`,
+//! `move(droplet, location)`, `mix(a, b)`, `split(droplet)`, and
+//! `heat(droplet, temperature, seconds)`. Locations are `(y, x)` tuples.
+
+mod compile;
+mod parse;
+
+use std::fmt;
+
+use indexmap::IndexMap;
+
+use crate::grid::DropletId;
+use crate::process::PuddleError;
+use crate::protocol::CompiledProtocol;
+
+pub use self::parse::{parse, Program};
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Syntax(String),
+    Unbound(String),
+    UnknownFunction(String),
+    /// A droplet was used after it was already used on `line`.
+    AlreadyUsed {
+        name: String,
+        line: usize,
+    },
+    /// A name was rebound while still holding a droplet bound on `line`.
+    AlreadyBound {
+        name: String,
+        line: usize,
+    },
+    /// A function returned without using one of its droplets.
+    NeverUsed(String),
+    /// An expression's droplets were thrown away.
+    Discarded,
+    Type {
+        expected: &'static str,
+        found: &'static str,
+    },
+    Arity {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    DuplicateFunction(String),
+    TooDeep,
+    TooManyIterations,
+    /// A loop bound was infinite, not a number, or too big to count to.
+    BadLoopBound(f64),
+    PuddleError(PuddleError),
+}
+
+#[derive(Debug)]
+pub struct LangError {
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl LangError {
+    pub fn new(line: usize, kind: ErrorKind) -> LangError {
+        LangError { line, kind }
+    }
+}
+
+impl fmt::Display for LangError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::ErrorKind::*;
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            Syntax(msg) => write!(f, "{}", msg),
+            Unbound(name) => write!(f, "'{}' is not bound", name),
+            UnknownFunction(name) => write!(f, "no function named '{}'", name),
+            AlreadyUsed { name, line } => {
+                write!(f, "droplet '{}' was already used on line {}", name, line)
+            }
+            AlreadyBound { name, line } => write!(
+                f,
+                "'{}' still holds a droplet from line {} that hasn't been used",
+                name, line
+            ),
+            NeverUsed(name) => write!(f, "droplet '{}' is never used", name),
+            Discarded => write!(f, "droplets in this expression are never used"),
+            Type { expected, found } => write!(f, "expected {}, found {}", expected, found),
+            Arity {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "'{}' takes {} arguments, but was given {}",
+                function, expected, found
+            ),
+            DuplicateFunction(name) => write!(f, "function '{}' is already defined", name),
+            TooDeep => write!(f, "too many nested function calls"),
+            TooManyIterations => write!(f, "loop has too many iterations"),
+            BadLoopBound(n) => write!(f, "can't loop to {}", n),
+            PuddleError(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for LangError {}
+
+pub type LangResult<T> = Result<T, LangError>;
+
+/// Parses and compiles a program into commands, using `new_id` to name the
+/// droplets. Parameters given in `params` override the defaults.
+///
+/// The line each command came from is stored along with it.
+pub fn compile(
+    src: &str,
+    params: &IndexMap<String, f64>,
+    new_id: &mut dyn FnMut() -> DropletId,
+) -> LangResult<CompiledProtocol> {
+    let program = parse(src)?;
+    compile::compile(&program, params, new_id)
+}
@@ -0,0 +1,522 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::lang::{ErrorKind, LangError, LangResult};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    Let,
+    For,
+    In,
+    Fun,
+    Return,
+    Param,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Equals,
+    DotDot,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Newline,
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Name(String),
+    Tuple(Vec<Pattern>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(f64),
+    Str(String),
+    Var(String),
+    Tuple(Vec<Expr>),
+    Call(String, Vec<Expr>),
+    Neg(Box<Expr>),
+    BinOp(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub line: usize,
+    pub kind: ExprKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub args: Vec<Pattern>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Param(String, Expr),
+    Fun(Function),
+    Let(Pattern, Expr),
+    For(String, Expr, Expr, Vec<Stmt>),
+    Return(Expr),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub line: usize,
+    pub kind: StmtKind,
+}
+
+/// A parsed program, the top-level statements in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    // newlines don't end statements inside parentheses
+    paren_depth: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Lexer<'a> {
+        Lexer {
+            chars: src.chars().peekable(),
+            line: 1,
+            paren_depth: 0,
+        }
+    }
+
+    fn error<T>(&self, msg: String) -> LangResult<T> {
+        Err(LangError::new(self.line, ErrorKind::Syntax(msg)))
+    }
+
+    fn push_while(&mut self, s: &mut String, pred: impl Fn(char) -> bool) {
+        while let Some(&c) = self.chars.peek() {
+            if !pred(c) {
+                break;
+            }
+            s.push(c);
+            self.chars.next();
+        }
+    }
+
+    fn next_token(&mut self) -> LangResult<(usize, Token)> {
+        loop {
+            let c = match self.chars.next() {
+                Some(c) => c,
+                None => return Ok((self.line, Token::Eof)),
+            };
+            let line = self.line;
+            let tok = match c {
+                '\n' => {
+                    self.line += 1;
+                    if self.paren_depth > 0 {
+                        continue;
+                    }
+                    Token::Newline
+                }
+                ';' => Token::Newline,
+                '#' => {
+                    while let Some(&c) = self.chars.peek() {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.chars.next();
+                    }
+                    continue;
+                }
+                c if c.is_whitespace() => continue,
+                '(' => {
+                    self.paren_depth += 1;
+                    Token::LParen
+                }
+                ')' => {
+                    self.paren_depth = self.paren_depth.saturating_sub(1);
+                    Token::RParen
+                }
+                '{' => Token::LBrace,
+                '}' => Token::RBrace,
+                ',' => Token::Comma,
+                '=' => Token::Equals,
+                '+' => Token::Plus,
+                '-' => Token::Minus,
+                '*' => Token::Star,
+                '/' => Token::Slash,
+                '.' => match self.chars.next() {
+                    Some('.') => Token::DotDot,
+                    _ => return self.error("expected '..'".into()),
+                },
+                '"' => {
+                    let mut s = String::new();
+                    loop {
+                        match self.chars.next() {
+                            Some('"') => break,
+                            Some('\n') | None => {
+                                return self.error("unterminated string".into());
+                            }
+                            Some(c) => s.push(c),
+                        }
+                    }
+                    Token::Str(s)
+                }
+                c if c.is_ascii_digit() => {
+                    let mut s = c.to_string();
+                    self.push_while(&mut s, |c| c.is_ascii_digit());
+                    // careful not to eat the first dot of a range
+                    let mut lookahead = self.chars.clone();
+                    lookahead.next();
+                    let is_fraction = match (self.chars.peek(), lookahead.peek()) {
+                        (Some('.'), Some(c)) => c.is_ascii_digit(),
+                        _ => false,
+                    };
+                    if is_fraction {
+                        self.chars.next();
+                        s.push('.');
+                        self.push_while(&mut s, |c| c.is_ascii_digit());
+                    }
+                    match s.parse() {
+                        Ok(n) => Token::Number(n),
+                        Err(_) => return self.error(format!("bad number '{}'", s)),
+                    }
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    let mut s = c.to_string();
+                    self.push_while(&mut s, |c| c.is_alphanumeric() || c == '_');
+                    match s.as_str() {
+                        "let" => Token::Let,
+                        "for" => Token::For,
+                        "in" => Token::In,
+                        "fun" => Token::Fun,
+                        "return" => Token::Return,
+                        "param" => Token::Param,
+                        _ => Token::Ident(s),
+                    }
+                }
+                c => return self.error(format!("unexpected character '{}'", c)),
+            };
+            return Ok((line, tok));
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].0
+    }
+
+    fn next(&mut self) -> Token {
+        let tok = self.tokens[self.pos].1.clone();
+        // the last token is always Eof, so just stay there
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn error<T>(&self, msg: String) -> LangResult<T> {
+        Err(LangError::new(self.line(), ErrorKind::Syntax(msg)))
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> LangResult<()> {
+        if *self.peek() == expected {
+            self.next();
+            Ok(())
+        } else {
+            self.error(format!("expected {}, found {:?}", what, self.peek()))
+        }
+    }
+
+    fn ident(&mut self) -> LangResult<String> {
+        match self.peek().clone() {
+            Token::Ident(name) => {
+                self.next();
+                Ok(name)
+            }
+            tok => self.error(format!("expected a name, found {:?}", tok)),
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while *self.peek() == Token::Newline {
+            self.next();
+        }
+    }
+
+    /// Parses statements until `end`, which is consumed.
+    fn stmts(&mut self, end: Token, top_level: bool) -> LangResult<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+        loop {
+            self.skip_newlines();
+            if *self.peek() == end {
+                self.next();
+                return Ok(stmts);
+            }
+            if *self.peek() == Token::Eof {
+                return self.error("unexpected end of file".into());
+            }
+            stmts.push(self.stmt(top_level)?);
+            match self.peek() {
+                Token::Newline => {
+                    self.next();
+                }
+                tok if *tok == end => (),
+                tok => return self.error(format!("expected end of statement, found {:?}", tok)),
+            }
+        }
+    }
+
+    fn block(&mut self) -> LangResult<Vec<Stmt>> {
+        self.expect(Token::LBrace, "'{'")?;
+        self.stmts(Token::RBrace, false)
+    }
+
+    fn stmt(&mut self, top_level: bool) -> LangResult<Stmt> {
+        let line = self.line();
+        let kind = match self.peek() {
+            Token::Param | Token::Fun if !top_level => {
+                return self.error("params and functions must be at the top level".into());
+            }
+            Token::Return if top_level => {
+                return self.error("return outside of a function".into());
+            }
+            Token::Param => {
+                self.next();
+                let name = self.ident()?;
+                self.expect(Token::Equals, "'='")?;
+                StmtKind::Param(name, self.expr()?)
+            }
+            Token::Fun => {
+                self.next();
+                let name = self.ident()?;
+                self.expect(Token::LParen, "'('")?;
+                let mut args = Vec::new();
+                while *self.peek() != Token::RParen {
+                    args.push(self.pattern()?);
+                    if *self.peek() != Token::RParen {
+                        self.expect(Token::Comma, "','")?;
+                    }
+                }
+                self.next();
+                let body = self.block()?;
+                StmtKind::Fun(Function { name, args, body })
+            }
+            Token::Let => {
+                self.next();
+                let pat = self.pattern()?;
+                self.expect(Token::Equals, "'='")?;
+                StmtKind::Let(pat, self.expr()?)
+            }
+            Token::For => {
+                self.next();
+                let var = self.ident()?;
+                self.expect(Token::In, "'in'")?;
+                let start = self.expr()?;
+                self.expect(Token::DotDot, "'..'")?;
+                let end = self.expr()?;
+                let body = self.block()?;
+                StmtKind::For(var, start, end, body)
+            }
+            Token::Return => {
+                self.next();
+                StmtKind::Return(self.expr()?)
+            }
+            _ => StmtKind::Expr(self.expr()?),
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    fn pattern(&mut self) -> LangResult<Pattern> {
+        if *self.peek() == Token::LParen {
+            self.next();
+            let mut pats = Vec::new();
+            while *self.peek() != Token::RParen {
+                pats.push(self.pattern()?);
+                if *self.peek() != Token::RParen {
+                    self.expect(Token::Comma, "','")?;
+                }
+            }
+            self.next();
+            Ok(Pattern::Tuple(pats))
+        } else {
+            Ok(Pattern::Name(self.ident()?))
+        }
+    }
+
+    fn expr(&mut self) -> LangResult<Expr> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Token::Plus => BinOp::Add,
+                Token::Minus => BinOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            let rhs = self.term()?;
+            lhs = Expr {
+                line: lhs.line,
+                kind: ExprKind::BinOp(op, Box::new(lhs), Box::new(rhs)),
+            };
+        }
+    }
+
+    fn term(&mut self) -> LangResult<Expr> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Token::Star => BinOp::Mul,
+                Token::Slash => BinOp::Div,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            let rhs = self.unary()?;
+            lhs = Expr {
+                line: lhs.line,
+                kind: ExprKind::BinOp(op, Box::new(lhs), Box::new(rhs)),
+            };
+        }
+    }
+
+    fn unary(&mut self) -> LangResult<Expr> {
+        let line = self.line();
+        if *self.peek() == Token::Minus {
+            self.next();
+            let e = self.unary()?;
+            return Ok(Expr {
+                line,
+                kind: ExprKind::Neg(Box::new(e)),
+            });
+        }
+        self.atom()
+    }
+
+    fn exprs_until_rparen(&mut self) -> LangResult<Vec<Expr>> {
+        let mut exprs = Vec::new();
+        while *self.peek() != Token::RParen {
+            exprs.push(self.expr()?);
+            if *self.peek() != Token::RParen {
+                self.expect(Token::Comma, "','")?;
+            }
+        }
+        self.next();
+        Ok(exprs)
+    }
+
+    fn atom(&mut self) -> LangResult<Expr> {
+        let line = self.line();
+        let kind = match self.next() {
+            Token::Number(n) => ExprKind::Number(n),
+            Token::Str(s) => ExprKind::Str(s),
+            Token::Ident(name) => {
+                if *self.peek() == Token::LParen {
+                    self.next();
+                    ExprKind::Call(name, self.exprs_until_rparen()?)
+                } else {
+                    ExprKind::Var(name)
+                }
+            }
+            Token::LParen => {
+                let mut exprs = self.exprs_until_rparen()?;
+                // just parentheses, not a tuple
+                if exprs.len() == 1 {
+                    return Ok(exprs.remove(0));
+                }
+                ExprKind::Tuple(exprs)
+            }
+            tok => {
+                return Err(LangError::new(
+                    line,
+                    ErrorKind::Syntax(format!("expected an expression, found {:?}", tok)),
+                ));
+            }
+        };
+        Ok(Expr { line, kind })
+    }
+}
+
+pub fn parse(src: &str) -> LangResult<Program> {
+    let mut lexer = Lexer::new(src);
+    let mut tokens = Vec::new();
+    loop {
+        let (line, tok) = lexer.next_token()?;
+        let done = tok == Token::Eof;
+        tokens.push((line, tok));
+        if done {
+            break;
+        }
+    }
+
+    let mut parser = Parser { tokens, pos: 0 };
+    let stmts = parser.stmts(Token::Eof, true)?;
+    Ok(Program { stmts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matches::assert_matches;
+
+    #[test]
+    fn test_parse() {
+        let src = "
+            param n = 3
+            # comments are ignored
+            fun f((a, b), x) {
+                return (a, b)
+            }
+            let (a, b) = f(
+                (create(1), create(2)),
+                -n * 2.5 + 1
+            )
+            for i in 0..n { let a = move(a, (i, 0)) }
+        ";
+        let program = parse(src).unwrap();
+        let lines: Vec<_> = program.stmts.iter().map(|s| s.line).collect();
+        assert_eq!(lines, vec![2, 4, 7, 11]);
+
+        match &program.stmts[2].kind {
+            StmtKind::Let(Pattern::Tuple(pats), expr) => {
+                assert_eq!(pats.len(), 2);
+                assert_matches!(&expr.kind, ExprKind::Call(name, args) if name == "f" && args.len() == 2);
+            }
+            kind => panic!("Unexpected statement {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let line_of = |src: &str| parse(src).unwrap_err().line;
+        assert_eq!(line_of("let a = create(1)\nlet = 2"), 2);
+        assert_eq!(line_of("let a = \"oops\n"), 1);
+        assert_eq!(line_of("return 1"), 1);
+        assert_eq!(line_of("for i in 0..2 {\n param x = 1\n}"), 2);
+        assert_eq!(line_of("let a = 1 2"), 1);
+    }
+}
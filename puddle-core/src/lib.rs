@@ -12,19 +12,29 @@
 extern crate log;
 
 // these need to be pub until we have an api
+pub mod checkpoint;
 pub mod command;
+pub mod eventlog;
 pub mod exec;
 pub mod grid;
+pub mod hardware;
+pub mod lang;
+pub mod metrics;
 pub mod plan;
 pub mod process;
+pub mod protocol;
+pub mod record;
+pub mod status;
 pub mod util;
 
 mod system;
 
+pub use crate::system::Stats;
+
 pub mod prelude {
     pub use crate::{
         exec::Executor,
-        grid::{Blob, DropletId, DropletInfo, Grid, Location},
+        grid::{Blob, DropletId, DropletInfo, Footprint, Grid, Location},
         process::{Manager, Process, ProcessId, PuddleError},
     };
 }
@@ -0,0 +1,185 @@
+//! Counters for watching a long run from outside, written out in the
+//! Prometheus text format. Like `exec::Controls`, these don't need the
+//! system lock, so they can be read in the middle of a flush.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::plan::PlanStats;
+
+#[derive(Default)]
+pub struct Metrics {
+    ticks: AtomicUsize,
+    plans: AtomicUsize,
+    schedule_nanos: AtomicU64,
+    place_nanos: AtomicU64,
+    route_nanos: AtomicU64,
+    nodes_expanded: AtomicUsize,
+    placement_retries: AtomicUsize,
+    processes: AtomicUsize,
+    droplets: AtomicUsize,
+    errors: AtomicUsize,
+    last_error: Mutex<Option<String>>,
+    flushing: AtomicBool,
+}
+
+fn nanos(d: Duration) -> u64 {
+    d.as_nanos() as u64
+}
+
+fn seconds(nanos: &AtomicU64) -> f64 {
+    nanos.load(Relaxed) as f64 / 1e9
+}
+
+impl Metrics {
+    /// Catches up with the planner's running totals.
+    pub(crate) fn planned(&self, stats: &PlanStats) {
+        self.plans.store(stats.n_plans, Relaxed);
+        self.schedule_nanos.store(nanos(stats.schedule), Relaxed);
+        self.place_nanos.store(nanos(stats.place), Relaxed);
+        self.route_nanos.store(nanos(stats.route), Relaxed);
+        self.nodes_expanded.store(stats.nodes_expanded, Relaxed);
+        self.placement_retries
+            .store(stats.placement_retries, Relaxed);
+    }
+
+    pub(crate) fn show(&self, ticks: usize, droplets: usize) {
+        self.ticks.store(ticks, Relaxed);
+        self.droplets.store(droplets, Relaxed);
+    }
+
+    pub(crate) fn set_processes(&self, processes: usize) {
+        self.processes.store(processes, Relaxed);
+    }
+
+    pub(crate) fn error(&self, message: &str) {
+        self.errors.fetch_add(1, Relaxed);
+        *self.last_error.lock().unwrap() = Some(message.into());
+    }
+
+    pub(crate) fn set_flushing(&self, flushing: bool) {
+        self.flushing.store(flushing, Relaxed);
+    }
+
+    pub fn ticks(&self) -> usize {
+        self.ticks.load(Relaxed)
+    }
+
+    pub fn errors(&self) -> usize {
+        self.errors.load(Relaxed)
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    pub fn is_flushing(&self) -> bool {
+        self.flushing.load(Relaxed)
+    }
+
+    /// Everything, in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, String)]| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            for (labels, value) in samples {
+                writeln!(out, "{}{} {}", name, labels, value).unwrap();
+            }
+        };
+        let count = |n: &AtomicUsize| vec![("", n.load(Relaxed).to_string())];
+
+        metric(
+            "puddle_ticks_total",
+            "counter",
+            "Ticks the executor has run.",
+            &count(&self.ticks),
+        );
+        metric(
+            "puddle_plans_total",
+            "counter",
+            "Phases the planner has planned.",
+            &count(&self.plans),
+        );
+        metric(
+            "puddle_plan_seconds_total",
+            "counter",
+            "Time spent planning, by phase.",
+            &[
+                (
+                    r#"{phase="schedule"}"#,
+                    seconds(&self.schedule_nanos).to_string(),
+                ),
+                (r#"{phase="place"}"#, seconds(&self.place_nanos).to_string()),
+                (r#"{phase="route"}"#, seconds(&self.route_nanos).to_string()),
+            ],
+        );
+        metric(
+            "puddle_route_nodes_expanded_total",
+            "counter",
+            "Search nodes the router has expanded.",
+            &count(&self.nodes_expanded),
+        );
+        metric(
+            "puddle_placement_retries_total",
+            "counter",
+            "Times planning backed off to fewer commands and placed again.",
+            &count(&self.placement_retries),
+        );
+        metric(
+            "puddle_processes",
+            "gauge",
+            "Processes that haven't been closed.",
+            &count(&self.processes),
+        );
+        metric(
+            "puddle_droplets",
+            "gauge",
+            "Droplets on the board.",
+            &count(&self.droplets),
+        );
+        metric(
+            "puddle_errors_total",
+            "counter",
+            "Times planning failed.",
+            &count(&self.errors),
+        );
+        metric(
+            "puddle_flushing",
+            "gauge",
+            "1 while the system is flushing, 0 when it's idle.",
+            &[("", (self.is_flushing() as usize).to_string())],
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.show(12, 3);
+        metrics.error("oops");
+        metrics.planned(&PlanStats {
+            n_plans: 2,
+            route: Duration::from_millis(1500),
+            ..PlanStats::default()
+        });
+
+        let text = metrics.render();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines.contains(&"puddle_ticks_total 12"));
+        assert!(lines.contains(&"puddle_droplets 3"));
+        assert!(lines.contains(&"puddle_errors_total 1"));
+        assert!(lines.contains(&"puddle_plans_total 2"));
+        assert!(lines.contains(&r#"puddle_plan_seconds_total{phase="route"} 1.5"#));
+        assert!(lines.contains(&"# TYPE puddle_processes gauge"));
+        assert!(lines.contains(&"puddle_flushing 0"));
+        assert_eq!(metrics.last_error(), Some("oops".into()));
+    }
+}
@@ -0,0 +1,317 @@
+use std::fmt;
+
+use indexmap::IndexMap;
+use petgraph::{algo::toposort, prelude::*};
+use serde::Serialize;
+
+use crate::grid::{location::yx, DropletId, Grid, Location, Peripheral};
+use crate::plan::graph::{CmdIndex, Graph};
+
+/// Something that keeps a protocol from ever running on a grid.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum Problem {
+    DropletTooBig {
+        droplet: DropletId,
+        dimensions: Location,
+    },
+    MissingPeripheral {
+        peripheral: Peripheral,
+    },
+    TooCrowded {
+        needed: usize,
+        available: usize,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::Problem::*;
+        match self {
+            DropletTooBig {
+                droplet,
+                dimensions,
+            } => write!(
+                f,
+                "droplet {:?} will be {} but the grid isn't that big",
+                droplet, dimensions
+            ),
+            MissingPeripheral { peripheral } => {
+                write!(f, "the grid doesn't have a {:?}", peripheral)
+            }
+            TooCrowded { needed, available } => write!(
+                f,
+                "droplets need at least {} electrodes, but the grid only has {}",
+                needed, available
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PeripheralDemand {
+    pub peripheral: Peripheral,
+    /// How many commands use it.
+    pub uses: usize,
+    /// The most commands that would use it at once if everything ran as soon
+    /// as possible.
+    pub peak: usize,
+    /// How many of them the grid has.
+    pub available: usize,
+}
+
+/// What a command graph needs from a grid, figured out before running it.
+///
+/// Droplet sizes are estimated from the commands, and each droplet is counted
+/// as one electrode bigger in each direction, since droplets need some space
+/// between them to keep from merging.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Analysis {
+    /// Droplets that are never consumed or output, so they'll stay on the
+    /// board forever.
+    pub unconsumed: Vec<DropletId>,
+    /// Electrodes taken up by droplets at the busiest point, if every command
+    /// ran as soon as possible.
+    pub peak_occupancy: usize,
+    /// Electrodes that have to be taken up at some point, no matter how the
+    /// commands are scheduled.
+    pub min_occupancy: usize,
+    /// Electrodes on the grid that aren't dead.
+    pub usable_electrodes: usize,
+    pub peripherals: Vec<PeripheralDemand>,
+    pub problems: Vec<Problem>,
+}
+
+impl Analysis {
+    pub fn fits(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Electrodes a droplet takes up, counting the space around it.
+pub(crate) fn occupancy(dim: Location) -> usize {
+    ((dim.y + 1) * (dim.x + 1)) as usize
+}
+
+pub fn analyze(graph: &Graph, grid: &Grid) -> Analysis {
+    let order = toposort(&graph.graph, None).expect("command graph has a cycle");
+    analyze_commands(graph, grid, &order)
+}
+
+/// Like `analyze`, but only looks at some of the commands, which have to be
+/// in an order that puts each one after the ones it depends on. Anything
+/// they get from commands that aren't in `order` is treated as already
+/// sitting on the board.
+pub fn analyze_commands(graph: &Graph, grid: &Grid, order: &[CmdIndex]) -> Analysis {
+    let g = &graph.graph;
+
+    // walk the graph in order, figuring out sizes and when things can run
+    let mut levels: IndexMap<CmdIndex, usize> = IndexMap::new();
+    let mut dims: IndexMap<DropletId, Location> = IndexMap::new();
+    let mut demands: Vec<PeripheralDemand> = Vec::new();
+    let mut demand_levels: Vec<Vec<usize>> = Vec::new();
+    let mut min_occupancy = 0;
+
+    for &node in order {
+        let cmd = match &g[node] {
+            Some(cmd) => cmd,
+            None => continue,
+        };
+
+        let level = g
+            .edges_directed(node, Incoming)
+            .filter_map(|e| levels.get(&e.source()).map(|l| l + 1))
+            .max()
+            .unwrap_or(0);
+        levels.insert(node, level);
+
+        let input_dims: Vec<_> = cmd
+            .input_droplets()
+            .iter()
+            .map(|id| dims.get(id).cloned().unwrap_or_else(|| yx(1, 1)))
+            .collect();
+        // all the inputs have to be on the board together
+        let input_occupancy = input_dims.iter().cloned().map(occupancy).sum();
+        min_occupancy = min_occupancy.max(input_occupancy);
+
+        let output_dims = cmd.output_dimensions(&input_dims);
+        dims.extend(cmd.output_droplets().into_iter().zip(output_dims));
+
+        if let Some(p) = cmd.peripheral() {
+            let i = match demands.iter().position(|d| d.peripheral.is_compatible(&p)) {
+                Some(i) => i,
+                None => {
+                    let available = grid
+                        .locations()
+                        .filter(|(_, e)| match &e.peripheral {
+                            Some(p2) => p.is_compatible(p2),
+                            None => false,
+                        })
+                        .count();
+                    demands.push(PeripheralDemand {
+                        peripheral: p,
+                        uses: 0,
+                        peak: 0,
+                        available,
+                    });
+                    demand_levels.push(Vec::new());
+                    demands.len() - 1
+                }
+            };
+            demands[i].uses += 1;
+            demand_levels[i].push(level);
+        }
+    }
+
+    for (demand, levels) in demands.iter_mut().zip(demand_levels) {
+        let mut counts: IndexMap<usize, usize> = IndexMap::new();
+        for level in levels {
+            *counts.entry(level).or_default() += 1;
+        }
+        demand.peak = counts.values().cloned().max().unwrap_or(0);
+    }
+
+    // droplets live from the level that makes them until the one that uses
+    // them, unconsumed droplets live until the end
+    let n_levels = levels.values().map(|l| l + 1).max().unwrap_or(0);
+    let mut edges: Vec<_> = levels
+        .keys()
+        .flat_map(|&node| g.edges_directed(node, Outgoing))
+        .collect();
+    edges.sort_by_key(|e| e.id());
+
+    let mut unconsumed = Vec::new();
+    let mut lifetimes = Vec::new();
+    for e in edges {
+        let id = *e.weight();
+        let start = levels[&e.source()];
+        let end = if g[e.target()].is_some() {
+            levels.get(&e.target()).cloned().unwrap_or(n_levels)
+        } else {
+            unconsumed.push(id);
+            n_levels
+        };
+        lifetimes.push((start, end, occupancy(dims[&id])));
+    }
+
+    let peak_occupancy = (0..n_levels)
+        .map(|level| {
+            lifetimes
+                .iter()
+                .filter(|(start, end, _)| *start <= level && level < *end)
+                .map(|(_, _, occ)| occ)
+                .sum()
+        })
+        .max()
+        .unwrap_or(0);
+
+    // all the unconsumed droplets end up on the board together
+    let final_occupancy = unconsumed.iter().map(|id| occupancy(dims[id])).sum();
+    min_occupancy = min_occupancy.max(final_occupancy);
+
+    let usable_electrodes = grid
+        .locations()
+        .filter(|(loc, _)| !grid.is_dead(*loc))
+        .count();
+
+    let mut problems = Vec::new();
+
+    let (height, width) = (grid.max_height() as i32, grid.max_width() as i32);
+    for (&droplet, &dimensions) in &dims {
+        if dimensions.y > height || dimensions.x > width {
+            problems.push(Problem::DropletTooBig {
+                droplet,
+                dimensions,
+            });
+        }
+    }
+
+    for demand in &demands {
+        if demand.available == 0 {
+            let peripheral = demand.peripheral.clone();
+            problems.push(Problem::MissingPeripheral { peripheral });
+        }
+    }
+
+    if min_occupancy > usable_electrodes {
+        problems.push(Problem::TooCrowded {
+            needed: min_occupancy,
+            available: usable_electrodes,
+        });
+    }
+
+    Analysis {
+        unconsumed,
+        peak_occupancy,
+        min_occupancy,
+        usable_electrodes,
+        peripherals: demands,
+        problems,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Combine, Create, Input, Split};
+
+    fn id(id: usize) -> DropletId {
+        id.into()
+    }
+
+    #[test]
+    fn test_analyze() {
+        let mut graph = Graph::default();
+        let cmds: Vec<crate::command::BoxedCommand> = vec![
+            Box::new(Create::new(None, 1.0, Some(yx(2, 2)), id(0)).unwrap()),
+            Box::new(Input::new("water".into(), 1.0, yx(1, 1), id(1)).unwrap()),
+            Box::new(Combine::new(id(0), id(1), id(2)).unwrap()),
+            Box::new(Split::new(id(2), id(3), id(4)).unwrap()),
+        ];
+        let order: Vec<_> = cmds
+            .into_iter()
+            .map(|cmd| graph.add_command(cmd).unwrap())
+            .collect();
+
+        let grid = Grid::rectangle(2, 3);
+
+        // just the split, as if the rest had already run
+        let analysis = analyze_commands(&graph, &grid, &order[3..]);
+        assert_eq!(analysis.unconsumed, vec![id(3), id(4)]);
+        assert!(analysis.peripherals.is_empty());
+        // the split's input isn't known, so it's taken to be (1, 1)
+        assert_eq!(analysis.min_occupancy, 4 + 4);
+
+        let analysis = analyze(&graph, &grid);
+
+        assert_eq!(analysis.unconsumed, vec![id(3), id(4)]);
+        // combined into (3, 2), then split into two (2, 2)s, which are left
+        // on the board at the end
+        assert_eq!(analysis.peak_occupancy, 9 + 9);
+        assert_eq!(analysis.min_occupancy, 9 + 9);
+        assert_eq!(analysis.usable_electrodes, 6);
+
+        assert_eq!(analysis.peripherals.len(), 1);
+        assert_eq!(analysis.peripherals[0].available, 0);
+
+        assert_eq!(
+            analysis.problems,
+            vec![
+                Problem::DropletTooBig {
+                    droplet: id(2),
+                    dimensions: yx(3, 2),
+                },
+                Problem::MissingPeripheral {
+                    peripheral: Peripheral::Input {
+                        pwm_channel: 0,
+                        name: "water".into(),
+                    },
+                },
+                Problem::TooCrowded {
+                    needed: 18,
+                    available: 6,
+                },
+            ]
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use petgraph::prelude as pg;
+use petgraph::visit::EdgeRef;
 
 use crate::util::find_duplicate;
 
@@ -101,6 +102,44 @@ impl Graph {
 
         Ok(cmd_id)
     }
+
+    /// Takes commands out of the graph. Anything that uses their outputs
+    /// should be removed along with them. The droplets they used become
+    /// unbound again, and the droplets they would have made are forgotten.
+    pub fn remove_commands(&mut self, cmds: &[CmdIndex]) -> Vec<BoxedCommand> {
+        let g = &mut self.graph;
+
+        // unbind the inputs that come from commands that are staying
+        for &cmd_id in cmds {
+            let inputs: Vec<_> = g
+                .edges_directed(cmd_id, pg::Incoming)
+                .filter(|e| !cmds.contains(&e.source()))
+                .map(|e| (e.id(), e.source(), *e.weight()))
+                .collect();
+            for (e_idx, src, id) in inputs {
+                g.remove_edge(e_idx);
+                let unbound = g.add_node(None);
+                self.droplet_idx[&id] = g.add_edge(src, unbound, id);
+            }
+        }
+
+        // forget all their outputs before taking any of them out, since
+        // removing a command takes its incoming edges with it
+        let outputs: Vec<_> = cmds
+            .iter()
+            .flat_map(|&cmd_id| g.edges_directed(cmd_id, pg::Outgoing))
+            .map(|e| (e.target(), *e.weight()))
+            .collect();
+        for (tgt, id) in outputs {
+            self.droplet_idx.swap_remove(&id);
+            if g[tgt].is_none() {
+                g.remove_node(tgt);
+            }
+        }
+
+        let removed = cmds.iter().filter_map(|&cmd_id| g.remove_node(cmd_id));
+        removed.flatten().collect()
+    }
 }
 
 #[cfg(test)]
@@ -142,4 +181,32 @@ mod tests {
         assert_matches!(r, Err(GraphError::AlreadyBound(_)));
     }
 
+    #[test]
+    fn test_remove_commands() {
+        let mut graph = Graph::default();
+        graph.add_command(input(0)).unwrap();
+        graph.add_command(input(1)).unwrap();
+        let mix1 = graph.add_command(mix(0, 1, 2)).unwrap();
+
+        let removed = graph.remove_commands(&[mix1]);
+        assert_eq!(removed.len(), 1);
+        assert!(!graph.droplet_idx.contains_key(&DropletId::from(2)));
+
+        // the inputs are free to be used again
+        let r = graph.add_command(mix(0, 1, 3));
+        assert_matches!(r, Ok(_));
+    }
+
+    #[test]
+    fn test_remove_commands_any_order() {
+        let mut graph = Graph::default();
+        let in0 = graph.add_command(input(0)).unwrap();
+        let in1 = graph.add_command(input(1)).unwrap();
+        let mix1 = graph.add_command(mix(0, 1, 2)).unwrap();
+
+        // the user comes out before what it uses
+        let removed = graph.remove_commands(&[mix1, in1, in0]);
+        assert_eq!(removed.len(), 3);
+        assert!(graph.droplet_idx.is_empty());
+    }
 }
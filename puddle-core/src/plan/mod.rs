@@ -1,24 +1,43 @@
+pub mod analyze;
 // TODO move graph
 pub mod graph;
 pub mod place;
 mod route;
 pub mod sched;
 
+use self::analyze::occupancy;
 use self::graph::{CmdIndex, Graph};
-use self::place::{Placement, PlacementRequest, Placer};
-use self::route::{Agent, Router, RoutingRequest};
-use self::sched::{SchedRequest, Scheduler};
+use self::place::{Placement, PlacementRequest, PlacementResponse, Placer};
+use self::route::{Agent, Router, RoutingError, RoutingRequest, RoutingResponse};
+use self::sched::{SchedRequest, SchedResponse, Scheduler};
 
 pub use self::route::Path;
 
+use std::time::Duration;
+
+use crate::command::CommandRequest;
 use crate::grid::{droplet::DropletId, GridView};
-use indexmap::IndexMap;
+use crate::process::ProcessId;
+use crate::util::Stopwatch;
+use indexmap::{IndexMap, IndexSet};
+use serde::Serialize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PlanError {
     RouteError(self::route::RoutingError),
     SchedError(self::sched::SchedError),
     PlaceError(self::place::PlacementError),
+    /// The command's process was killed before it could finish.
+    Cancelled,
+}
+
+/// Planning failed while trying to run `commands`. When a placement or
+/// route can't be found, planning backs off until it's down to the one
+/// command to blame.
+#[derive(Debug)]
+pub struct PlanFailure {
+    pub error: PlanError,
+    pub commands: Vec<CmdIndex>,
 }
 
 pub struct PlannedCommand {
@@ -32,10 +51,28 @@ pub struct PlanPhase {
     pub planned_commands: Vec<PlannedCommand>,
 }
 
-type PlanResult = Result<PlanPhase, PlanError>;
+type PlanResult = Result<PlanPhase, PlanFailure>;
+
+/// Time spent in each part of planning, summed over all the plans made.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PlanStats {
+    pub n_plans: usize,
+    pub schedule: Duration,
+    pub place: Duration,
+    pub route: Duration,
+    /// Search nodes the router has looked at.
+    pub nodes_expanded: usize,
+    /// Times a plan failed and was tried again with fewer commands.
+    pub placement_retries: usize,
+}
 
 pub struct Planner {
     pub gridview: GridView,
+    pub stats: PlanStats,
+    /// The most electrodes each process's droplets may take up at once.
+    pub quotas: IndexMap<ProcessId, usize>,
+    /// Processes that shouldn't have anything else planned.
+    pub stopped: IndexSet<ProcessId>,
     scheduler: Scheduler,
     placer: Placer,
     router: Router,
@@ -45,34 +82,108 @@ impl Planner {
     pub fn new(gridview: GridView) -> Planner {
         Planner {
             gridview: gridview,
+            stats: PlanStats::default(),
+            quotas: IndexMap::new(),
+            stopped: IndexSet::new(),
             scheduler: Scheduler::default(),
             placer: Placer::default(),
             router: Router::default(),
         }
     }
 
-    pub fn plan(&mut self, graph: &Graph, _droplets: &[DropletId]) -> PlanResult {
+    pub fn is_scheduled(&self, cmd: CmdIndex) -> bool {
+        self.scheduler.is_scheduled(cmd)
+    }
+
+    /// Treats these commands as already run, without planning them.
+    pub fn mark_scheduled(&mut self, commands_to_run: Vec<CmdIndex>) {
+        self.scheduler.commit(&SchedResponse {
+            commands_to_run,
+            droplets_to_store: vec![],
+        })
+    }
+
+    /// How many more electrodes each process with a quota may take up.
+    fn budgets(&self) -> IndexMap<ProcessId, isize> {
+        let mut budgets: IndexMap<_, _> = self
+            .quotas
+            .iter()
+            .map(|(&pid, &quota)| (pid, quota as isize))
+            .collect();
+        for droplet in self.gridview.droplets.values() {
+            if let Some(budget) = budgets.get_mut(&droplet.id.process_id) {
+                *budget -= occupancy(droplet.footprint.dimensions()) as isize;
+            }
+        }
+        budgets
+    }
+
+    /// How many electrodes each command that's ready to run would take up.
+    fn costs(&self, graph: &Graph) -> IndexMap<CmdIndex, isize> {
+        let mut costs = IndexMap::new();
+        for cmd_id in graph.graph.node_indices() {
+            let cmd = match &graph.graph[cmd_id] {
+                Some(cmd) if !self.is_scheduled(cmd_id) => cmd,
+                _ => continue,
+            };
+            let input_dims: Option<Vec<_>> = cmd
+                .input_droplets()
+                .iter()
+                .map(|id| self.gridview.droplets.get(id))
+                .map(|d| d.map(|d| d.footprint.dimensions()))
+                .collect();
+            let input_dims = match input_dims {
+                Some(dims) => dims,
+                None => continue,
+            };
+            let output_dims = cmd.output_dimensions(&input_dims);
+            let taken: usize = output_dims.into_iter().map(occupancy).sum();
+            let freed: usize = input_dims.into_iter().map(occupancy).sum();
+            costs.insert(cmd_id, taken as isize - freed as isize);
+        }
+        costs
+    }
+
+    /// Plans the next phase of commands. If `droplets` isn't empty, only
+    /// the commands needed for those droplets are considered.
+    pub fn plan(&mut self, graph: &Graph, droplets: &[DropletId]) -> PlanResult {
         debug!("Planning GV: {:#?}", self.gridview.droplets);
         self.gridview.check_no_collision();
 
+        let mut watch = Stopwatch::start();
+        let wanted = if droplets.is_empty() {
+            None
+        } else {
+            Some(sched::needed_commands(graph, droplets))
+        };
+        let (budgets, costs) = if self.quotas.is_empty() {
+            (IndexMap::new(), IndexMap::new())
+        } else {
+            (self.budgets(), self.costs(graph))
+        };
         let mut sched_limit = None;
-        let (sched_resp, command_requests, place_resp) = loop {
+        let (sched_resp, command_requests, place_resp, route_resp) = loop {
             let sched_resp = {
                 let req = SchedRequest {
                     graph,
                     limit: sched_limit,
+                    wanted: wanted.clone(),
+                    budgets: budgets.clone(),
+                    costs: costs.clone(),
+                    stopped: self.stopped.clone(),
                 };
                 debug!("Schedule request");
-                let resp = self
-                    .scheduler
-                    .schedule(&req)
-                    .map_err(PlanError::SchedError)?;
+                let resp = self.scheduler.schedule(&req).map_err(|e| PlanFailure {
+                    error: PlanError::SchedError(e),
+                    commands: vec![],
+                })?;
                 debug!("{:?}", resp);
                 for cmd_id in &resp.commands_to_run {
                     debug!("Gonna schedule {:?}: {:?}", cmd_id, graph.graph[*cmd_id])
                 }
                 resp
             };
+            self.stats.schedule += watch.lap();
 
             let command_requests: Vec<_> = sched_resp
                 .commands_to_run
@@ -99,66 +210,41 @@ impl Planner {
                 stored_droplets: sched_resp.droplets_to_store.as_slice(),
             };
             let place = self.placer.place(req);
+            self.stats.place += watch.lap();
             debug!("Placement result: {:#?}", place);
-            match place {
-                Ok(resp) => break (sched_resp, command_requests, resp),
-                Err(e) => {
-                    if command_requests.len() <= 1 {
-                        error!("Actually failing to place for real");
-                        return Err(PlanError::PlaceError(e));
+            let result = place.map_err(PlanError::PlaceError).and_then(|place_resp| {
+                let route = self.route(graph, &sched_resp, &command_requests, &place_resp);
+                self.stats.route += watch.lap();
+                self.stats.nodes_expanded = self.router.nodes_expanded;
+                route
+                    .map(|route_resp| (place_resp, route_resp))
+                    .map_err(PlanError::RouteError)
+            });
+
+            match result {
+                Ok((place_resp, route_resp)) => {
+                    break (sched_resp, command_requests, place_resp, route_resp)
+                }
+                Err(error) => {
+                    let n_cmds = sched_resp.commands_to_run.len();
+                    if n_cmds <= 1 {
+                        error!("Actually failing to plan for real");
+                        return Err(PlanFailure {
+                            error,
+                            commands: sched_resp.commands_to_run,
+                        });
                     } else {
-                        let n_cmds = sched_resp.commands_to_run.len();
-                        assert!(n_cmds > 1);
                         info!(
-                            "Failed to place, rolling back to {} parallel commands",
+                            "Failed to plan, rolling back to {} parallel commands",
                             n_cmds - 1
                         );
+                        self.stats.placement_retries += 1;
                         sched_limit = Some(n_cmds - 1)
                     }
                 }
             }
         };
 
-        let route_resp = {
-            let mut agents: Vec<_> = sched_resp
-                .droplets_to_store
-                .iter()
-                .zip(place_resp.stored_droplets)
-                .map(|(id, loc)| Agent::from_droplet(&self.gridview.droplets[id], loc))
-                .collect();
-
-            // TODO getting these input droplets is pretty painful
-            let placed = sched_resp
-                .commands_to_run
-                .iter()
-                .zip(&command_requests)
-                .zip(&place_resp.commands);
-            for ((cmd_id, req), placement) in placed {
-                let cmd = graph.graph[*cmd_id].as_ref().expect("Command was unbound!");
-                let in_ids = cmd.input_droplets();
-                let ins = in_ids.iter().zip(&req.input_locations);
-                for (&droplet_id, location) in ins {
-                    agents.push(self::route::Agent {
-                        id: droplet_id,
-                        source: self.gridview.droplets[&droplet_id].location,
-                        dimensions: self.gridview.droplets[&droplet_id].dimensions,
-                        destination: placement.mapping[location],
-                    });
-                }
-            }
-
-            let req = RoutingRequest {
-                agents,
-                gridview: &self.gridview,
-                blockages: vec![],
-            };
-            // debug!("{:?}", req);
-            let resp = self.router.route(&req).map_err(PlanError::RouteError)?;
-            debug!("{:?}", resp);
-
-            resp
-        };
-
         let routes = route_resp.routes;
         let planned_commands: Vec<_> = sched_resp
             .commands_to_run
@@ -174,10 +260,52 @@ impl Planner {
 
         // now commit to the schedule
         self.scheduler.commit(&sched_resp);
+        self.stats.n_plans += 1;
 
         Ok(PlanPhase {
             routes,
             planned_commands,
         })
     }
+
+    fn route(
+        &mut self,
+        graph: &Graph,
+        sched_resp: &SchedResponse,
+        command_requests: &[CommandRequest],
+        place_resp: &PlacementResponse,
+    ) -> Result<RoutingResponse, RoutingError> {
+        let mut agents: Vec<_> = sched_resp
+            .droplets_to_store
+            .iter()
+            .zip(&place_resp.stored_droplets)
+            .map(|(id, loc)| Agent::from_droplet(&self.gridview.droplets[id], *loc))
+            .collect();
+
+        // TODO getting these input droplets is pretty painful
+        let placed = sched_resp
+            .commands_to_run
+            .iter()
+            .zip(command_requests)
+            .zip(&place_resp.commands);
+        for ((cmd_id, req), placement) in placed {
+            let cmd = graph.graph[*cmd_id].as_ref().expect("Command was unbound!");
+            let in_ids = cmd.input_droplets();
+            let ins = in_ids.iter().zip(&req.input_locations);
+            for (&droplet_id, location) in ins {
+                let droplet = &self.gridview.droplets[&droplet_id];
+                agents.push(Agent::from_droplet(droplet, placement.mapping[location]));
+            }
+        }
+
+        let req = RoutingRequest {
+            agents,
+            gridview: &self.gridview,
+            blockages: vec![],
+        };
+        // debug!("{:?}", req);
+        let resp = self.router.route(&req)?;
+        debug!("{:?}", resp);
+        Ok(resp)
+    }
 }
@@ -1,5 +1,5 @@
 use crate::command::CommandRequest;
-use crate::grid::{DropletId, Grid, GridView, Location, Rectangle};
+use crate::grid::{DropletId, Grid, GridView, Location};
 use indexmap::{IndexMap, IndexSet};
 
 #[derive(Debug, Clone)]
@@ -11,7 +11,7 @@ pub struct Placement {
 #[derive(Debug)]
 pub struct PlacementResponse {
     pub commands: Vec<Placement>,
-    // droplets only need to be "placed" by their upper left corner
+    // droplets only need to be "placed" by their anchor
     pub stored_droplets: Vec<Location>,
 }
 
@@ -22,7 +22,7 @@ pub struct PlacementRequest<'a> {
     pub stored_droplets: &'a [DropletId],
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PlacementError {
     Bad,
 }
@@ -58,8 +58,10 @@ impl<'a> Context<'a> {
 
             // check to make sure this forced placement is valid
             for loc in mapping.values() {
-                let nbrs = self.req.gridview.grid.neighbors9(*loc);
-                if nbrs.iter().any(|n| self.bad_locs.contains(n)) {
+                if !self.req.gridview.is_usable(*loc) {
+                    return Err(PlacementError::Bad);
+                }
+                if is_near_bad(&self.req.gridview.grid, *loc, &self.bad_locs) {
                     return Err(PlacementError::Bad);
                 }
             }
@@ -104,30 +106,32 @@ impl<'a> Context<'a> {
 
         let droplet = &self.req.gridview.droplets[&id];
 
+        // the shape's upper left corner isn't necessarily the anchor, so
+        // translate between the two
+        let shape = Grid::from_footprint(&droplet.footprint);
+        let anchor = droplet.footprint.anchor();
+
         let mut locations_by_distance: Vec<(u32, Location)> = self
             .req
             .gridview
             .grid
             .locations()
-            .map(|(loc, _cell)| (loc.distance_to(droplet.location), loc))
+            .map(|(loc, _cell)| (droplet.location.distance_to(loc + anchor), loc))
             .collect();
         locations_by_distance.sort();
 
-        let Location { y, x } = droplet.dimensions;
-        let shape = Grid::rectangle(y as usize, x as usize);
-
         let offset = locations_by_distance
             .iter()
             .map(|&(_distance, loc)| loc)
             .find(|loc| self.is_compatible(&shape, *loc))
             .ok_or(PlacementError::Bad)?;
 
-        debug!("Placed at {:?}", offset);
-        Ok(offset)
+        debug!("Placed at {:?}", offset + anchor);
+        Ok(offset + anchor)
     }
 
     fn is_compatible(&self, smaller: &Grid, offset: Location) -> bool {
-        is_compatible(&self.req.gridview.grid, smaller, offset, &self.bad_locs)
+        is_compatible(self.req.gridview, smaller, offset, &self.bad_locs)
     }
 
     fn place(mut self) -> PlacementResult {
@@ -143,15 +147,10 @@ impl<'a> Context<'a> {
 
         // iteratively place the droplets
         for id in self.req.stored_droplets {
-            let offset = self.place_droplet(*id)?;
-            self.bad_locs.extend(
-                Rectangle {
-                    location: offset,
-                    dimensions: self.req.gridview.droplets[id].dimensions,
-                }
-                .locations(),
-            );
-            self.resp.stored_droplets.push(offset)
+            let location = self.place_droplet(*id)?;
+            let footprint = &self.req.gridview.droplets[id].footprint;
+            self.bad_locs.extend(footprint.locations(location));
+            self.resp.stored_droplets.push(location)
         }
 
         Ok(self.resp)
@@ -169,7 +168,7 @@ impl Placer {
 }
 
 fn is_compatible(
-    bigger: &Grid,
+    bigger: &GridView,
     smaller: &Grid,
     offset: Location,
     bad_locs: &IndexSet<Location>,
@@ -177,21 +176,27 @@ fn is_compatible(
     smaller.locations().all(|(small_loc, small_cell)| {
         let big_loc = small_loc + offset;
 
-        let padding = 2;
-        let bad = bad_locs
-            .iter()
-            .any(|bad| (bad.x - big_loc.x).abs() < padding && (bad.y - big_loc.y).abs() < padding);
-        if bad {
+        if is_near_bad(&bigger.grid, big_loc, bad_locs) {
             return false;
         };
 
+        if !bigger.is_usable(big_loc) {
+            return false;
+        }
+
         // return the compatibility
         bigger
+            .grid
             .get_cell(big_loc)
             .map_or(false, |big_cell| small_cell.is_compatible(&big_cell))
     })
 }
 
+/// Whether this location is on or touching a bad location.
+fn is_near_bad(grid: &Grid, loc: Location, bad_locs: &IndexSet<Location>) -> bool {
+    bad_locs.contains(&loc) || grid.touching(loc).iter().any(|n| bad_locs.contains(n))
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -199,12 +204,62 @@ mod tests {
 
     #[test]
     fn grid_self_compatible() {
-        let grid = Grid::rectangle(5, 4);
+        let gv = GridView::new(Grid::rectangle(5, 4));
         let shape = Grid::rectangle(5, 4);
         let offset = Location { y: 0, x: 0 };
         let bad_locs = IndexSet::default();
 
-        assert!(is_compatible(&grid, &shape, offset, &bad_locs))
+        assert!(is_compatible(&gv, &shape, offset, &bad_locs))
+    }
+
+    #[test]
+    fn place_around_dead_electrodes() {
+        use crate::grid::gridview::tests::{c2id, parse_gridview};
+        use crate::grid::location::yx;
+
+        #[rustfmt::skip]
+        let mut gv = parse_gridview(&[
+            "a.....",
+            "......",
+        ]);
+        // the droplet can't stay on a dead electrode
+        gv.grid.dead_electrodes.insert(yx(0, 0));
+        // and it can't go to a worn out one either
+        gv.health.endurance = Some(1);
+        gv.health.actuate(yx(0, 1));
+
+        let req = PlacementRequest {
+            gridview: &gv,
+            fixed_commands: vec![],
+            commands: &[],
+            stored_droplets: &[c2id('a')],
+        };
+        let resp = Placer::default().place(req).unwrap();
+
+        assert_eq!(resp.stored_droplets, vec![yx(1, 0)]);
+    }
+
+    #[test]
+    fn place_irregular_droplet() {
+        use crate::grid::gridview::tests::{c2id, parse_gridview};
+
+        // the anchor of this droplet is not the corner of its bounding box,
+        // and a 2x2 rectangle wouldn't fit here
+        #[rustfmt::skip]
+        let gv = parse_gridview(&[
+            "...a",
+            "  aa",
+        ]);
+
+        let req = PlacementRequest {
+            gridview: &gv,
+            fixed_commands: vec![],
+            commands: &[],
+            stored_droplets: &[c2id('a')],
+        };
+        let resp = Placer::default().place(req).unwrap();
+
+        assert_eq!(resp.stored_droplets, vec![Location { y: 0, x: 3 }]);
     }
 
     // #[test]
@@ -251,5 +306,4 @@ mod tests {
     //         Some(&heater_loc)
     //     );
     // }
-
 }
@@ -1,6 +1,7 @@
+use std::cell::Cell;
 use std::rc::Rc;
 
-use crate::grid::{grid::NEIGHBORS_5, Droplet, DropletId, Grid, GridView, Location, Rectangle};
+use crate::grid::{Droplet, DropletId, Footprint, Grid, GridView, Location};
 use indexmap::IndexMap;
 
 pub type Path = Vec<Location>;
@@ -16,20 +17,25 @@ pub struct RoutingResponse {
     pub routes: IndexMap<DropletId, Path>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RoutingError {
     NoRoute { agents: Vec<Agent> },
 }
 
 #[derive(Default)]
-pub struct Router {}
+pub struct Router {
+    /// Search nodes looked at over every route, for `metrics`.
+    pub nodes_expanded: usize,
+}
 
 impl Router {
     pub fn route(&mut self, req: &RoutingRequest) -> Result<RoutingResponse, RoutingError> {
         debug!("Routing agents: {:#?}", req.agents);
 
         let mut ctx = Context::from_request(req);
-        match ctx.route() {
+        let paths = ctx.route();
+        self.nodes_expanded += ctx.expanded.get();
+        match paths {
             Some(paths) => Ok(RoutingResponse {
                 routes: paths.into_iter().collect(),
             }),
@@ -48,7 +54,7 @@ pub struct Agent {
     pub id: DropletId,
     pub source: Location,
     pub destination: Location,
-    pub dimensions: Location,
+    pub footprint: Footprint,
 }
 
 impl Agent {
@@ -56,14 +62,10 @@ impl Agent {
         Agent {
             id: d.id,
             source: d.location,
-            dimensions: d.dimensions,
+            footprint: d.footprint.clone(),
             destination,
         }
     }
-
-    fn rectangle(&self, loc: Location) -> Rectangle {
-        Rectangle::new(loc, self.dimensions)
-    }
 }
 
 #[derive(Debug)]
@@ -96,6 +98,9 @@ type EdgeCost = u32;
 const STAY_COST: EdgeCost = 4;
 const MOVE_COST: EdgeCost = 5;
 const COLLISION_COST: EdgeCost = 50;
+// cost of stepping onto a completely worn out electrode, partially worn
+// electrodes cost proportionally less
+const WEAR_COST: EdgeCost = 20;
 
 fn step_cost(loc: Location) -> EdgeCost {
     let sit_still = Location { y: 0, x: 0 };
@@ -120,10 +125,10 @@ impl Node {
         self.locations.iter().zip(&group.agents)
     }
 
-    fn heuristic(&self, group: &Group) -> u32 {
+    fn heuristic(&self, ctx: &Context, group: &Group) -> u32 {
         let n_steps: u32 = self
             .with_group(group)
-            .map(|(l, a)| l.distance_to(a.destination))
+            .map(|(&l, a)| ctx.grid.topology.distance(l, a.destination))
             .sum();
         MOVE_COST * n_steps
     }
@@ -134,11 +139,10 @@ impl Node {
     }
 
     fn is_valid(&self, ctx: &Context, group: &Group) -> bool {
-        // make sure all the agents are in the grid
+        // make sure all the agents are on electrodes the placer would use
         for (&loc, agent) in self.with_group(group) {
-            let rect = agent.rectangle(loc);
-            for rloc in rect.locations() {
-                if ctx.grid.get_cell(rloc).is_none() {
+            for floc in agent.footprint.locations(loc) {
+                if !ctx.gridview.is_usable(floc) {
                     return false;
                 }
             }
@@ -146,13 +150,8 @@ impl Node {
 
         let mut iter = self.with_group(group);
         while let Some((&loc1, a1)) = iter.next() {
-            let r1 = a1.rectangle(loc1);
             for (&loc2, a2) in iter.clone() {
-                let r2 = a2.rectangle(loc2);
-                let dist = r1.collision_distance(&r2);
-                // collision distance is the number of spaces between, so
-                // anything above 0 is good
-                if dist <= 0 {
+                if ctx.collides(a1, loc1, a2, loc2) {
                     return false;
                 }
             }
@@ -176,15 +175,19 @@ impl Node {
             .map(|(&agent, &offset)| agent + offset)
             .collect();
 
-        let edge_cost = offsets.iter().cloned().map(step_cost).sum();
-
         let node = Node {
             locations: new_locs,
             time: self.time + 1,
         };
 
         if node.is_valid(ctx, group) {
-            Some((edge_cost, node))
+            let step: EdgeCost = offsets.iter().cloned().map(step_cost).sum();
+            let wear: EdgeCost = node
+                .with_group(group)
+                .flat_map(|(&loc, agent)| agent.footprint.locations(loc))
+                .map(|loc| ctx.wear_cost(loc))
+                .sum();
+            Some((step + wear, node))
         } else {
             None
         }
@@ -193,22 +196,27 @@ impl Node {
     // This is rather naive for now, it pretty much always generates
     // exponentially many new agents
     fn open(&self, ctx: &Context, group: &Group, new_nodes: &mut Vec<(EdgeCost, Node)>) {
-        let nbrs = NEIGHBORS_5;
+        // each agent gets its own set of moves, since that can depend on
+        // where it is in non-square topologies
+        let nbrs: Vec<_> = self
+            .with_group(group)
+            .map(|(&loc, agent)| ctx.grid.step_offsets(&agent.footprint, loc))
+            .collect();
         let mut assignments = vec![0; self.locations.len()];
-        let mut new_locations = Vec::with_capacity(nbrs.len());
+        let mut new_locations = Vec::with_capacity(self.locations.len());
 
         'outer: loop {
             // commit this assignment
             new_locations.clear();
-            new_locations.extend(assignments.iter().map(|a| nbrs[*a]));
+            new_locations.extend(assignments.iter().zip(&nbrs).map(|(a, n)| n[*a]));
 
             if let Some(agent) = self.take_action(ctx, group, &new_locations) {
                 new_nodes.push(agent)
             }
 
             // advance the assignments by basically doing carry addition
-            for a in assignments.iter_mut() {
-                if *a + 1 < nbrs.len() {
+            for (a, n) in assignments.iter_mut().zip(&nbrs) {
+                if *a + 1 < n.len() {
                     // don't have to carry, addition is complete
                     *a += 1;
                     continue 'outer;
@@ -238,8 +246,10 @@ struct Collision {
 // borrows from request
 struct Context<'req> {
     grid: &'req Grid,
+    gridview: &'req GridView,
     agents: IndexMap<DropletId, Agent>,
     groups: IndexMap<DropletId, Rc<Group>>,
+    expanded: Cell<usize>,
 }
 
 type PathMap = IndexMap<DropletId, Vec<Location>>;
@@ -250,15 +260,26 @@ impl Context<'_> {
 
         Context {
             grid: &req.gridview.grid,
+            gridview: req.gridview,
             // TODO we can make agents ourselves instead of the request doing it
             agents: agents().map(|a| (a.id, a)).collect(),
             // each group is a singleton node for now,
             groups: agents()
                 .map(|a| (a.id, Rc::new(Group::singleton(a))))
                 .collect(),
+            expanded: Cell::new(0),
         }
     }
 
+    fn collides(&self, a1: &Agent, loc1: Location, a2: &Agent, loc2: Location) -> bool {
+        self.grid
+            .footprints_touch(&a1.footprint, loc1, &a2.footprint, loc2)
+    }
+
+    fn wear_cost(&self, loc: Location) -> EdgeCost {
+        (self.gridview.health.wear(loc) * f64::from(WEAR_COST)).round() as EdgeCost
+    }
+
     fn find_collisions(&self, paths: &PathMap) -> Vec<Collision> {
         let mut collisions = Vec::new();
 
@@ -271,10 +292,9 @@ impl Context<'_> {
                 let a1 = &self.agents[&id1];
                 let p1 = p1.as_ref();
                 let loc1 = path_nth(p1, time);
-                let rect1 = Rectangle::new(loc1, a1.dimensions);
 
                 if cfg!(debug_assertions) {
-                    for loc in rect1.clone().locations() {
+                    for loc in a1.footprint.locations(loc1) {
                         assert!(self.grid.get_cell(loc).is_some())
                     }
                 }
@@ -283,8 +303,7 @@ impl Context<'_> {
                     let a2 = &self.agents[&id2];
                     let p2 = p2.as_ref();
                     let loc2 = path_nth(p2, time);
-                    let rect2 = Rectangle::new(loc2, a2.dimensions);
-                    if rect1.collision_distance(&rect2) <= 0 {
+                    if self.collides(a1, loc1, a2, loc2) {
                         let c = Collision { id1, id2, time };
                         collisions.push(c)
                     }
@@ -302,20 +321,11 @@ impl Context<'_> {
         node: &Node,
     ) -> Option<DropletId> {
         for (id, path) in paths {
-            let location = path_nth(path, node.time as usize);
-            let dimensions = self.agents[id].dimensions;
-            let path_rect = Rectangle {
-                location,
-                dimensions,
-            };
+            let path_loc = path_nth(path, node.time as usize);
+            let path_agent = &self.agents[id];
             for (a, &location) in group.agents.iter().zip(node.locations.iter()) {
                 assert_ne!(*id, a.id);
-                let dimensions = a.dimensions;
-                let rect = Rectangle {
-                    location,
-                    dimensions,
-                };
-                if rect.collision_distance(&path_rect) <= 0 {
+                if self.collides(a, location, path_agent, path_loc) {
                     return Some(*id);
                 }
             }
@@ -425,7 +435,7 @@ impl Context<'_> {
         };
 
         let heuristic = |n: &Node| {
-            let mut h = n.heuristic(group);
+            let mut h = n.heuristic(self, group);
             if self.find_collisions_with(paths, group, n).is_some() {
                 h += COLLISION_COST;
             }
@@ -444,6 +454,7 @@ impl Context<'_> {
         };
 
         let result = pathfinding::directed::astar::astar(&start, successors, heuristic, success);
+        self.expanded.set(self.expanded.get() + seen);
 
         #[cfg(not(target_arch = "wasm32"))]
         {
@@ -615,6 +626,171 @@ mod tests {
         check_paths(&gv0, &paths, &expected);
     }
 
+    #[test]
+    fn test_irregular_route() {
+        // an L-shaped droplet should route just like a rectangular one
+        #[rustfmt::skip]
+        let gv0 = parse_gridview(&[
+            "a.    ",
+            "aa....",
+            " .....",
+            " .....",
+        ]);
+
+        #[rustfmt::skip]
+        let gv1 = parse_gridview(&[
+            "..    ",
+            "......",
+            " ...a.",
+            " ...aa",
+        ]);
+
+        let req = &mk_route_request(&gv0, &gv1);
+        let mut ctx = Context::from_request(req);
+        let paths = ctx.route().unwrap();
+
+        let path = &paths[&c2id('a')];
+        assert_eq!(path.first(), Some(&Location { y: 0, x: 0 }));
+        assert_eq!(path.last(), Some(&Location { y: 2, x: 4 }));
+    }
+
+    #[test]
+    fn test_route_around_bad_electrodes() {
+        use crate::grid::location::yx;
+
+        #[rustfmt::skip]
+        let mut gv0 = parse_gridview(&[
+            "a..",
+            "...",
+            "...",
+        ]);
+
+        #[rustfmt::skip]
+        let mut gv1 = parse_gridview(&[
+            "...",
+            "...",
+            "..a",
+        ]);
+
+        // the middle is dead, and the left side is almost worn out
+        for gv in &mut [&mut gv0, &mut gv1] {
+            gv.grid.dead_electrodes.insert(yx(1, 1));
+            gv.health.endurance = Some(10);
+            for _ in 0..9 {
+                gv.health.actuate(yx(1, 0));
+                gv.health.actuate(yx(2, 0));
+            }
+        }
+
+        let mut expected = ExpectedPaths::default();
+        #[rustfmt::skip]
+        expected.insert('a', &[
+            "Aaa",
+            "..a",
+            "..a",
+        ]);
+
+        let req = &mk_route_request(&gv0, &gv1);
+        let mut ctx = Context::from_request(req);
+        let paths = ctx.route().unwrap();
+
+        check_paths(&gv0, &paths, &expected);
+    }
+
+    #[test]
+    fn test_route_around_worn_out_electrodes() {
+        use crate::grid::location::yx;
+
+        #[rustfmt::skip]
+        let mut gv0 = parse_gridview(&[
+            "a..",
+            ". .",
+            ". .",
+            "...",
+        ]);
+
+        #[rustfmt::skip]
+        let mut gv1 = parse_gridview(&[
+            "..a",
+            ". .",
+            ". .",
+            "...",
+        ]);
+
+        // the way across is worn out, so it can't be used no matter how far
+        // around the other way is
+        for gv in &mut [&mut gv0, &mut gv1] {
+            gv.health.endurance = Some(10);
+            for _ in 0..10 {
+                gv.health.actuate(yx(0, 1));
+            }
+        }
+
+        let mut expected = ExpectedPaths::default();
+        #[rustfmt::skip]
+        expected.insert('a', &[
+            "A.a",
+            "a a",
+            "a a",
+            "aaa",
+        ]);
+
+        let req = &mk_route_request(&gv0, &gv1);
+        let mut ctx = Context::from_request(req);
+        let paths = ctx.route().unwrap();
+
+        check_paths(&gv0, &paths, &expected);
+    }
+
+    #[test]
+    fn test_hex_route_moves_every_electrode() {
+        use crate::grid::Topology;
+
+        // the droplet covers an even and an odd row, and ends up an odd
+        // number of rows down
+        #[rustfmt::skip]
+        let mut gv0 = parse_gridview(&[
+            "aa....",
+            "aa....",
+            "......",
+            "......",
+            "......",
+        ]);
+
+        #[rustfmt::skip]
+        let mut gv1 = parse_gridview(&[
+            "......",
+            "......",
+            "......",
+            "...aa.",
+            "...aa.",
+        ]);
+
+        gv0.grid.topology = Topology::Hex;
+        gv1.grid.topology = Topology::Hex;
+
+        let req = &mk_route_request(&gv0, &gv1);
+        let mut ctx = Context::from_request(req);
+        let paths = ctx.route().unwrap();
+
+        let footprint = &gv0.droplets[&c2id('a')].footprint;
+        let path = &paths[&c2id('a')];
+        assert_eq!(path.last(), Some(&Location { y: 3, x: 3 }));
+        for step in path.windows(2) {
+            let offset = step[1] - step[0];
+            for loc in footprint.locations(step[0]) {
+                let adjacent = Topology::Hex.adjacent(loc);
+                assert!(
+                    offset == Location { y: 0, x: 0 } || adjacent.contains(&(loc + offset)),
+                    "{} doesn't step to a neighbor going from {} to {}",
+                    loc,
+                    step[0],
+                    step[1]
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_impossible_route_fail() {
         let gv0 = parse_gridview(&["a.. ..."]);
@@ -1,3 +1,5 @@
+use std::collections::VecDeque;
+
 use petgraph::{
     algo::toposort,
     prelude::*,
@@ -6,7 +8,8 @@ use petgraph::{
 
 use crate::grid::DropletId;
 use crate::plan::graph::{CmdIndex, Graph};
-use indexmap::IndexMap;
+use crate::process::ProcessId;
+use indexmap::{IndexMap, IndexSet};
 
 type Schedule = usize;
 
@@ -16,7 +19,7 @@ pub struct Scheduler {
     current_sched: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SchedError {
     NothingToSchedule,
 }
@@ -24,6 +27,16 @@ pub enum SchedError {
 pub struct SchedRequest<'a> {
     pub graph: &'a Graph,
     pub limit: Option<usize>,
+    /// Only schedule these commands, or everything if this is None.
+    pub wanted: Option<IndexSet<CmdIndex>>,
+    /// How many more electrodes each process may take up. Processes that
+    /// aren't in here can take up as many as they like.
+    pub budgets: IndexMap<ProcessId, isize>,
+    /// How many electrodes running each command would take up, or free if
+    /// it's negative. Commands that aren't in here are free.
+    pub costs: IndexMap<CmdIndex, isize>,
+    /// Processes that shouldn't have anything scheduled.
+    pub stopped: IndexSet<ProcessId>,
 }
 
 #[derive(Debug)]
@@ -152,6 +165,15 @@ impl Scheduler {
             .filter(|&(node, _crit)| !self.node_sched.contains_key(node))
             // ignore nodes the "unbound" nodes
             .filter(|&(&node, _crit)| req.graph.graph[node].is_some() && self.is_ready(req, node))
+            // and leave out anything the caller doesn't need yet
+            .filter(|&(node, _crit)| match &req.wanted {
+                Some(wanted) => wanted.contains(node),
+                None => true,
+            })
+            .filter(|&(&node, _crit)| match command_process(req.graph, node) {
+                Some(pid) => !req.stopped.contains(&pid),
+                None => true,
+            })
             .collect();
 
         // we want to do the nodes first the reduce the number of droplets
@@ -163,6 +185,34 @@ impl Scheduler {
             (out_degree - in_degree, neg_crit)
         });
 
+        // take turns between processes, so one with a lot to do can't starve
+        // the others, and rotate who goes first each time
+        let todos = interleave(todos, self.current_sched, |&(&node, _crit)| {
+            command_process(req.graph, node)
+        });
+
+        // leave out anything that would put a process over its budget
+        let mut budgets = req.budgets.clone();
+        let mut todos: Vec<_> = todos
+            .into_iter()
+            .filter(|&(node, _crit)| {
+                let budget = command_process(req.graph, *node).and_then(|p| budgets.get_mut(&p));
+                let budget = match budget {
+                    Some(budget) => budget,
+                    None => return true,
+                };
+                // only count what gets taken up, nothing is freed until the
+                // command actually runs
+                let cost = req.costs.get(node).cloned().unwrap_or(0).max(0);
+                if cost > *budget {
+                    debug!("Not scheduling {:?}, it's over budget", node);
+                    return false;
+                }
+                *budget -= cost;
+                true
+            })
+            .collect();
+
         if todos.is_empty() {
             return Err(SchedError::NothingToSchedule);
         }
@@ -186,6 +236,74 @@ impl Scheduler {
         }
         self.current_sched += 1;
     }
+
+    pub fn is_scheduled(&self, cmd: CmdIndex) -> bool {
+        self.node_sched.contains_key(&cmd)
+    }
+}
+
+/// The process a command belongs to, going by the droplets it touches.
+pub fn command_process(graph: &Graph, cmd: CmdIndex) -> Option<ProcessId> {
+    let g = &graph.graph;
+    g.edges_directed(cmd, Incoming)
+        .chain(g.edges_directed(cmd, Outgoing))
+        .map(|e| e.weight().process_id)
+        .next()
+}
+
+/// Round robins through the items of each process, keeping their order
+/// within a process. Processes go in order of id, starting at the `start`th
+/// one (wrapping around).
+fn interleave<T>(items: Vec<T>, start: usize, key: impl Fn(&T) -> Option<ProcessId>) -> Vec<T> {
+    let mut groups: IndexMap<Option<ProcessId>, VecDeque<T>> = IndexMap::new();
+    for item in items {
+        groups.entry(key(&item)).or_default().push_back(item);
+    }
+    groups.sort_keys();
+
+    let mut queues: Vec<_> = groups.into_iter().map(|(_, q)| q).collect();
+    if !queues.is_empty() {
+        let n = queues.len();
+        queues.rotate_left(start % n);
+    }
+
+    let mut interleaved = Vec::new();
+    while !queues.is_empty() {
+        for q in &mut queues {
+            interleaved.extend(q.pop_front());
+        }
+        queues.retain(|q| !q.is_empty());
+    }
+    interleaved
+}
+
+/// Finds the commands that have to run to settle the given droplets: the
+/// command that makes each one, the command that uses it (if there is one
+/// yet), and everything those depend on.
+pub fn needed_commands(graph: &Graph, droplets: &[DropletId]) -> IndexSet<CmdIndex> {
+    let g = &graph.graph;
+    let mut needed = IndexSet::new();
+    let mut todo = Vec::new();
+
+    for id in droplets {
+        let edge = match graph.droplet_idx.get(id) {
+            Some(&edge) => edge,
+            None => continue,
+        };
+        let (src, tgt) = g.edge_endpoints(edge).expect("droplet edge not in graph");
+        todo.push(src);
+        if g[tgt].is_some() {
+            todo.push(tgt);
+        }
+    }
+
+    while let Some(node) = todo.pop() {
+        if needed.insert(node) {
+            todo.extend(g.neighbors_directed(node, Incoming));
+        }
+    }
+
+    needed
 }
 
 fn critical_paths(graph: &Graph) -> IndexMap<CmdIndex, usize> {
@@ -240,6 +358,10 @@ mod tests {
         let req = SchedRequest {
             graph: &graph,
             limit: None,
+            wanted: None,
+            budgets: IndexMap::new(),
+            costs: IndexMap::new(),
+            stopped: IndexSet::new(),
         };
 
         let mut sched = Scheduler::default();
@@ -257,6 +379,10 @@ mod tests {
         let req = SchedRequest {
             graph: &graph,
             limit: None,
+            wanted: None,
+            budgets: IndexMap::new(),
+            costs: IndexMap::new(),
+            stopped: IndexSet::new(),
         };
 
         let mut sched = Scheduler::default();
@@ -305,6 +431,76 @@ mod tests {
         assert_eq!(crit[&map["input"]], 6);
     }
 
+    #[test]
+    fn test_needed_commands() {
+        let (graph, map) = long_graph();
+        let needed = |ids: &[usize]| {
+            let ids: Vec<DropletId> = ids.iter().map(|&id| id.into()).collect();
+            let mut names: Vec<_> = needed_commands(&graph, &ids)
+                .iter()
+                .map(|cmd| map.iter().find(|(_, c)| *c == cmd).unwrap().0)
+                .cloned()
+                .collect();
+            names.sort();
+            names
+        };
+
+        // droplets that are used need their users to run too
+        assert_eq!(needed(&[2]), vec!["input", "short", "split"]);
+        assert_eq!(needed(&[10]), vec!["input", "pass1", "pass2", "split"]);
+        assert_eq!(needed(&[20]).len(), map.len());
+        assert_eq!(needed(&[3]).len(), map.len());
+        assert_eq!(needed(&[]), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_fair_schedule() {
+        // process 1 has a lot more to do than process 2
+        let mut graph = Graph::default();
+        let mut cmds = IndexMap::new();
+        for (pid, id) in &[(1, 0), (1, 1), (1, 2), (2, 0), (2, 1)] {
+            let cmd = Dummy::new(&[], &[*id]).process(*pid).boxed();
+            cmds.insert((*pid, *id), graph.add_command(cmd).unwrap());
+        }
+        let mut req = SchedRequest {
+            graph: &graph,
+            limit: Some(2),
+            wanted: None,
+            budgets: IndexMap::new(),
+            costs: IndexMap::new(),
+            stopped: IndexSet::new(),
+        };
+
+        let mut sched = Scheduler::default();
+        let pids = |resp: &SchedResponse| -> Vec<ProcessId> {
+            let pids = resp.commands_to_run.iter();
+            pids.map(|&c| command_process(&graph, c).unwrap()).collect()
+        };
+
+        // both get a turn, even though process 1 has more
+        let resp = sched.schedule(&req).unwrap();
+        assert_eq!(pids(&resp), vec![1, 2]);
+        sched.commit(&resp);
+
+        // and process 2 goes first next time
+        let resp = sched.schedule(&req).unwrap();
+        assert_eq!(pids(&resp), vec![2, 1]);
+
+        // budgets hold back commands that cost too much
+        req.limit = None;
+        req.budgets.insert(1, 5);
+        for &cmd in cmds.values() {
+            req.costs.insert(cmd, 4);
+        }
+        let resp = sched.schedule(&req).unwrap();
+        assert_eq!(pids(&resp), vec![2, 1]);
+
+        // and stopped processes don't get scheduled at all
+        req.stopped.insert(2);
+        let resp = sched.schedule(&req).unwrap();
+        assert_eq!(pids(&resp), vec![1]);
+    }
+
     #[test]
     fn test_storing_droplets() {
         let (graph, map) = long_graph();
@@ -320,6 +516,10 @@ mod tests {
         let req = SchedRequest {
             graph: &graph,
             limit: None,
+            wanted: None,
+            budgets: IndexMap::new(),
+            costs: IndexMap::new(),
+            stopped: IndexSet::new(),
         };
         let mut resp = SchedResponse {
             commands_to_run: vec![map["pass2"]],
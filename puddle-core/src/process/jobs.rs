@@ -0,0 +1,212 @@
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::PuddleResult;
+
+pub type JobId = usize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Done { result: Value },
+    Failed { error: String },
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        *self != JobStatus::Running
+    }
+}
+
+/// Sent to everyone watching when a job finishes.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobFinished {
+    pub job: JobId,
+    pub status: JobStatus,
+    /// Who started the job, so watchers can keep to their own.
+    #[serde(skip)]
+    pub owner: Option<String>,
+}
+
+/// How long a finished job sticks around for someone to check on it.
+pub const JOB_TTL: Duration = Duration::from_secs(10 * 60);
+
+struct Job {
+    status: JobStatus,
+    finished: Option<Instant>,
+    owner: Option<String>,
+}
+
+/// Long running work, like flushes, done on another thread so the caller
+/// can check back later instead of waiting. Finished jobs are forgotten
+/// once they've been done for a while, see `JOB_TTL`.
+pub struct Jobs {
+    next_id: AtomicUsize,
+    statuses: Mutex<IndexMap<JobId, Job>>,
+    changed: Condvar,
+    watchers: Mutex<Vec<Sender<JobFinished>>>,
+    ttl: Duration,
+}
+
+impl Default for Jobs {
+    fn default() -> Jobs {
+        Jobs::with_ttl(JOB_TTL)
+    }
+}
+
+impl Jobs {
+    pub fn with_ttl(ttl: Duration) -> Jobs {
+        Jobs {
+            next_id: AtomicUsize::new(0),
+            statuses: Mutex::default(),
+            changed: Condvar::new(),
+            watchers: Mutex::default(),
+            ttl,
+        }
+    }
+
+    /// Starts `work` on its own thread, returning right away. The job
+    /// belongs to `owner`, if anyone.
+    pub fn spawn<T, F>(self: &Arc<Self>, owner: Option<String>, work: F) -> JobId
+    where
+        T: Serialize,
+        F: FnOnce() -> PuddleResult<T> + Send + 'static,
+    {
+        let job = self.next_id.fetch_add(1, Relaxed);
+        {
+            let mut statuses = self.statuses.lock().unwrap();
+            self.prune(&mut statuses);
+            let status = JobStatus::Running;
+            statuses.insert(
+                job,
+                Job {
+                    status,
+                    finished: None,
+                    owner,
+                },
+            );
+        }
+
+        let jobs = Arc::clone(self);
+        thread::spawn(move || {
+            let status = match work() {
+                Ok(result) => match serde_json::to_value(result) {
+                    Ok(result) => JobStatus::Done { result },
+                    Err(err) => JobStatus::Failed {
+                        error: err.to_string(),
+                    },
+                },
+                Err(err) => JobStatus::Failed {
+                    error: err.to_string(),
+                },
+            };
+            jobs.finish(job, status);
+        });
+
+        debug!("Started job {}", job);
+        job
+    }
+
+    fn finish(&self, job: JobId, status: JobStatus) {
+        info!("Job {} finished: {:?}", job, status);
+        let owner = {
+            let mut statuses = self.statuses.lock().unwrap();
+            self.prune(&mut statuses);
+            // running jobs are never pruned, so it's still there
+            let entry = statuses.get_mut(&job).expect("Running job went missing");
+            entry.status = status.clone();
+            entry.finished = Some(Instant::now());
+            entry.owner.clone()
+        };
+        self.changed.notify_all();
+
+        let finished = JobFinished { job, status, owner };
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.retain(|tx| tx.send(finished.clone()).is_ok());
+    }
+
+    /// Forgets the jobs that have been done for longer than the TTL.
+    fn prune(&self, statuses: &mut IndexMap<JobId, Job>) {
+        let ttl = self.ttl;
+        statuses.retain(|_, job| match job.finished {
+            Some(finished) => finished.elapsed() < ttl,
+            None => true,
+        });
+    }
+
+    /// Who the job belongs to, or None if there's no such job. The inner
+    /// None is a job that belongs to no one in particular.
+    pub fn owner(&self, job: JobId) -> Option<Option<String>> {
+        let mut statuses = self.statuses.lock().unwrap();
+        self.prune(&mut statuses);
+        statuses.get(&job).map(|job| job.owner.clone())
+    }
+
+    pub fn status(&self, job: JobId) -> Option<JobStatus> {
+        let mut statuses = self.statuses.lock().unwrap();
+        self.prune(&mut statuses);
+        statuses.get(&job).map(|job| job.status.clone())
+    }
+
+    /// Waits for the job to finish, but no longer than `timeout`. Returns
+    /// whatever the status is by then.
+    pub fn wait(&self, job: JobId, timeout: Duration) -> Option<JobStatus> {
+        let deadline = Instant::now() + timeout;
+        let mut statuses = self.statuses.lock().unwrap();
+        self.prune(&mut statuses);
+        loop {
+            let status = &statuses.get(&job)?.status;
+            let now = Instant::now();
+            if status.is_finished() || now >= deadline {
+                return Some(status.clone());
+            }
+            statuses = self
+                .changed
+                .wait_timeout(statuses, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Hears about every job that finishes from here on.
+    pub fn subscribe(&self) -> Receiver<JobFinished> {
+        let (tx, rx) = channel();
+        self.watchers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finished_jobs_expire() {
+        let jobs = Arc::new(Jobs::with_ttl(Duration::from_millis(50)));
+        let job = jobs.spawn(None, || Ok(1));
+        let status = jobs.wait(job, Duration::from_secs(10)).unwrap();
+        assert!(status.is_finished());
+
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(jobs.status(job), None);
+
+        // running jobs never expire
+        let (tx, rx) = channel::<()>();
+        let slow = jobs.spawn(None, move || Ok(rx.recv().is_ok()));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(jobs.status(slow), Some(JobStatus::Running));
+        tx.send(()).unwrap();
+        assert!(jobs
+            .wait(slow, Duration::from_secs(10))
+            .unwrap()
+            .is_finished());
+    }
+}
@@ -1,9 +1,27 @@
+use std::convert::TryFrom;
 use std::ops::{Deref, DerefMut, Drop};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::grid::{DropletInfo, Grid};
-use crate::process::{Process, ProcessId, PuddleError, PuddleResult};
-use crate::system::System;
+use crate::checkpoint::{load_checkpoint, Checkpoint, RestoreError};
+use crate::eventlog::EventLog;
+use crate::exec::{Controls, LiveStep, RunState, Snapshot, Watchers};
+use crate::grid::{
+    parse::{LocatedPeripheral, ParsedGrid},
+    DropletInfo, ElectrodeHealth, Grid,
+};
+use crate::hardware::{Hardware, SharedHardware};
+use crate::metrics::Metrics;
+use crate::plan::analyze::Analysis;
+use crate::process::{
+    flush_process, JobFinished, JobId, JobStatus, Jobs, Process, ProcessId, PuddleError,
+    PuddleResult,
+};
+use crate::record::{recorder_from_env, Recorder};
+use crate::status::{Activity, Backend, Status};
+use crate::system::{unpaused, ProcessTable, Stats, System};
 
 use indexmap::IndexMap;
 
@@ -42,25 +60,244 @@ impl<'a> DerefMut for ProcessHandle<'a> {
 #[allow(dead_code)]
 pub struct Manager {
     system: Arc<Mutex<System>>,
+    controls: Arc<Controls>,
+    watchers: Arc<Watchers>,
+    metrics: Arc<Metrics>,
+    jobs: Arc<Jobs>,
     processes: Mutex<IndexMap<ProcessId, Process>>,
+    /// Every live process, and who it belongs to if anyone. Kept apart
+    /// from `processes` so it can be checked while a process is in use.
+    table: Arc<ProcessTable>,
+    /// The real board, if there is one, so its status can be checked
+    /// without the system lock.
+    hardware: Mutex<Option<SharedHardware>>,
     blocking: bool,
 }
 
 impl Manager {
+    /// Makes a manager for the grid, picking up from the checkpoint file if
+    /// there is one. See `checkpoint`.
     pub fn new(blocking: bool, grid: Grid) -> Manager {
-        let system = Arc::new(Mutex::new(System::new(grid)));
+        if let Some(checkpoint) = load_checkpoint() {
+            match Manager::restore(blocking, grid.clone(), checkpoint) {
+                Ok(manager) => return manager,
+                Err(err) => error!("Failed to restore checkpoint, starting fresh. {}", err),
+            }
+        }
+        Manager::with_system(blocking, System::new(grid))
+    }
+
+    /// Makes a manager for simulating away from the board. It starts with
+    /// fresh wear, and doesn't load or save the health or checkpoint files.
+    pub fn offline(blocking: bool, grid: Grid) -> Manager {
+        let system = System::offline(grid, ElectrodeHealth::default());
+        Manager::with_system(blocking, system)
+    }
+
+    /// Makes a manager that picks up where a checkpoint left off, processes
+    /// and all.
+    pub fn restore(
+        blocking: bool,
+        grid: Grid,
+        checkpoint: Checkpoint,
+    ) -> Result<Manager, RestoreError> {
+        let mut system = System::new(grid);
+        system.restore(checkpoint)?;
+        let saved = system.saved_processes();
+
+        let manager = Manager::with_system(blocking, system);
+        for (pid, saved) in saved {
+            info!("Restored process {} '{}'", pid, saved.name);
+            let system = Arc::clone(&manager.system);
+            manager.put_process(Process::restore(pid, saved, system));
+        }
+        Ok(manager)
+    }
+
+    fn with_system(blocking: bool, mut system: System) -> Manager {
+        if let Some(recorder) = recorder_from_env() {
+            system.record(recorder);
+        }
+        let controls = system.controls();
+        let watchers = system.watchers();
+        let metrics = system.metrics();
+        let table = system.processes();
+        let system = Arc::new(Mutex::new(system));
 
         Manager {
             system,
+            controls,
+            watchers,
+            metrics,
+            jobs: Arc::default(),
             blocking,
             processes: Mutex::new(IndexMap::default()),
+            table,
+            hardware: Mutex::new(None),
         }
     }
 
+    /// Records everything from here on to a file, which can be checked
+    /// later with `record::replay`.
+    pub fn record(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let recorder = Recorder::create(path)?;
+        self.system.lock().unwrap().record(recorder);
+        Ok(())
+    }
+
+    /// Writes the executor's events to a file from here on, see `eventlog`.
+    pub fn log_events(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let log = EventLog::create(path)?;
+        self.system.lock().unwrap().log_events(log);
+        Ok(())
+    }
+
+    pub fn get_grid(&self) -> ParsedGrid {
+        ParsedGrid::from(self.system.lock().unwrap().grid().clone())
+    }
+
+    pub fn list_peripherals(&self) -> Vec<LocatedPeripheral> {
+        self.get_grid().peripherals
+    }
+
+    /// Swaps in a new grid, after checking it. This only works when nothing
+    /// is left to run, see `System::set_grid`.
+    pub fn load_grid(&self, grid: ParsedGrid) -> PuddleResult<()> {
+        let grid = Grid::try_from(grid).map_err(PuddleError::InvalidGrid)?;
+        self.system.lock().unwrap().set_grid(grid)
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.system.lock().unwrap().checkpoint()
+    }
+
+    /// Pauses execution and saves the electrode health and a checkpoint,
+    /// for exiting without dropping the manager. A flush that's running
+    /// stops at the next tick, and picks up from the checkpoint next time.
+    pub fn shut_down(&self) {
+        self.pause();
+        self.system.lock().unwrap().save()
+    }
+
+    /// Drives real hardware along with the simulation from here on. Fails
+    /// if the grid has pins the hardware can't drive, see
+    /// `Grid::check_pins`; grids loaded later get the same check.
+    pub fn set_hardware(&self, hardware: Box<dyn Hardware>) -> PuddleResult<()> {
+        let hardware = Arc::new(Mutex::new(hardware));
+        self.system
+            .lock()
+            .unwrap()
+            .set_hardware(Arc::clone(&hardware))?;
+        *self.hardware.lock().unwrap() = Some(hardware);
+        Ok(())
+    }
+
+    /// Holds on to every step from here on, for `get_logs`. Off by default,
+    /// see `Executor::keep_steps`.
+    pub fn keep_steps(&self) {
+        self.system.lock().unwrap().keep_steps()
+    }
+
     pub fn get_logs(&self) -> Vec<crate::exec::StepInfo> {
         self.system.lock().unwrap().get_logs().to_vec()
     }
 
+    pub fn stats(&self) -> Stats {
+        self.system.lock().unwrap().stats()
+    }
+
+    pub fn analyze(&self) -> Analysis {
+        self.system.lock().unwrap().analyze()
+    }
+
+    /// Pauses execution before the next tick, even in the middle of a
+    /// flush. Flushes wait until it's resumed, but they let go of the
+    /// system in the meantime, so everything else keeps working.
+    pub fn pause(&self) {
+        self.controls.pause()
+    }
+
+    pub fn resume(&self) {
+        self.controls.resume()
+    }
+
+    /// Runs `n` more ticks, then pauses again.
+    pub fn step(&self, n: usize) {
+        self.controls.step(n)
+    }
+
+    pub fn run_state(&self) -> RunState {
+        self.controls.state()
+    }
+
+    /// Starts flushing the process in the background. The job's result is
+    /// the process's droplets, like `flush` returns.
+    pub fn flush_async(&self, pid: ProcessId) -> PuddleResult<JobId> {
+        if !self.processes.lock().unwrap().contains_key(&pid) {
+            return Err(PuddleError::NonExistentProcess(pid));
+        }
+        let system = Arc::clone(&self.system);
+        let owner = self.table.get(pid).and_then(|p| p.owner);
+        let job = self.jobs.spawn(owner, move || flush_process(&system, pid));
+        Ok(job)
+    }
+
+    pub fn job_status(&self, job: JobId) -> PuddleResult<JobStatus> {
+        self.jobs
+            .status(job)
+            .ok_or(PuddleError::NonExistentJob(job))
+    }
+
+    /// Waits up to `timeout` for the job to finish.
+    pub fn wait(&self, job: JobId, timeout: Duration) -> PuddleResult<JobStatus> {
+        self.jobs
+            .wait(job, timeout)
+            .ok_or(PuddleError::NonExistentJob(job))
+    }
+
+    /// Hears about every job that finishes from here on.
+    pub fn subscribe_jobs(&self) -> Receiver<JobFinished> {
+        self.jobs.subscribe()
+    }
+
+    /// What the board looks like right now. Doesn't flush or wait for one.
+    pub fn snapshot(&self) -> Snapshot {
+        self.watchers.snapshot()
+    }
+
+    /// Counters for the whole run, see `metrics`. Doesn't wait for a flush.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// How the system is doing, without waiting for a flush. The hardware
+    /// is only there if it was set with `set_hardware`.
+    pub fn status(&self) -> Status {
+        let activity = if self.metrics.is_flushing() {
+            Activity::Flushing
+        } else {
+            Activity::Idle
+        };
+        let shared = self.hardware.lock().unwrap().clone();
+        let (backend, hardware) = match shared {
+            Some(hardware) => (Backend::Hardware, Some(hardware.lock().unwrap().status())),
+            None => (Backend::Sim, None),
+        };
+        Status {
+            backend,
+            hardware,
+            tick: self.metrics.ticks(),
+            activity,
+            run_state: self.run_state(),
+            last_error: self.metrics.last_error(),
+        }
+    }
+
+    /// Gets every step as it's committed, even in the middle of a flush.
+    pub fn subscribe(&self) -> Receiver<Arc<LiveStep>> {
+        self.watchers.subscribe()
+    }
+
     // pub fn gridview(&self) -> MutexGuard<GridView> {
     //     self.gridview.lock().unwrap()
     // }
@@ -73,7 +310,12 @@ impl Manager {
             .ok_or_else(|| PuddleError::NonExistentProcess(pid))
     }
 
+    /// Puts a process back once it's done being used, unless it was killed
+    /// in the meantime.
     fn put_process(&self, process: Process) {
+        if !self.table.contains(process.id()) {
+            return;
+        }
         let old = self.processes.lock().unwrap().insert(process.id(), process);
         assert!(old.is_none());
     }
@@ -87,23 +329,84 @@ impl Manager {
     }
 
     pub fn new_process<S>(&self, name: S) -> PuddleResult<ProcessId>
+    where
+        S: Into<String>,
+    {
+        self.new_owned_process(name, None)
+    }
+
+    /// Makes a process that only `owner` can use, if there is one. See
+    /// `check_owner`.
+    pub fn new_owned_process<S>(&self, name: S, owner: Option<String>) -> PuddleResult<ProcessId>
     where
         S: Into<String>,
     {
         let system = Arc::clone(&self.system);
-        let process = Process::new(name.into(), system);
+        let process = Process::with_owner(name.into(), owner, system, &self.table);
         let pid = process.id();
         let mut procs = self.processes.lock().unwrap();
         procs.insert(pid, process);
         Ok(pid)
     }
 
+    /// Fails if the job belongs to someone other than `user`, the same way
+    /// as if there were no such job, so jobs are only seen by their owners.
+    /// Jobs for processes without an owner are open to everyone.
+    pub fn check_job_owner(&self, job: JobId, user: &str) -> PuddleResult<()> {
+        match self.jobs.owner(job) {
+            Some(Some(owner)) if owner != user => Err(PuddleError::NonExistentJob(job)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails if the process belongs to someone other than `user`.
+    /// Processes made without an owner are open to everyone.
+    pub fn check_owner(&self, pid: ProcessId, user: &str) -> PuddleResult<()> {
+        match self.table.get(pid).and_then(|p| p.owner) {
+            Some(owner) if owner != user => Err(PuddleError::NotOwner(pid)),
+            _ => Ok(()),
+        }
+    }
+
     pub fn close_process(&self, pid: ProcessId) -> PuddleResult<()> {
         let p = self.take_process(pid)?;
         p.flush()?;
+        self.table.remove(pid);
         Ok(())
     }
 
+    /// Stops a process without finishing what it asked for, and throws its
+    /// droplets away. See `System::kill`. This works even if the process is
+    /// in use or in the middle of a flush; its commands are aborted at the
+    /// next tick. Throwing the droplets away waits for execution to be
+    /// resumed if it's paused. If they can't be thrown away, the process is
+    /// left around so this can be tried again.
+    pub fn kill_process(&self, pid: ProcessId) -> PuddleResult<()> {
+        if !self.table.contains(pid) {
+            return Err(PuddleError::NonExistentProcess(pid));
+        }
+        self.controls.kill(pid);
+        let result = unpaused(
+            &self.system,
+            |sys, resumed| {
+                if resumed {
+                    sys.resume_kill(pid)
+                } else {
+                    sys.kill(pid)
+                }
+            },
+            |_, result| result,
+        );
+        match result {
+            // the system already took it out of the table
+            Ok(()) => {
+                self.processes.lock().unwrap().swap_remove(&pid);
+            }
+            Err(_) => self.controls.forget_kill(pid),
+        }
+        result
+    }
+
     pub fn get_new_process<S>(&self, name: S) -> ProcessHandle
     where
         S: Into<String>,
@@ -112,12 +415,8 @@ impl Manager {
         self.get_process(pid).expect("get failed")
     }
 
+    /// Every droplet on the board, without flushing.
     pub fn visualizer_droplet_info(&self) -> PuddleResult<Vec<DropletInfo>> {
-        unimplemented!()
-        // // DONT FLUSH
-        // let endp = self.exec_endpoint.lock().unwrap();
-        // endp.send(()).unwrap();
-        // let info = endp.recv().unwrap();
-        // Ok(info)
+        Ok(self.snapshot().droplets)
     }
 }
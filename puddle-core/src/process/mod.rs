@@ -1,5 +1,9 @@
+mod jobs;
 mod manager;
+mod ops;
 mod process;
 
+pub use self::jobs::*;
 pub use self::manager::*;
+pub use self::ops::*;
 pub use self::process::*;
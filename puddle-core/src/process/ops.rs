@@ -0,0 +1,208 @@
+//! Every operation a process can do, written down as data. The RPC server
+//! and the JS bindings both go through this table, so they can't drift
+//! apart from `Process` or from each other.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::grid::{DropletId, Location};
+
+use super::{Process, PuddleResult};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    Create {
+        #[serde(default)]
+        location: Option<Location>,
+        volume: f64,
+        #[serde(default)]
+        dimensions: Option<Location>,
+    },
+    Input {
+        port: String,
+        volume: f64,
+        dimensions: Location,
+    },
+    Output {
+        port: String,
+        droplet: DropletId,
+    },
+    Move {
+        droplet: DropletId,
+        to: Location,
+    },
+    Mix {
+        droplets: (DropletId, DropletId),
+    },
+    Combine {
+        droplets: (DropletId, DropletId),
+    },
+    CombineInto {
+        droplets: (DropletId, DropletId),
+    },
+    Agitate {
+        droplet: DropletId,
+    },
+    Split {
+        droplet: DropletId,
+    },
+    Heat {
+        droplet: DropletId,
+        temperature: f32,
+        seconds: f64,
+    },
+    Sense {
+        droplet: DropletId,
+        sensor: String,
+    },
+    Flush,
+    SetQuota {
+        quota: Option<usize>,
+    },
+}
+
+/// The name of every operation, which is also its RPC method and its
+/// `op` tag.
+pub const OPERATIONS: &[&str] = &[
+    "create",
+    "input",
+    "output",
+    "move",
+    "mix",
+    "combine",
+    "combine_into",
+    "agitate",
+    "split",
+    "heat",
+    "sense",
+    "flush",
+    "set_quota",
+];
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        use self::Operation::*;
+        match self {
+            Create { .. } => "create",
+            Input { .. } => "input",
+            Output { .. } => "output",
+            Move { .. } => "move",
+            Mix { .. } => "mix",
+            Combine { .. } => "combine",
+            CombineInto { .. } => "combine_into",
+            Agitate { .. } => "agitate",
+            Split { .. } => "split",
+            Heat { .. } => "heat",
+            Sense { .. } => "sense",
+            Flush => "flush",
+            SetQuota { .. } => "set_quota",
+        }
+    }
+}
+
+fn to_value(x: impl Serialize) -> Value {
+    serde_json::to_value(x).expect("Failed to serialize result")
+}
+
+impl Process {
+    /// Does the operation, returning whatever the matching method would
+    /// have, as JSON.
+    pub fn apply(&self, op: Operation) -> PuddleResult<Value> {
+        use self::Operation::*;
+        debug!("Process {} applying {:?}", self.id(), op);
+        let result = match op {
+            Create {
+                location,
+                volume,
+                dimensions,
+            } => to_value(self.create(location, volume, dimensions)?),
+            Input {
+                port,
+                volume,
+                dimensions,
+            } => to_value(self.input(port, volume, dimensions)?),
+            Output { port, droplet } => {
+                self.output(port, droplet)?;
+                Value::Null
+            }
+            Move { droplet, to } => to_value(self.move_droplet(droplet, to)?),
+            Mix { droplets: (a, b) } => to_value(self.mix(a, b)?),
+            Combine { droplets: (a, b) } => to_value(self.combine(a, b)?),
+            CombineInto { droplets: (a, b) } => to_value(self.combine_into(a, b)?),
+            Agitate { droplet } => to_value(self.agitate(droplet)?),
+            Split { droplet } => to_value(self.split(droplet)?),
+            Heat {
+                droplet,
+                temperature,
+                seconds,
+            } => to_value(self.heat(droplet, temperature, seconds)?),
+            Sense { droplet, sensor } => to_value(self.sense(droplet, sensor)?),
+            Flush => to_value(self.flush()?),
+            SetQuota { quota } => {
+                self.set_quota(quota);
+                Value::Null
+            }
+        };
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::location::yx;
+
+    #[test]
+    fn test_operation_names() {
+        let d = DropletId {
+            id: 0,
+            process_id: 0,
+        };
+        let ops = vec![
+            Operation::Create {
+                location: None,
+                volume: 1.0,
+                dimensions: None,
+            },
+            Operation::Input {
+                port: "in".into(),
+                volume: 1.0,
+                dimensions: yx(1, 1),
+            },
+            Operation::Output {
+                port: "out".into(),
+                droplet: d,
+            },
+            Operation::Move {
+                droplet: d,
+                to: yx(1, 1),
+            },
+            Operation::Mix { droplets: (d, d) },
+            Operation::Combine { droplets: (d, d) },
+            Operation::CombineInto { droplets: (d, d) },
+            Operation::Agitate { droplet: d },
+            Operation::Split { droplet: d },
+            Operation::Heat {
+                droplet: d,
+                temperature: 50.0,
+                seconds: 1.0,
+            },
+            Operation::Sense {
+                droplet: d,
+                sensor: "sensor".into(),
+            },
+            Operation::Flush,
+            Operation::SetQuota { quota: None },
+        ];
+
+        let names: Vec<_> = ops.iter().map(Operation::name).collect();
+        assert_eq!(names, OPERATIONS);
+
+        // the tag is the name, so the table works both ways
+        for op in &ops {
+            let json = serde_json::to_value(op).unwrap();
+            assert_eq!(json["op"], op.name());
+        }
+    }
+}
@@ -3,21 +3,58 @@ use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::{Arc, Mutex};
 
+use indexmap::IndexMap;
+
 use crate::util::seconds_duration;
 
-use crate::grid::{DropletId, DropletInfo, Location};
-use crate::system::System;
+use crate::grid::{validate::InvalidGrid, DropletId, DropletInfo, Footprint, Location};
+use crate::system::{unpaused, ProcessTable, System};
 
 use crate::command;
-use crate::command::BoxedCommand;
+use crate::command::{BoxedCommand, SensorReading};
 
-use crate::plan::PlanError;
+use crate::checkpoint::SavedProcess;
+use crate::lang::{self, ErrorKind, LangError};
+use crate::plan::{analyze::Problem, graph::GraphError, PlanError};
+use crate::process::JobId;
+use crate::protocol::{CompiledProtocol, Protocol, ProtocolError};
 
 #[derive(Debug)]
 pub enum PuddleError {
     PlanError(PlanError),
     NonExistentDropletId(usize),
     NonExistentProcess(ProcessId),
+    /// The process was stopped because some of its commands couldn't be
+    /// planned.
+    ProcessFailed(ProcessId, String),
+    /// The process has commands that can't run without going over its
+    /// quota.
+    QuotaExceeded(ProcessId),
+    GraphError(GraphError),
+    NonExistentJob(JobId),
+    /// The grid can't be changed while anything is still running.
+    NotIdle,
+    InvalidGrid(InvalidGrid),
+    /// The droplet wouldn't be on the new grid.
+    DropletOffGrid(DropletId),
+    /// The process belongs to someone else.
+    NotOwner(ProcessId),
+    /// The footprint would fall apart on this grid's topology.
+    DisconnectedFootprint(Footprint),
+    /// Some of the process's commands didn't run, and it wasn't because
+    /// of a failure or a quota.
+    Unfinished(ProcessId),
+    /// The sense command that would make this droplet never ran.
+    NoReading(DropletId),
+    /// The droplet belongs to another process.
+    ForeignDroplet(DropletId),
+    /// The grid has no output to throw the process's droplets away in.
+    NoWastePort(ProcessId),
+    /// The commands can never run on this grid, no matter how they're
+    /// scheduled.
+    WontFit(Vec<Problem>),
+    /// The hardware is attached but can't be driven, so nothing runs.
+    HardwareUnavailable(String),
 }
 
 impl fmt::Display for PuddleError {
@@ -27,6 +64,24 @@ impl fmt::Display for PuddleError {
             PlanError(err) => write!(f, "Plan error {:#?}", err),
             NonExistentProcess(pid) => write!(f, "Process {} does not exist", pid),
             NonExistentDropletId(id) => write!(f, "Droplet {} does not exist", id),
+            ProcessFailed(pid, why) => write!(f, "Process {} failed: {}", pid, why),
+            QuotaExceeded(pid) => write!(f, "Process {} is over its quota", pid),
+            GraphError(err) => write!(f, "Graph error {:?}", err),
+            NonExistentJob(job) => write!(f, "Job {} does not exist", job),
+            NotIdle => write!(f, "Can't change the grid while commands are pending"),
+            InvalidGrid(err) => write!(f, "{}", err),
+            DropletOffGrid(id) => write!(f, "Droplet {:?} wouldn't be on the new grid", id),
+            NotOwner(pid) => write!(f, "Process {} belongs to another user", pid),
+            DisconnectedFootprint(fp) => write!(f, "Footprint {:?} isn't connected", fp),
+            Unfinished(pid) => write!(f, "Process {} has commands that didn't run", pid),
+            NoReading(id) => write!(f, "Sensor wasn't read for droplet {:?}", id),
+            ForeignDroplet(id) => write!(f, "Droplet {:?} belongs to another process", id),
+            NoWastePort(pid) => write!(f, "No output to dispose of process {}'s droplets", pid),
+            WontFit(problems) => {
+                let problems: Vec<_> = problems.iter().map(|p| p.to_string()).collect();
+                write!(f, "Can't ever fit on the grid: {}", problems.join(", "))
+            }
+            HardwareUnavailable(err) => write!(f, "Hardware isn't ready: {}", err),
         }
     }
 }
@@ -41,28 +96,81 @@ pub struct Process {
     id: ProcessId,
     #[allow(dead_code)]
     name: String,
+    owner: Option<String>,
     next_droplet_id: AtomicUsize,
     system: Arc<Mutex<System>>,
     // TODO we probably want something like this for more precise flushing
     // unresolved_droplet_ids: Mutex<Set<DropletId>>,
 }
 
+/// Runs everything the process has asked for and returns its droplets,
+/// only holding the system lock while execution isn't paused.
+pub(crate) fn flush_process(
+    system: &Mutex<System>,
+    pid: ProcessId,
+) -> PuddleResult<Vec<DropletInfo>> {
+    unpaused(
+        system,
+        |sys, resumed| sys.flush_process(pid, resumed),
+        |sys, result| result.and_then(|()| sys.flushed_process(pid)),
+    )
+}
+
 static NEXT_PROCESS_ID: AtomicUsize = AtomicUsize::new(0);
 
 impl Process {
     pub fn new(name: String, system: Arc<Mutex<System>>) -> Process {
+        let table = system.lock().unwrap().processes();
+        Process::with_owner(name, None, system, &table)
+    }
+
+    /// Makes a process that belongs to `owner`, see `Manager::check_owner`.
+    /// This only needs the process table, not the system lock, so it
+    /// doesn't wait on a flush.
+    pub fn with_owner(
+        name: String,
+        owner: Option<String>,
+        system: Arc<Mutex<System>>,
+        table: &ProcessTable,
+    ) -> Process {
+        let id = NEXT_PROCESS_ID.fetch_add(1, Relaxed);
+        table.add(id, name.clone(), owner.clone());
         Process {
-            id: NEXT_PROCESS_ID.fetch_add(1, Relaxed),
+            id,
             name: name,
+            owner,
             next_droplet_id: AtomicUsize::new(0),
             system,
         }
     }
 
+    /// Picks up a process from a checkpoint.
+    pub fn restore(id: ProcessId, saved: SavedProcess, system: Arc<Mutex<System>>) -> Process {
+        // make sure new processes don't reuse the id
+        let mut next = NEXT_PROCESS_ID.load(Relaxed);
+        while next <= id {
+            match NEXT_PROCESS_ID.compare_exchange(next, id + 1, Relaxed, Relaxed) {
+                Ok(_) => break,
+                Err(actual) => next = actual,
+            }
+        }
+        Process {
+            id,
+            name: saved.name,
+            owner: saved.owner,
+            next_droplet_id: AtomicUsize::new(saved.next_droplet_id),
+            system,
+        }
+    }
+
     pub fn id(&self) -> ProcessId {
         self.id
     }
 
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
     fn new_droplet_id(&self) -> DropletId {
         DropletId {
             id: self.next_droplet_id.fetch_add(1, Relaxed),
@@ -70,17 +178,64 @@ impl Process {
         }
     }
 
+    /// Fails if any of the droplets belong to another process, so one
+    /// process can't use up or move around another's droplets.
+    fn check_droplets(&self, droplets: &[DropletId]) -> PuddleResult<()> {
+        match droplets.iter().find(|d| d.process_id != self.id) {
+            Some(&d) => Err(PuddleError::ForeignDroplet(d)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks every command before any of them are added, returning the
+    /// index of the first one that uses someone else's droplet.
+    fn check_commands(&self, cmds: &[BoxedCommand]) -> Result<(), (usize, PuddleError)> {
+        for (i, cmd) in cmds.iter().enumerate() {
+            self.check_droplets(&cmd.input_droplets())
+                .map_err(|err| (i, err))?;
+        }
+        Ok(())
+    }
+
     fn plan(&self, cmd: BoxedCommand) -> PuddleResult<()> {
+        self.check_droplets(&cmd.input_droplets())?;
         let mut sys = self.system.lock().unwrap();
         sys.add(cmd)
     }
 }
 
 impl Process {
+    /// Runs everything this process has asked for, but not necessarily
+    /// what other processes have.
     pub fn flush(&self) -> PuddleResult<Vec<DropletInfo>> {
-        let mut sys = self.system.lock().unwrap();
-        sys.flush(&[]).unwrap();
-        Ok(sys.info(Some(self.id)))
+        flush_process(&self.system, self.id)
+    }
+
+    /// Like `flush`, but only runs things until the given droplets exist.
+    pub fn flush_droplets(&self, droplets: &[DropletId]) -> PuddleResult<Vec<DropletInfo>> {
+        self.check_droplets(droplets)?;
+        let pid = self.id;
+        unpaused(
+            &self.system,
+            |sys, resumed| {
+                if resumed {
+                    sys.resume_flush(droplets)
+                } else {
+                    sys.flush(droplets)
+                }
+            },
+            |sys, result| {
+                result?;
+                sys.check_flushed(droplets)?;
+                Ok(sys.info(Some(pid)))
+            },
+        )
+    }
+
+    /// Limits how many electrodes this process's droplets can take up at
+    /// once. See `System::set_quota`.
+    pub fn set_quota(&self, quota: Option<usize>) {
+        self.system.lock().unwrap().set_quota(self.id, quota)
     }
 
     pub fn create(
@@ -95,6 +250,25 @@ impl Process {
         Ok(output)
     }
 
+    pub fn create_with_footprint(
+        &self,
+        loc: Option<Location>,
+        vol: f64,
+        footprint: Footprint,
+    ) -> PuddleResult<DropletId> {
+        let connected = {
+            let sys = self.system.lock().unwrap();
+            sys.grid().footprint_is_connected(&footprint, loc)
+        };
+        if !connected {
+            return Err(PuddleError::DisconnectedFootprint(footprint));
+        }
+        let output = self.new_droplet_id();
+        let create_cmd = command::Create::with_footprint(loc, vol, footprint, output)?;
+        self.plan(Box::new(create_cmd))?;
+        Ok(output)
+    }
+
     pub fn input(
         &self,
         name: impl Into<String>,
@@ -121,15 +295,23 @@ impl Process {
     }
 
     pub fn mix(&self, d1: DropletId, d2: DropletId) -> PuddleResult<DropletId> {
-        let combine_out = self.new_droplet_id();
-        let combine_cmd = command::Combine::new(d1, d2, combine_out)?;
+        let combined = self.combine(d1, d2)?;
+        self.agitate(combined)
+    }
+
+    /// Puts two droplets together without mixing them.
+    pub fn combine(&self, d1: DropletId, d2: DropletId) -> PuddleResult<DropletId> {
+        let output = self.new_droplet_id();
+        let combine_cmd = command::Combine::new(d1, d2, output)?;
         self.plan(Box::new(combine_cmd))?;
+        Ok(output)
+    }
 
-        let agitate_out = self.new_droplet_id();
-        let agitate_cmd = command::Agitate::new(combine_out, agitate_out)?;
+    pub fn agitate(&self, d: DropletId) -> PuddleResult<DropletId> {
+        let output = self.new_droplet_id();
+        let agitate_cmd = command::Agitate::new(d, output)?;
         self.plan(Box::new(agitate_cmd))?;
-
-        Ok(agitate_out)
+        Ok(output)
     }
 
     pub fn combine_into(&self, d1: DropletId, d2: DropletId) -> PuddleResult<DropletId> {
@@ -155,6 +337,89 @@ impl Process {
         Ok(out)
     }
 
+    /// Compiles a protocol and adds all of its commands to this process,
+    /// returning the droplets it leaves behind by name.
+    pub fn load(
+        &self,
+        protocol: &Protocol,
+        params: &IndexMap<String, f64>,
+    ) -> Result<IndexMap<String, DropletId>, ProtocolError> {
+        let compiled = protocol.compile(params, &mut || self.new_droplet_id())?;
+        self.plan_all(compiled)
+    }
+
+    /// Adds a whole protocol at once, where it can use existing droplets by
+    /// the names in `droplets`. Either all of it is added or none of it is.
+    /// Returns the droplets left at the end, by name.
+    pub fn submit(
+        &self,
+        protocol: &Protocol,
+        droplets: IndexMap<String, DropletId>,
+    ) -> Result<IndexMap<String, DropletId>, ProtocolError> {
+        for (name, &id) in &droplets {
+            if id.process_id != self.id {
+                let name = name.clone();
+                return Err(ProtocolError::ForeignDroplet { name, id });
+            }
+        }
+        let params = IndexMap::new();
+        let compiled = protocol.compile_with(&params, droplets, &mut || self.new_droplet_id())?;
+        self.plan_all(compiled)
+    }
+
+    fn plan_all(
+        &self,
+        compiled: CompiledProtocol,
+    ) -> Result<IndexMap<String, DropletId>, ProtocolError> {
+        let (steps, cmds): (Vec<_>, Vec<_>) = compiled.commands.into_iter().unzip();
+        self.check_commands(&cmds)
+            .and_then(|()| self.system.lock().unwrap().add_all(cmds))
+            .map_err(|(i, err)| ProtocolError::PuddleError {
+                step: steps[i],
+                err,
+            })?;
+        Ok(compiled.droplets)
+    }
+
+    /// Compiles a program in the protocol language and adds all of its
+    /// commands to this process, returning the droplets it leaves behind.
+    pub fn load_program(
+        &self,
+        src: &str,
+        params: &IndexMap<String, f64>,
+    ) -> Result<IndexMap<String, DropletId>, LangError> {
+        let compiled = lang::compile(src, params, &mut || self.new_droplet_id())?;
+        let (lines, cmds): (Vec<_>, Vec<_>) = compiled.commands.into_iter().unzip();
+        self.check_commands(&cmds)
+            .and_then(|()| self.system.lock().unwrap().add_all(cmds))
+            .map_err(|(i, err)| LangError::new(lines[i], ErrorKind::PuddleError(err)))?;
+        Ok(compiled.droplets)
+    }
+
+    /// Reads a sensor under the droplet, waiting for the droplet to get
+    /// there. The droplet isn't changed, but it gets a new id.
+    ///
+    /// The simulator has no sensors, so its readings are just the droplet's
+    /// volume, marked as `simulated`.
+    ///
+    /// The reading isn't checkpointed, so it's lost if the system is
+    /// restored before the sense runs. See `checkpoint`.
+    pub fn sense(
+        &self,
+        d: DropletId,
+        sensor: impl Into<String>,
+    ) -> PuddleResult<(DropletId, SensorReading)> {
+        let out = self.new_droplet_id();
+        let sense_cmd = command::Sense::new(sensor.into(), d, out)?;
+        let reading = sense_cmd.reading();
+        self.plan(Box::new(sense_cmd))?;
+
+        self.flush_droplets(&[out])?;
+        let reading = *reading.lock().unwrap();
+        let reading = reading.ok_or(PuddleError::NoReading(out))?;
+        Ok((out, reading))
+    }
+
     pub fn ticks(&self) -> usize {
         self.system.lock().unwrap().ticks()
     }
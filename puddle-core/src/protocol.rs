@@ -0,0 +1,366 @@
+use std::fmt;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::command::{self, BoxedCommand};
+use crate::grid::{DropletId, Location};
+use crate::process::PuddleError;
+use crate::util::seconds_duration;
+
+/// A protocol written down as data, so it can be stored in a file and
+/// submitted all at once instead of written as a program against the API.
+///
+/// Droplets are referred to by name. Each step consumes the droplets it
+/// names and binds its outputs to new names, which later steps can use.
+/// Numbers can either be written literally, or as `$name` to refer to one of
+/// the protocol's parameters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Protocol {
+    #[serde(default)]
+    pub name: String,
+    /// Parameters and their default values.
+    #[serde(default)]
+    pub params: IndexMap<String, f64>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Number {
+    Literal(f64),
+    Param(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Step {
+    Create {
+        out: String,
+        #[serde(default)]
+        location: Option<Location>,
+        volume: Number,
+        #[serde(default)]
+        dimensions: Option<Location>,
+    },
+    Input {
+        out: String,
+        port: String,
+        volume: Number,
+        dimensions: Location,
+    },
+    Output {
+        droplet: String,
+        port: String,
+    },
+    Move {
+        droplet: String,
+        to: Location,
+        out: String,
+    },
+    Mix {
+        droplets: (String, String),
+        out: String,
+    },
+    Split {
+        droplet: String,
+        out: (String, String),
+    },
+    Heat {
+        droplet: String,
+        temperature: Number,
+        seconds: Number,
+        out: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    UnboundDroplet {
+        step: usize,
+        name: String,
+    },
+    AlreadyBound {
+        step: usize,
+        name: String,
+    },
+    UnknownParam {
+        step: usize,
+        name: String,
+    },
+    PuddleError {
+        step: usize,
+        err: PuddleError,
+    },
+    /// One of the droplets handed to the protocol belongs to another
+    /// process.
+    ForeignDroplet {
+        name: String,
+        id: DropletId,
+    },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::ProtocolError::*;
+        match self {
+            UnboundDroplet { step, name } => {
+                write!(f, "Step {}: no droplet named '{}'", step, name)
+            }
+            AlreadyBound { step, name } => write!(
+                f,
+                "Step {}: droplet '{}' is already bound and hasn't been used",
+                step, name
+            ),
+            UnknownParam { step, name } => {
+                write!(f, "Step {}: no parameter named '{}'", step, name)
+            }
+            PuddleError { step, err } => write!(f, "Step {}: {}", step, err),
+            ForeignDroplet { name, id } => write!(
+                f,
+                "Droplet '{}' ({:?}) belongs to another process",
+                name, id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// A protocol turned into commands, ready to be added to a process.
+#[derive(Debug)]
+pub struct CompiledProtocol {
+    /// Each command, along with the step (or line, for programs) it came
+    /// from.
+    pub commands: Vec<(usize, BoxedCommand)>,
+    /// The named droplets left over at the end of the protocol.
+    pub droplets: IndexMap<String, DropletId>,
+}
+
+enum StepError {
+    Unbound(String),
+    AlreadyBound(String),
+    UnknownParam(String),
+    Puddle(PuddleError),
+}
+
+impl From<PuddleError> for StepError {
+    fn from(err: PuddleError) -> StepError {
+        StepError::Puddle(err)
+    }
+}
+
+type StepResult<T> = Result<T, StepError>;
+
+struct Compiler<'a> {
+    params: IndexMap<String, f64>,
+    env: IndexMap<String, DropletId>,
+    new_id: &'a mut dyn FnMut() -> DropletId,
+    step: usize,
+    commands: Vec<(usize, BoxedCommand)>,
+}
+
+impl Compiler<'_> {
+    fn number(&self, n: &Number) -> StepResult<f64> {
+        match n {
+            Number::Literal(x) => Ok(*x),
+            Number::Param(name) => {
+                let key = name.trim_start_matches('$');
+                self.params
+                    .get(key)
+                    .cloned()
+                    .ok_or_else(|| StepError::UnknownParam(name.clone()))
+            }
+        }
+    }
+
+    fn take(&mut self, name: &str) -> StepResult<DropletId> {
+        self.env
+            .swap_remove(name)
+            .ok_or_else(|| StepError::Unbound(name.into()))
+    }
+
+    fn bind(&mut self, name: &str) -> StepResult<DropletId> {
+        if self.env.contains_key(name) {
+            return Err(StepError::AlreadyBound(name.into()));
+        }
+        let id = (self.new_id)();
+        self.env.insert(name.into(), id);
+        Ok(id)
+    }
+
+    fn add(&mut self, cmd: impl command::Command + 'static) {
+        self.commands.push((self.step, Box::new(cmd)))
+    }
+
+    fn step(&mut self, step: &Step) -> StepResult<()> {
+        use self::Step::*;
+        match step {
+            Create {
+                out,
+                location,
+                volume,
+                dimensions,
+            } => {
+                let volume = self.number(volume)?;
+                let out = self.bind(out)?;
+                self.add(command::Create::new(*location, volume, *dimensions, out)?);
+            }
+            Input {
+                out,
+                port,
+                volume,
+                dimensions,
+            } => {
+                let volume = self.number(volume)?;
+                let out = self.bind(out)?;
+                self.add(command::Input::new(port.clone(), volume, *dimensions, out)?);
+            }
+            Output { droplet, port } => {
+                let d = self.take(droplet)?;
+                self.add(command::Output::new(port.clone(), d)?);
+            }
+            Move { droplet, to, out } => {
+                let d = self.take(droplet)?;
+                let out = self.bind(out)?;
+                self.add(command::Move::new(d, *to, out)?);
+            }
+            Mix { droplets, out } => {
+                let d1 = self.take(&droplets.0)?;
+                let d2 = self.take(&droplets.1)?;
+                // mixing is a combine followed by an agitate
+                let combined = (self.new_id)();
+                let out = self.bind(out)?;
+                self.add(command::Combine::new(d1, d2, combined)?);
+                self.add(command::Agitate::new(combined, out)?);
+            }
+            Split { droplet, out } => {
+                let d = self.take(droplet)?;
+                let out1 = self.bind(&out.0)?;
+                let out2 = self.bind(&out.1)?;
+                self.add(command::Split::new(d, out1, out2)?);
+            }
+            Heat {
+                droplet,
+                temperature,
+                seconds,
+                out,
+            } => {
+                let temperature = self.number(temperature)? as f32;
+                let duration = seconds_duration(self.number(seconds)?);
+                let d = self.take(droplet)?;
+                let out = self.bind(out)?;
+                self.add(command::Heat::new(d, out, temperature, duration)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Protocol {
+    /// Turns this protocol into commands, using `new_id` to name the
+    /// droplets. Parameters given in `params` override the defaults.
+    pub fn compile(
+        &self,
+        params: &IndexMap<String, f64>,
+        new_id: &mut dyn FnMut() -> DropletId,
+    ) -> Result<CompiledProtocol, ProtocolError> {
+        self.compile_with(params, IndexMap::new(), new_id)
+    }
+
+    /// Like `compile`, but the protocol can also use droplets that already
+    /// exist, by the names given in `droplets`.
+    pub fn compile_with(
+        &self,
+        params: &IndexMap<String, f64>,
+        droplets: IndexMap<String, DropletId>,
+        new_id: &mut dyn FnMut() -> DropletId,
+    ) -> Result<CompiledProtocol, ProtocolError> {
+        let mut all_params = self.params.clone();
+        all_params.extend(params.iter().map(|(k, v)| (k.clone(), *v)));
+
+        let mut compiler = Compiler {
+            params: all_params,
+            env: droplets,
+            new_id,
+            step: 0,
+            commands: Vec::new(),
+        };
+
+        for (i, step) in self.steps.iter().enumerate() {
+            debug!("Compiling protocol step {}: {:?}", i, step);
+            compiler.step = i;
+            compiler.step(step).map_err(|err| match err {
+                StepError::Unbound(name) => ProtocolError::UnboundDroplet { step: i, name },
+                StepError::AlreadyBound(name) => ProtocolError::AlreadyBound { step: i, name },
+                StepError::UnknownParam(name) => ProtocolError::UnknownParam { step: i, name },
+                StepError::Puddle(err) => ProtocolError::PuddleError { step: i, err },
+            })?;
+        }
+
+        Ok(CompiledProtocol {
+            commands: compiler.commands,
+            droplets: compiler.env,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matches::assert_matches;
+
+    fn compile(protocol: &Protocol) -> Result<CompiledProtocol, ProtocolError> {
+        let mut next = 0;
+        let mut new_id = || {
+            next += 1;
+            DropletId {
+                id: next,
+                process_id: 0,
+            }
+        };
+        protocol.compile(&IndexMap::new(), &mut new_id)
+    }
+
+    #[test]
+    fn test_compile() {
+        let json = r#"{
+            "params": {"vol": 2.0},
+            "steps": [
+                {"op": "create", "out": "a", "volume": "$vol"},
+                {"op": "create", "out": "b", "volume": 1.0},
+                {"op": "mix", "droplets": ["a", "b"], "out": "a"},
+                {"op": "split", "droplet": "a", "out": ["c", "d"]}
+            ]
+        }"#;
+        let protocol: Protocol = serde_json::from_str(json).unwrap();
+        let compiled = compile(&protocol).unwrap();
+
+        // mix turns into two commands
+        assert_eq!(compiled.commands.len(), 5);
+        let names: Vec<_> = compiled.droplets.keys().cloned().collect();
+        assert_eq!(names, vec!["c", "d"]);
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let check = |json: &str| {
+            let protocol: Protocol = serde_json::from_str(json).unwrap();
+            compile(&protocol).unwrap_err()
+        };
+
+        let err = check(r#"{"steps": [{"op": "output", "droplet": "a", "port": "out"}]}"#);
+        assert_matches!(err, ProtocolError::UnboundDroplet { step: 0, .. });
+
+        let err = check(
+            r#"{"steps": [
+                {"op": "create", "out": "a", "volume": 1.0},
+                {"op": "create", "out": "a", "volume": 1.0}
+            ]}"#,
+        );
+        assert_matches!(err, ProtocolError::AlreadyBound { step: 1, .. });
+
+        let err = check(r#"{"steps": [{"op": "create", "out": "a", "volume": "$nope"}]}"#);
+        assert_matches!(err, ProtocolError::UnknownParam { step: 0, .. });
+    }
+}
@@ -0,0 +1,259 @@
+//! Recording everything a system is asked to do, so it can be run again
+//! later to check that it does exactly the same thing.
+//!
+//! A recording is newline-delimited JSON, one [`Event`] per line, written as
+//! things happen so it survives a crash. It starts with the grid and how
+//! worn its electrodes were, then has the inputs (commands, flushes, and so
+//! on) mixed in with the outcomes (which commands were planned together,
+//! every step the board took, and everything that was heated or sensed
+//! along the way).
+//! [`replay`] feeds the inputs to a fresh system and checks that the
+//! outcomes match. The replay's wear is its own, so the electrode health
+//! file is left alone.
+
+use std::convert::TryFrom;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::command::{Action, SavedCommand};
+use crate::exec::StepInfo;
+use crate::grid::{parse::ParsedGrid, validate::InvalidGrid, DropletId, ElectrodeHealth, Grid};
+use crate::process::{ProcessId, PuddleError};
+use crate::system::System;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Always comes first. The wear is what the board started with, so the
+    /// replay plans around the same electrodes.
+    Start {
+        grid: ParsedGrid,
+        #[serde(default)]
+        health: ElectrodeHealth,
+    },
+    Add {
+        command: SavedCommand,
+    },
+    Flush {
+        droplets: Vec<DropletId>,
+    },
+    SetQuota {
+        pid: ProcessId,
+        quota: Option<usize>,
+    },
+    Kill {
+        pid: ProcessId,
+    },
+    SetGrid {
+        grid: ParsedGrid,
+    },
+    /// The planner decided to run these commands together, named by their
+    /// index in the command graph.
+    Plan {
+        commands: Vec<usize>,
+    },
+    Step {
+        step: StepInfo,
+    },
+    /// A command did something through a peripheral. These come before the
+    /// step they finished in.
+    Action {
+        action: Action,
+    },
+}
+
+impl Event {
+    /// Whether this is something the system did, rather than something it
+    /// was asked to do.
+    pub fn is_outcome(&self) -> bool {
+        matches!(
+            self,
+            Event::Plan { .. } | Event::Step { .. } | Event::Action { .. }
+        )
+    }
+}
+
+enum Sink {
+    File(LineWriter<File>),
+    Memory(Vec<Event>),
+}
+
+pub struct Recorder {
+    sink: Sink,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Recorder> {
+        let file = File::create(path)?;
+        let sink = Sink::File(LineWriter::new(file));
+        Ok(Recorder { sink })
+    }
+
+    pub fn memory() -> Recorder {
+        let sink = Sink::Memory(Vec::new());
+        Recorder { sink }
+    }
+
+    pub fn record(&mut self, event: Event) {
+        match &mut self.sink {
+            Sink::File(file) => {
+                let result = serde_json::to_writer(&mut *file, &event)
+                    .map_err(|e| e.into())
+                    .and_then(|()| writeln!(file));
+                if let Err(err) = result {
+                    error!("Failed to record {:?}. {}", event, err);
+                }
+            }
+            Sink::Memory(events) => events.push(event),
+        }
+    }
+
+    /// The events recorded so far, if they were kept in memory.
+    pub fn events(&self) -> &[Event] {
+        match &self.sink {
+            Sink::File(_) => &[],
+            Sink::Memory(events) => events,
+        }
+    }
+}
+
+/// If this variable is set, managers record everything to the file it
+/// names.
+const RECORD_VAR: &str = "PUDDLE_RECORD";
+
+pub(crate) fn recorder_from_env() -> Option<Recorder> {
+    let path = env::var(RECORD_VAR).ok()?;
+    match Recorder::create(&path) {
+        Ok(recorder) => {
+            info!("Recording to {}", path);
+            Some(recorder)
+        }
+        Err(err) => {
+            error!("Failed to start recording to {}. {}", path, err);
+            None
+        }
+    }
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Event>, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut events = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            events.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(events)
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// Recordings have to start with the grid, and only have it once.
+    NoStart,
+    InvalidGrid(InvalidGrid),
+    PuddleError(PuddleError),
+    /// The `index`th outcome was different. Either can be None if one run
+    /// did more than the other.
+    Diverged {
+        index: usize,
+        expected: Option<Box<Event>>,
+        found: Option<Box<Event>>,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::ReplayError::*;
+        match self {
+            NoStart => write!(f, "recording should start with the grid, and only once"),
+            InvalidGrid(err) => write!(f, "recording has a bad grid: {:?}", err),
+            PuddleError(err) => write!(f, "{}", err),
+            Diverged {
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "outcome {} was different\nexpected: {:?}\nfound:    {:?}",
+                index, expected, found
+            ),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+impl From<PuddleError> for ReplayError {
+    fn from(err: PuddleError) -> ReplayError {
+        ReplayError::PuddleError(err)
+    }
+}
+
+/// Runs a recording's inputs on a fresh system, and checks that it plans
+/// and steps exactly like it did before. Returns how many outcomes matched.
+pub fn replay(events: Vec<Event>) -> Result<usize, ReplayError> {
+    let mut events = events.into_iter();
+    let (grid, health) = match events.next() {
+        Some(Event::Start { grid, health }) => {
+            let grid = Grid::try_from(grid).map_err(ReplayError::InvalidGrid)?;
+            (grid, health)
+        }
+        _ => return Err(ReplayError::NoStart),
+    };
+
+    // the replay's wear isn't the board's, so keep it out of the files
+    let mut system = System::offline(grid, health);
+    system.record(Recorder::memory());
+
+    // errors from flushing and killing were part of the original run too,
+    // so they're checked by comparing outcomes
+    let mut expected = Vec::new();
+    for event in events {
+        match event {
+            Event::Start { .. } => return Err(ReplayError::NoStart),
+            Event::Add { command } => system.add(command.load()?)?,
+            Event::Flush { droplets } => {
+                let _ = system.flush(&droplets);
+            }
+            Event::SetQuota { pid, quota } => system.set_quota(pid, quota),
+            Event::Kill { pid } => {
+                let _ = system.kill(pid);
+            }
+            Event::SetGrid { grid } => {
+                let grid = Grid::try_from(grid).map_err(ReplayError::InvalidGrid)?;
+                system.set_grid(grid)?;
+            }
+            outcome => expected.push(outcome),
+        }
+    }
+
+    let found: Vec<_> = match system.recorder() {
+        Some(recorder) => recorder
+            .events()
+            .iter()
+            .filter(|e| e.is_outcome())
+            .cloned()
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let n = expected.len().max(found.len());
+    for index in 0..n {
+        let (e, f) = (expected.get(index), found.get(index));
+        if e != f {
+            return Err(ReplayError::Diverged {
+                index,
+                expected: e.cloned().map(Box::new),
+                found: f.cloned().map(Box::new),
+            });
+        }
+    }
+    Ok(n)
+}
@@ -0,0 +1,44 @@
+//! A quick look at whether the system is healthy, for monitoring. Nothing
+//! here waits for a flush, so a wedged board still gets an answer.
+
+use serde::Serialize;
+
+use crate::exec::RunState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    Sim,
+    Hardware,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activity {
+    Idle,
+    Flushing,
+}
+
+/// Whether the devices on the board are answering.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HardwareStatus {
+    pub hv507_initialized: bool,
+    /// Why the devices couldn't be set up, if they couldn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub heater_readable: bool,
+    /// The last reading, if there was one.
+    pub heater_temperature: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub backend: Backend,
+    /// Only there when running on real hardware.
+    pub hardware: Option<HardwareStatus>,
+    pub tick: usize,
+    pub activity: Activity,
+    pub run_state: RunState,
+    /// The last time planning failed, and why.
+    pub last_error: Option<String>,
+}
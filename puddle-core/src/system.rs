@@ -1,38 +1,456 @@
-use crate::command::BoxedCommand;
-use crate::exec::{Executor, StepInfo};
-use crate::grid::{droplet::DropletInfo, DropletId, Grid, GridView};
-use crate::process::{ProcessId, PuddleResult};
+use std::convert::TryFrom;
+use std::env;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::plan::graph::Graph;
-use crate::plan::{sched::SchedError, PlanError, Planner};
+use indexmap::IndexMap;
+use petgraph::algo::toposort;
+use serde::Serialize;
+
+use crate::checkpoint::{
+    checkpoint_enabled, save_checkpoint, Checkpoint, RestoreError, Restored, SavedProcess,
+};
+use crate::command::{BoxedCommand, Output};
+use crate::exec::{Controls, Executor, Progress, StepInfo, Watchers};
+use crate::grid::{
+    droplet::DropletInfo, parse::ParsedGrid, DropletId, ElectrodeHealth, Grid, GridView, Peripheral,
+};
+use crate::hardware::SharedHardware;
+use crate::metrics::Metrics;
+use crate::process::{ProcessId, PuddleError, PuddleResult};
+
+use crate::eventlog::{EventLog, ExecEvent};
+use crate::plan::analyze::{analyze, analyze_commands, Analysis, Problem};
+use crate::plan::graph::{CmdIndex, Graph};
+use crate::plan::sched::{command_process, needed_commands, SchedError};
+use crate::plan::{PlanError, PlanFailure, PlanStats, Planner};
+use crate::record::{Event, Recorder};
+use crate::util::Stopwatch;
+
+/// How much work the system has done so far.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Stats {
+    pub ticks: usize,
+    pub flushes: usize,
+    pub plan: PlanStats,
+    pub execute: Duration,
+}
+
+/// The processes that are still around. The droplet ids are only filled in
+/// when restoring, otherwise they come from the graph. Like `Controls`, this
+/// doesn't need the system lock, so processes can come and go in the middle
+/// of a flush.
+pub struct ProcessTable {
+    processes: Mutex<IndexMap<ProcessId, SavedProcess>>,
+    metrics: Arc<Metrics>,
+}
+
+impl ProcessTable {
+    fn new(metrics: Arc<Metrics>) -> ProcessTable {
+        ProcessTable {
+            processes: Mutex::default(),
+            metrics,
+        }
+    }
+
+    pub fn add(&self, pid: ProcessId, name: String, owner: Option<String>) {
+        let next_droplet_id = 0;
+        let saved = SavedProcess {
+            name,
+            next_droplet_id,
+            owner,
+        };
+        self.set(|processes| {
+            processes.insert(pid, saved);
+        });
+    }
+
+    pub fn remove(&self, pid: ProcessId) {
+        self.set(|processes| {
+            processes.swap_remove(&pid);
+        });
+    }
+
+    pub fn get(&self, pid: ProcessId) -> Option<SavedProcess> {
+        self.processes.lock().unwrap().get(&pid).cloned()
+    }
+
+    pub fn contains(&self, pid: ProcessId) -> bool {
+        self.processes.lock().unwrap().contains_key(&pid)
+    }
+
+    fn all(&self) -> IndexMap<ProcessId, SavedProcess> {
+        self.processes.lock().unwrap().clone()
+    }
+
+    fn set(&self, change: impl FnOnce(&mut IndexMap<ProcessId, SavedProcess>)) {
+        let mut processes = self.processes.lock().unwrap();
+        change(&mut processes);
+        self.metrics.set_processes(processes.len());
+    }
+}
 
 pub struct System {
-    #[allow(dead_code)]
     grid: Grid,
     graph: Graph,
     planner: Planner,
     executor: Executor,
+    processes: Arc<ProcessTable>,
+    /// Processes that couldn't be planned, and why.
+    failures: IndexMap<ProcessId, String>,
+    /// How many commands each process has that haven't been planned yet,
+    /// kept up as they come and go so counting them doesn't scan the graph.
+    pending: IndexMap<Option<ProcessId>, usize>,
+    flushes: usize,
+    execute_time: Duration,
+    recorder: Option<Recorder>,
+    /// Whether the wear and checkpoints are the real board's, kept in the
+    /// files the environment names. Replays and offline simulations leave
+    /// those files alone.
+    persistent: bool,
+    /// When the health and checkpoint were last saved, see `save`.
+    saved_at: Instant,
 }
 
 impl System {
     pub fn new(grid: Grid) -> System {
+        System::with_health(grid, load_health(), true)
+    }
+
+    /// Makes a system that starts from the given wear, and never reads or
+    /// writes the health or checkpoint files.
+    pub fn offline(grid: Grid, health: ElectrodeHealth) -> System {
+        System::with_health(grid, health, false)
+    }
+
+    fn with_health(grid: Grid, mut health: ElectrodeHealth, persistent: bool) -> System {
         info!("Creating a system");
+        if grid.endurance.is_some() {
+            health.endurance = grid.endurance;
+        }
         let planner = {
-            let gv = GridView::new(grid.clone());
+            let mut gv = GridView::new(grid.clone());
+            gv.health = health.clone();
             Planner::new(gv)
         };
+        let mut executor = Executor::new(grid.clone());
+        executor.gridview.health = health;
+        let processes = Arc::new(ProcessTable::new(Arc::clone(&executor.metrics)));
         System {
             grid: grid.clone(),
             graph: Graph::default(),
             planner,
-            executor: Executor::new(grid.clone()),
+            executor,
+            processes,
+            failures: IndexMap::new(),
+            pending: IndexMap::new(),
+            flushes: 0,
+            execute_time: Duration::default(),
+            recorder: None,
+            persistent,
+            saved_at: Instant::now(),
+        }
+    }
+
+    /// Starts recording everything from here on. See `record`.
+    pub fn record(&mut self, mut recorder: Recorder) {
+        if self.graph.graph.node_count() > 0 {
+            warn!("Recording a system that isn't fresh, it won't replay");
+        }
+        let grid = ParsedGrid::from(self.grid.clone());
+        let health = self.executor.gridview.health.clone();
+        recorder.record(Event::Start { grid, health });
+        self.executor.outcomes = Some(Vec::new());
+        self.recorder = Some(recorder);
+    }
+
+    /// Sends the executor's events here instead of wherever they were going.
+    pub fn log_events(&mut self, log: EventLog) {
+        self.executor.events = log;
+    }
+
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    fn record_event(&mut self, event: impl FnOnce() -> Event) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(event())
         }
     }
 
     pub fn add(&mut self, cmd: BoxedCommand) -> PuddleResult<()> {
-        // TODO unwrap
+        let cmd_id = self.add_command(cmd)?;
+        // only record it once it's made it in, like `add_all`
+        if let Some(recorder) = &mut self.recorder {
+            let cmd = self.graph.graph[cmd_id].as_ref().unwrap();
+            recorder.record(Event::Add {
+                command: cmd.save(),
+            });
+        }
+        Ok(())
+    }
+
+    fn add_command(&mut self, cmd: BoxedCommand) -> PuddleResult<CmdIndex> {
         info!("Adding command {:?}", cmd);
-        let _cmd_id = self.graph.add_command(cmd).unwrap();
+        let cmd_id = self
+            .graph
+            .add_command(cmd)
+            .map_err(PuddleError::GraphError)?;
+        self.pend(cmd_id);
+        self.check_fit(cmd_id);
+        self.show();
+        Ok(cmd_id)
+    }
+
+    /// Warns about a new command that can't run on this grid. It's only
+    /// a warning since the command's inputs aren't known yet, `add_all`
+    /// sees the whole protocol and fails instead.
+    fn check_fit(&self, cmd_id: CmdIndex) {
+        for problem in self.fit_problems(&[cmd_id]) {
+            warn!("Command will not fit: {}", problem);
+        }
+    }
+
+    /// What keeps the new commands from ever running on this grid. This
+    /// only looks at the new ones, the rest were checked when they came in.
+    fn fit_problems(&self, added: &[CmdIndex]) -> Vec<Problem> {
+        analyze_commands(&self.graph, &self.planner.gridview.grid, added).problems
+    }
+
+    /// Adds all of the commands, or none of them. If one can't be added,
+    /// the ones before it are taken back out, and its index is returned
+    /// with the error. The same goes if they can never fit on the grid,
+    /// then the first one that doesn't fit with the ones before it is to
+    /// blame.
+    pub fn add_all(&mut self, cmds: Vec<BoxedCommand>) -> Result<(), (usize, PuddleError)> {
+        let mut added = Vec::new();
+        for (i, cmd) in cmds.into_iter().enumerate() {
+            debug!("Adding command {:?}", cmd);
+            match self.graph.add_command(cmd) {
+                Ok(cmd_id) => added.push(cmd_id),
+                Err(err) => {
+                    warn!("Command {} can't be added, taking back {}", i, added.len());
+                    self.graph.remove_commands(&added);
+                    return Err((i, PuddleError::GraphError(err)));
+                }
+            }
+        }
+
+        // they were added in order, so they're already sorted
+        let problems = self.fit_problems(&added);
+        if !problems.is_empty() {
+            // blame the first command that can't fit with the ones before it
+            let i = (1..added.len())
+                .find(|&n| !self.fit_problems(&added[..n]).is_empty())
+                .unwrap_or(added.len())
+                - 1;
+            warn!("Protocol will not fit, taking back {}", added.len());
+            self.graph.remove_commands(&added);
+            return Err((i, PuddleError::WontFit(problems)));
+        }
+        for &cmd_id in &added {
+            self.pend(cmd_id);
+        }
+
+        // only record what actually made it in
+        if let Some(recorder) = &mut self.recorder {
+            for &cmd_id in &added {
+                let cmd = self.graph.graph[cmd_id].as_ref().unwrap();
+                recorder.record(Event::Add {
+                    command: cmd.save(),
+                });
+            }
+        }
+        self.show();
+        Ok(())
+    }
+
+    /// Commands that haven't been planned yet, not counting the ones that
+    /// never will be because their process failed.
+    fn unplanned(&self) -> usize {
+        let stopped = &self.planner.stopped;
+        self.pending
+            .iter()
+            .filter(|(pid, _)| match pid {
+                Some(pid) => !stopped.contains(pid),
+                None => true,
+            })
+            .map(|(_, &n)| n)
+            .sum()
+    }
+
+    /// Counts a command that's just been added as waiting to be planned.
+    fn pend(&mut self, cmd_id: CmdIndex) {
+        let pid = command_process(&self.graph, cmd_id);
+        *self.pending.entry(pid).or_insert(0) += 1;
+    }
+
+    /// Stops counting commands once they've been planned.
+    fn unpend(&mut self, planned: &[CmdIndex]) {
+        for &cmd_id in planned {
+            let pid = command_process(&self.graph, cmd_id);
+            if let Some(n) = self.pending.get_mut(&pid) {
+                *n = n.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Lets watchers see what's been added. See `Watchers::snapshot`.
+    fn show(&mut self) {
+        self.executor.unplanned = self.unplanned();
+        self.executor.show();
+    }
+
+    /// The processes that are still around, and where their droplet ids
+    /// are up to.
+    pub fn saved_processes(&self) -> IndexMap<ProcessId, SavedProcess> {
+        self.processes
+            .all()
+            .into_iter()
+            .map(|(pid, p)| {
+                let saved = SavedProcess {
+                    next_droplet_id: self.next_droplet_id(pid),
+                    ..p
+                };
+                (pid, saved)
+            })
+            .collect()
+    }
+
+    /// The process table, which can be used without the system lock.
+    pub fn processes(&self) -> Arc<ProcessTable> {
+        Arc::clone(&self.processes)
+    }
+
+    /// The next droplet id the process can use without running into any it
+    /// has already used.
+    fn next_droplet_id(&self, pid: ProcessId) -> usize {
+        let ids = self.graph.droplet_idx.keys();
+        let ids = ids.filter(|id| id.process_id == pid).map(|id| id.id + 1);
+        let restored = self.processes.get(pid).map(|p| p.next_droplet_id);
+        ids.chain(restored).max().unwrap_or(0)
+    }
+
+    /// Writes down everything that's needed to pick up where this left off.
+    /// Sensor readings aren't part of it, see the `checkpoint` module.
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.try_checkpoint().expect("command graph has a cycle")
+    }
+
+    /// Like `checkpoint`, but gives up instead of panicking if the command
+    /// graph is broken.
+    fn try_checkpoint(&self) -> Option<Checkpoint> {
+        let g = &self.graph.graph;
+        let order = toposort(g, None).ok()?;
+        let commands = order
+            .into_iter()
+            // the droplets are saved as they were before the current phase,
+            // so everything in it counts as not having run yet
+            .filter(|&cmd_id| {
+                !self.planner.is_scheduled(cmd_id) || self.executor.in_current_phase(cmd_id)
+            })
+            .filter_map(|cmd_id| g[cmd_id].as_ref())
+            .map(|cmd| cmd.save())
+            .collect();
+
+        Some(Checkpoint {
+            grid: ParsedGrid::from(self.grid.clone()),
+            droplets: self.planner.gridview.droplets.values().cloned().collect(),
+            commands,
+            processes: self.saved_processes(),
+            quotas: self.planner.quotas.clone(),
+            failures: self.failures.clone(),
+        })
+    }
+
+    /// Picks up from a checkpoint. This should be a fresh system on the same
+    /// grid the checkpoint was made on. The droplets go right back on the
+    /// board, and the commands that hadn't run yet are added back in.
+    pub fn restore(&mut self, checkpoint: Checkpoint) -> Result<(), RestoreError> {
+        match Grid::try_from(checkpoint.grid) {
+            Ok(ref grid) if *grid == self.grid => (),
+            _ => return Err(RestoreError::DifferentGrid),
+        }
+
+        let commands = checkpoint
+            .commands
+            .into_iter()
+            .map(|saved| saved.load())
+            .collect::<PuddleResult<Vec<_>>>()
+            .map_err(RestoreError::PuddleError)?;
+
+        let mut restored = Vec::new();
+        for droplet in checkpoint.droplets {
+            let output = droplet.id;
+            let cmd_id = self
+                .graph
+                .add_command(Box::new(Restored { output }))
+                .map_err(RestoreError::GraphError)?;
+            restored.push(cmd_id);
+            let droplets = &mut self.planner.gridview.droplets;
+            droplets.insert(output, droplet.clone());
+            self.executor.gridview.droplets.insert(output, droplet);
+        }
+        self.planner.mark_scheduled(restored);
+
+        for cmd in commands {
+            let cmd_id = self
+                .graph
+                .add_command(cmd)
+                .map_err(RestoreError::GraphError)?;
+            self.pend(cmd_id);
+        }
+
+        let restored = checkpoint.processes;
+        self.processes.set(|processes| *processes = restored);
+        self.planner.quotas = checkpoint.quotas;
+        self.planner.stopped = checkpoint.failures.keys().cloned().collect();
+        self.failures = checkpoint.failures;
+
+        self.show();
+        Ok(())
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    /// Swaps in a new grid. Only works when nothing is left to run, and
+    /// every droplet on the board is somewhere the new grid has.
+    pub fn set_grid(&mut self, grid: Grid) -> PuddleResult<()> {
+        if self.unplanned() > 0 || self.executor.in_phase() {
+            return Err(PuddleError::NotIdle);
+        }
+        for droplet in self.planner.gridview.droplets.values() {
+            if droplet.locations().any(|loc| grid.get_cell(loc).is_none()) {
+                return Err(PuddleError::DropletOffGrid(droplet.id));
+            }
+        }
+        if self.executor.has_hardware() {
+            grid.check_pins().map_err(PuddleError::InvalidGrid)?;
+        }
+
+        info!("Swapping in a new grid");
+        self.record_event(|| Event::SetGrid {
+            grid: ParsedGrid::from(grid.clone()),
+        });
+        // the wear counts go with the electrodes, wherever they are now
+        let mut health = self.executor.gridview.health.remap(&self.grid, &grid);
+        if grid.endurance.is_some() {
+            health.endurance = grid.endurance;
+        }
+        self.planner.gridview.health = health.clone();
+        self.executor.gridview.health = health;
+        self.save_health();
+
+        self.planner.gridview.grid = grid.clone();
+        self.executor.gridview.grid = grid.clone();
+        self.grid = grid;
+        // the droplets may be on different pins now
+        self.executor.output_pins();
         Ok(())
     }
 
@@ -40,37 +458,501 @@ impl System {
         self.planner.gridview.droplet_info(pid)
     }
 
+    /// See `Executor::set_hardware`. The grid has to be one the hardware
+    /// can drive, see `Grid::check_pins`.
+    pub fn set_hardware(&mut self, hardware: SharedHardware) -> PuddleResult<()> {
+        self.grid.check_pins().map_err(PuddleError::InvalidGrid)?;
+        self.executor.set_hardware(hardware);
+        Ok(())
+    }
+
+    /// See `Executor::keep_steps`.
+    pub fn keep_steps(&mut self) {
+        self.executor.keep_steps()
+    }
+
     pub fn get_logs(&self) -> &[StepInfo] {
         self.executor.get_logs()
     }
 
+    /// Statically checks everything that's been added against the grid.
+    pub fn analyze(&self) -> Analysis {
+        analyze(&self.graph, &self.planner.gridview.grid)
+    }
+
+    /// Runs everything the process has asked for. Like `flush`, this can
+    /// stop partway if execution is paused; call it again with `resumed`
+    /// set to keep going. See `unpaused`.
+    pub fn flush_process(&mut self, pid: ProcessId, resumed: bool) -> PuddleResult<Progress> {
+        // it could have been killed while the flush was paused
+        if !self.processes.contains(pid) {
+            return Err(PuddleError::NonExistentProcess(pid));
+        }
+        if let Some(why) = self.failure(pid) {
+            return Err(PuddleError::ProcessFailed(pid, why.into()));
+        }
+        let droplets = self.process_droplets(pid);
+        // an empty list would flush everyone's droplets
+        if droplets.is_empty() {
+            Ok(Progress::Done)
+        } else if resumed {
+            self.resume_flush(&droplets)
+        } else {
+            self.flush(&droplets)
+        }
+    }
+
+    /// The process's droplets, once `flush_process` is done.
+    pub fn flushed_process(&self, pid: ProcessId) -> PuddleResult<Vec<DropletInfo>> {
+        self.check_flushed(&self.process_droplets(pid))?;
+        Ok(self.info(Some(pid)))
+    }
+
+    /// Every droplet the process has ever made, or will make.
+    pub fn process_droplets(&self, pid: ProcessId) -> Vec<DropletId> {
+        let ids = self.graph.droplet_idx.keys();
+        ids.filter(|id| id.process_id == pid).cloned().collect()
+    }
+
+    /// Limits how many electrodes the process's droplets can take up at
+    /// once, counting the space around each droplet. Commands that would go
+    /// over wait until the process frees up some space.
+    pub fn set_quota(&mut self, pid: ProcessId, quota: Option<usize>) {
+        self.record_event(|| Event::SetQuota { pid, quota });
+        match quota {
+            Some(quota) => self.planner.quotas.insert(pid, quota),
+            None => self.planner.quotas.swap_remove(&pid),
+        };
+    }
+
+    /// Checks that everything needed for `droplets` has been run, blaming
+    /// the process that's holding things up if it hasn't.
+    pub fn check_flushed(&self, droplets: &[DropletId]) -> PuddleResult<()> {
+        let unfinished = needed_commands(&self.graph, droplets)
+            .into_iter()
+            .find(|&cmd| !self.planner.is_scheduled(cmd));
+        let pid = match unfinished {
+            Some(cmd) => command_process(&self.graph, cmd).expect("Command has no droplets"),
+            None => return Ok(()),
+        };
+        if let Some(why) = self.failures.get(&pid) {
+            Err(PuddleError::ProcessFailed(pid, why.clone()))
+        } else if self.planner.quotas.contains_key(&pid) {
+            Err(PuddleError::QuotaExceeded(pid))
+        } else {
+            Err(PuddleError::Unfinished(pid))
+        }
+    }
+
+    pub fn failure(&self, pid: ProcessId) -> Option<&str> {
+        self.failures.get(&pid).map(String::as_str)
+    }
+
+    /// Stops planning for the processes of the commands that couldn't be
+    /// planned, and aborts everything they had left, so the other processes
+    /// can keep going.
+    fn fail(&mut self, failure: PlanFailure) -> PuddleResult<()> {
+        let PlanFailure { error, commands } = failure;
+        let why = format!("{:?}", error);
+        let ticks = self.executor.ticks();
+        self.executor.metrics.error(&why);
+        if commands.is_empty() {
+            let message = why;
+            let events = &mut self.executor.events;
+            events.emit(ticks, || ExecEvent::Error {
+                process: None,
+                message,
+            });
+            return Err(PuddleError::PlanError(error));
+        }
+
+        for cmd_id in commands {
+            let pid = command_process(&self.graph, cmd_id).expect("Command has no droplets");
+            if self.planner.stopped.insert(pid) {
+                error!("Process {} failed: {}", pid, why);
+                let message = why.clone();
+                let events = &mut self.executor.events;
+                events.emit(ticks, || ExecEvent::Error {
+                    process: Some(pid),
+                    message,
+                });
+                self.failures.insert(pid, why.clone());
+            }
+        }
+
+        let to_abort: Vec<_> = self
+            .graph
+            .graph
+            .node_indices()
+            .filter(|&cmd_id| !self.planner.is_scheduled(cmd_id))
+            .filter(|&cmd_id| match command_process(&self.graph, cmd_id) {
+                Some(pid) => self.planner.stopped.contains(&pid),
+                None => false,
+            })
+            .collect();
+        for cmd_id in to_abort {
+            if let Some(cmd) = self.graph.graph[cmd_id].as_mut() {
+                cmd.abort(error.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// An output to throw droplets away in, preferably one named "waste".
+    fn waste_port(&self) -> Option<String> {
+        let outputs: Vec<_> = self
+            .grid
+            .locations()
+            .filter_map(|(_, e)| match e.peripheral {
+                Some(Peripheral::Output { name, .. }) => Some(name),
+                _ => None,
+            })
+            .collect();
+        let waste = outputs.iter().find(|name| *name == "waste");
+        waste.or_else(|| outputs.first()).cloned()
+    }
+
+    /// Stops a process for good. Its running commands are aborted, the ones
+    /// that haven't run yet are taken out of the graph, and the droplets it
+    /// has left on the board are sent to an output so they're out of
+    /// everyone else's way. If that fails, or there's no output to send
+    /// them to, the process is still stopped, but it sticks around so the
+    /// kill can be tried again.
+    ///
+    /// Sending the droplets away takes ticks, so if execution is paused
+    /// this returns `Progress::Paused`; finish up with `resume_kill`.
+    pub fn kill(&mut self, pid: ProcessId) -> PuddleResult<Progress> {
+        info!("Killing process {}", pid);
+        self.record_event(|| Event::Kill { pid });
+        self.executor.abort_process(pid, &mut self.graph);
+        self.executor.controls.forget_kill(pid);
+        self.remove_pending(pid);
+
+        let leftovers = self.leftovers(pid);
+        let result = if leftovers.is_empty() {
+            Ok(Progress::Done)
+        } else if let Some(port) = self.waste_port() {
+            // let the outputs through even if the process had failed
+            self.planner.stopped.swap_remove(&pid);
+            self.dispose(port, &leftovers)
+        } else {
+            warn!(
+                "No output to dispose of process {}'s {} droplets",
+                pid,
+                leftovers.len()
+            );
+            Err(PuddleError::NoWastePort(pid))
+        };
+        self.finish_kill(pid, result)
+    }
+
+    /// Keeps sending a killed process's droplets away after a pause.
+    pub fn resume_kill(&mut self, pid: ProcessId) -> PuddleResult<Progress> {
+        let leftovers = self.leftovers(pid);
+        // an empty list would flush everyone's droplets
+        let result = if leftovers.is_empty() {
+            Ok(Progress::Done)
+        } else {
+            self.run(&leftovers)
+        };
+        self.finish_kill(pid, result)
+    }
+
+    fn finish_kill(
+        &mut self,
+        pid: ProcessId,
+        result: PuddleResult<Progress>,
+    ) -> PuddleResult<Progress> {
+        match result {
+            Ok(Progress::Paused) => return Ok(Progress::Paused),
+            Ok(Progress::Done) => {
+                self.planner.quotas.swap_remove(&pid);
+                self.processes.remove(pid);
+            }
+            // take the outputs back out, so the next try can add them again
+            Err(_) => self.remove_pending(pid),
+        }
+        self.planner.stopped.insert(pid);
+        self.show();
+        result
+    }
+
+    /// The process's droplets that are on the board, or will be once the
+    /// current phase is done.
+    fn leftovers(&self, pid: ProcessId) -> Vec<DropletId> {
+        let droplets = self.planner.gridview.droplets.keys();
+        droplets
+            .filter(|id| id.process_id == pid)
+            .cloned()
+            .collect()
+    }
+
+    /// Takes the process's commands that haven't been planned out of the
+    /// graph.
+    fn remove_pending(&mut self, pid: ProcessId) {
+        let pending: Vec<_> = self
+            .graph
+            .graph
+            .node_indices()
+            .filter(|&cmd_id| self.graph.graph[cmd_id].is_some())
+            .filter(|&cmd_id| !self.planner.is_scheduled(cmd_id))
+            .filter(|&cmd_id| command_process(&self.graph, cmd_id) == Some(pid))
+            .collect();
+        for mut cmd in self.graph.remove_commands(&pending) {
+            cmd.abort(PlanError::Cancelled);
+        }
+        self.pending.swap_remove(&Some(pid));
+    }
+
+    fn dispose(&mut self, port: String, droplets: &[DropletId]) -> PuddleResult<Progress> {
+        for &id in droplets {
+            self.add_command(Box::new(Output::new(port.clone(), id)?))?;
+        }
+        self.run(droplets)
+    }
+
     // TODO switch to event loop here
-    pub fn flush(&mut self, droplets: &[DropletId]) -> PuddleResult<()> {
+    /// Runs the commands needed to settle `droplets`, or everything if it's
+    /// empty. If a process's commands can't be planned, that process is
+    /// stopped and the rest keep going.
+    ///
+    /// If execution is paused partway, this returns `Progress::Paused`
+    /// and `resume_flush` picks up where it left off.
+    pub fn flush(&mut self, droplets: &[DropletId]) -> PuddleResult<Progress> {
+        self.record_event(|| Event::Flush {
+            droplets: droplets.to_vec(),
+        });
+        self.run(droplets)
+    }
+
+    /// Keeps going with a `flush` that was paused.
+    pub fn resume_flush(&mut self, droplets: &[DropletId]) -> PuddleResult<Progress> {
+        self.run(droplets)
+    }
+
+    fn run(&mut self, droplets: &[DropletId]) -> PuddleResult<Progress> {
+        // the droplets would only move in the simulation
+        if let Some(err) = self.executor.hardware_error() {
+            return Err(PuddleError::HardwareUnavailable(err));
+        }
+        self.executor.metrics.set_flushing(true);
+        let result = self.run_phases(droplets);
+        // a paused flush is still going
+        if let Ok(Progress::Paused) = result {
+            return result;
+        }
+        self.executor.metrics.set_flushing(false);
+        result
+    }
+
+    /// Finishes the phase the executor is in the middle of, if it's allowed
+    /// to, and catches the planner up once it's done.
+    fn continue_phase(&mut self) -> Progress {
+        if !self.executor.in_phase() {
+            return Progress::Done;
+        }
+
+        let mut watch = Stopwatch::start();
+        let progress = loop {
+            // save every so often, a phase can take a long time on hardware
+            let until = Some(self.saved_at + SAVE_INTERVAL).filter(|_| self.persistent);
+            match self.executor.resume_until(&mut self.graph, until) {
+                Some(progress) => break progress,
+                None => self.save(),
+            }
+        };
+        self.execute_time += watch.lap();
+        if let (Some(recorder), Some(outcomes)) = (&mut self.recorder, &mut self.executor.outcomes)
+        {
+            for outcome in outcomes.drain(..) {
+                recorder.record(outcome);
+            }
+        }
+        if progress == Progress::Paused {
+            return progress;
+        }
+
+        // TODO this is a little hacky
+        self.planner.gridview = self.executor.gridview.clone();
+        debug!(
+            "Updated planner droplets: {:#?}",
+            self.planner.gridview.droplets
+        );
+        Progress::Done
+    }
+
+    fn run_phases(&mut self, droplets: &[DropletId]) -> PuddleResult<Progress> {
         info!("Flushing...");
         loop {
-            let phase = match self.planner.plan(&self.graph, droplets) {
+            if self.continue_phase() == Progress::Paused {
+                info!("Paused partway through a flush");
+                return Ok(Progress::Paused);
+            }
+
+            // don't start anything new for processes that are being killed
+            for pid in self.executor.controls.killed() {
+                self.planner.stopped.insert(pid);
+            }
+
+            let plan = self.planner.plan(&self.graph, droplets);
+            self.executor.metrics.planned(&self.planner.stats);
+            let phase = match plan {
                 Ok(phase) => phase,
-                Err(PlanError::SchedError(SchedError::NothingToSchedule)) => break,
-                Err(e) => panic!("{:?}", e),
+                Err(PlanFailure {
+                    error: PlanError::SchedError(SchedError::NothingToSchedule),
+                    ..
+                }) => break,
+                Err(failure) => {
+                    self.fail(failure)?;
+                    continue;
+                }
             };
 
-            // TODO For now this is blocking
-            self.executor.run(phase, &mut self.graph);
+            self.record_event(|| {
+                let planned = phase.planned_commands.iter();
+                let commands = planned.map(|planned| planned.cmd_id.index()).collect();
+                Event::Plan { commands }
+            });
 
-            // TODO this is a little hacky
-            self.planner.gridview = self.executor.gridview.clone();
-            debug!(
-                "Updated planner droplets: {:#?}",
-                self.planner.gridview.droplets
-            );
+            let planned: Vec<_> = phase.planned_commands.iter().map(|p| p.cmd_id).collect();
+            self.unpend(&planned);
+            self.executor.unplanned = self.unplanned();
+            self.executor.start(phase);
         }
 
+        self.show();
+        self.save();
+        self.flushes += 1;
         info!("Flushed!");
 
-        Ok(())
+        Ok(Progress::Done)
+    }
+
+    /// Saves the electrode health and a checkpoint, if there are files for
+    /// them. This happens after every flush and every `SAVE_INTERVAL`
+    /// during one, but anything that exits without dropping the system
+    /// should call it first.
+    pub fn save(&mut self) {
+        self.save_health();
+        self.autosave();
+        self.saved_at = Instant::now();
+    }
+
+    fn save_health(&self) {
+        if self.persistent {
+            save_health(&self.executor.gridview.health);
+        }
+    }
+
+    /// Saves a checkpoint if there's a file for it, see `checkpoint`.
+    fn autosave(&self) {
+        if !self.persistent || !checkpoint_enabled() {
+            return;
+        }
+        match self.try_checkpoint() {
+            Some(checkpoint) => save_checkpoint(&checkpoint),
+            None => error!("Command graph has a cycle, not saving a checkpoint"),
+        }
+    }
+
+    pub fn controls(&self) -> Arc<Controls> {
+        Arc::clone(&self.executor.controls)
+    }
+
+    pub fn watchers(&self) -> Arc<Watchers> {
+        Arc::clone(&self.executor.watchers)
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.executor.metrics)
     }
 
     pub fn ticks(&self) -> usize {
         self.executor.ticks()
     }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            ticks: self.ticks(),
+            flushes: self.flushes,
+            plan: self.planner.stats.clone(),
+            execute: self.execute_time,
+        }
+    }
+}
+
+/// Runs `step` with the system locked until it's done, then `finish` with
+/// how it went. Whenever execution is paused partway, the lock is let go
+/// until it's resumed, so everything else that needs the system can still
+/// get in, and then `step` is called again with `resumed` set to pick up
+/// where it left off.
+pub fn unpaused<T>(
+    system: &Mutex<System>,
+    mut step: impl FnMut(&mut System, bool) -> PuddleResult<Progress>,
+    finish: impl FnOnce(&mut System, PuddleResult<()>) -> PuddleResult<T>,
+) -> PuddleResult<T> {
+    let mut sys = system.lock().unwrap();
+    let mut resumed = false;
+    let result = loop {
+        match step(&mut sys, resumed) {
+            Ok(Progress::Done) => break Ok(()),
+            Ok(Progress::Paused) => {
+                let controls = sys.controls();
+                drop(sys);
+                controls.wait_while_paused();
+                sys = system.lock().unwrap();
+                resumed = true;
+            }
+            Err(err) => break Err(err),
+        }
+    };
+    finish(&mut sys, result)
+}
+
+impl Drop for System {
+    fn drop(&mut self) {
+        // the last flush already saved one, so don't make a panic worse
+        if !thread::panicking() {
+            self.autosave();
+        }
+    }
+}
+
+/// How often a long flush saves the health and a checkpoint.
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The electrode health file is named by this variable. If it's not set, the
+/// actuation counters only last as long as the System does.
+const HEALTH_VAR: &str = "PUDDLE_ELECTRODE_HEALTH";
+
+fn load_health() -> ElectrodeHealth {
+    let path = match env::var(HEALTH_VAR) {
+        Ok(path) => path,
+        Err(_) => return ElectrodeHealth::default(),
+    };
+
+    if !Path::new(&path).exists() {
+        info!("No electrode health at {}, starting fresh", path);
+        return ElectrodeHealth::default();
+    }
+
+    match ElectrodeHealth::load(&path) {
+        Ok(health) => {
+            info!("Loaded electrode health from {}", path);
+            health
+        }
+        Err(err) => {
+            error!("Failed to load electrode health from {}. {}", path, err);
+            ElectrodeHealth::default()
+        }
+    }
+}
+
+fn save_health(health: &ElectrodeHealth) {
+    if let Ok(path) = env::var(HEALTH_VAR) {
+        match health.save(&path) {
+            Ok(()) => debug!("Saved electrode health to {}", path),
+            Err(err) => error!("Failed to save electrode health to {}. {}", path, err),
+        }
+    }
 }
@@ -47,6 +47,35 @@ impl Timer {
     }
 }
 
+/// Measures elapsed time like `Timer`, but is safe to use in the browser.
+/// There's no clock on wasm, so everything takes no time there.
+pub struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    lap: Instant,
+}
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        Stopwatch {
+            #[cfg(not(target_arch = "wasm32"))]
+            lap: Instant::now(),
+        }
+    }
+
+    /// Returns the time since the last lap (or the start).
+    pub fn lap(&mut self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let now = Instant::now();
+            let lap_time = now - self.lap;
+            self.lap = now;
+            lap_time
+        }
+        #[cfg(target_arch = "wasm32")]
+        Duration::default()
+    }
+}
+
 pub fn duration_seconds(duration: &Duration) -> f64 {
     let nanos: f64 = duration.subsec_nanos().into();
     (duration.as_secs() as f64) + nanos / 1e9
@@ -1,8 +1,16 @@
 use std::collections::{HashMap, HashSet};
 use std::env;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 
 use matches::assert_matches;
-use puddle_core::{grid::location::yx, prelude::*, process::ProcessHandle};
+use puddle_core::{
+    grid::{location::yx, GridView, Peripheral},
+    hardware::Hardware,
+    prelude::*,
+    process::ProcessHandle,
+    status::HardwareStatus,
+};
 
 fn manager_from_str(s: &str) -> Manager {
     let _ = env_logger::builder().is_test(true).try_init();
@@ -25,6 +33,76 @@ fn manager_from_rect(rows: usize, cols: usize) -> Manager {
     Manager::new(blocking, grid)
 }
 
+/// A 10x10 board with one process that's going to move a droplet from
+/// corner to corner, which takes enough ticks to pause partway through.
+fn manager_with_long_move() -> (Manager, ProcessId, DropletId) {
+    let man = manager_from_rect(10, 10);
+    let pid = man.new_process("test").unwrap();
+    let d = {
+        let p = man.get_process(pid).unwrap();
+        let d = p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+        p.move_droplet(d, yx(9, 9)).unwrap()
+    };
+    (man, pid, d)
+}
+
+/// Flushes the process on another thread, so the test can watch it go.
+fn flush_in_background(man: &Arc<Manager>, pid: ProcessId) -> JoinHandle<Result<(), PuddleError>> {
+    let man = Arc::clone(man);
+    thread::spawn(move || man.get_process(pid).unwrap().flush().map(|_| ()))
+}
+
+/// Stands in for a real board, remembering which pins were on each time.
+struct FakeBoard(Arc<Mutex<Vec<Vec<u32>>>>);
+
+impl Hardware for FakeBoard {
+    fn output_pins(&mut self, gv: &GridView) {
+        let pins = gv
+            .droplets
+            .values()
+            .flat_map(|d| d.locations())
+            .map(|loc| gv.grid.get_cell(loc).unwrap().pin)
+            .collect();
+        self.0.lock().unwrap().push(pins);
+    }
+
+    fn status(&mut self) -> HardwareStatus {
+        HardwareStatus {
+            hv507_initialized: true,
+            error: None,
+            heater_readable: false,
+            heater_temperature: None,
+        }
+    }
+
+    fn read_sensor(&mut self, sensor: &Peripheral) -> Option<f64> {
+        match sensor {
+            Peripheral::Sensor { name, .. } if name == "optical" => Some(42.0),
+            _ => None,
+        }
+    }
+}
+
+/// A board that never came up.
+struct DeadBoard;
+
+impl Hardware for DeadBoard {
+    fn output_pins(&mut self, _gv: &GridView) {}
+
+    fn status(&mut self) -> HardwareStatus {
+        HardwareStatus {
+            hv507_initialized: false,
+            error: Some("no pi here".into()),
+            heater_readable: false,
+            heater_temperature: None,
+        }
+    }
+
+    fn read_sensor(&mut self, _sensor: &Peripheral) -> Option<f64> {
+        None
+    }
+}
+
 fn info_dict(p: &ProcessHandle) -> HashMap<DropletId, DropletInfo> {
     p.flush().unwrap().into_iter().map(|d| (d.id, d)).collect()
 }
@@ -355,6 +433,150 @@ fn create_dimension() {
     assert_eq!(droplets[&id1].dimensions, dim1);
 }
 
+#[test]
+fn move_irregular_droplet() {
+    let man = manager_from_rect(9, 9);
+    let p = man.get_new_process("test");
+
+    // .a
+    // aa
+    let footprint = Footprint::from_offsets(vec![yx(0, 1), yx(1, 0), yx(1, 1)]);
+    let id1 = p
+        .create_with_footprint(Some(yx(1, 2)), 1.0, footprint.clone())
+        .unwrap();
+    let id2 = p.move_droplet(id1, yx(6, 6)).unwrap();
+
+    let droplets = info_dict(&p);
+
+    assert_eq!(droplets.len(), 1);
+    assert_eq!(droplets[&id2].location, yx(6, 6));
+    assert_eq!(droplets[&id2].footprint, footprint);
+    assert_eq!(droplets[&id2].dimensions, yx(2, 2));
+}
+
+#[test]
+fn split_and_combine_irregular_droplet() {
+    let man = manager_from_rect(12, 12);
+    let p = man.get_new_process("test");
+
+    // a.
+    // aa
+    // a.
+    // aa
+    let footprint = Footprint::from_offsets(vec![
+        yx(0, 0),
+        yx(1, 0),
+        yx(1, 1),
+        yx(2, 0),
+        yx(3, 0),
+        yx(3, 1),
+    ]);
+    let id = p
+        .create_with_footprint(Some(yx(1, 1)), 2.0, footprint)
+        .unwrap();
+    let (id1, id2) = p.split(id).unwrap();
+
+    let droplets = info_dict(&p);
+    let halves = Footprint::from_offsets(vec![yx(0, 0), yx(1, 0), yx(1, 1)]);
+    assert_eq!(droplets[&id1].footprint, halves);
+    assert_eq!(droplets[&id2].footprint, halves);
+
+    let id3 = p.combine(id1, id2).unwrap();
+
+    let droplets = info_dict(&p);
+    assert_eq!(droplets.len(), 1);
+    assert_eq!(droplets[&id3].footprint.len(), 6);
+    assert!(!droplets[&id3].footprint.is_rectangle());
+    assert_eq!(droplets[&id3].dimensions, yx(4, 2));
+}
+
+#[test]
+fn move_droplet_hex() {
+    let board_str = r#"
+        board: [
+          [  0,  1,  2,  3,  4 ],
+          [  5,  6,  7,  8,  9 ],
+          [ 10, 11, 12, 13, 14 ],
+          [ 15, 16, 17, 18, 19 ],
+        ]
+        topology: {type: Hex}
+    "#;
+
+    let man = manager_from_str(board_str);
+    let p = man.get_new_process("test");
+
+    let id1 = p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+    let id2 = p.move_droplet(id1, yx(3, 3)).unwrap();
+
+    let droplets = info_dict(&p);
+
+    assert_eq!(droplets[&id2].location, yx(3, 3));
+    // a square grid would take 6 steps, but hex diagonals make it 5
+    assert_eq!(p.ticks(), 7);
+}
+
+#[test]
+fn move_droplet_explicit_topology() {
+    // the electrodes are in a row, but they aren't wired up in order
+    let board_str = r#"
+        board: [[0, 1, 2, 3]]
+        topology:
+          type: Explicit
+          neighbors:
+            - location: {y: 0, x: 0}
+              neighbors: [{y: 0, x: 2}]
+            - location: {y: 0, x: 2}
+              neighbors: [{y: 0, x: 1}]
+            - location: {y: 0, x: 1}
+              neighbors: [{y: 0, x: 3}]
+    "#;
+
+    let man = manager_from_str(board_str);
+    let p = man.get_new_process("test");
+
+    let id1 = p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+    let id2 = p.move_droplet(id1, yx(0, 3)).unwrap();
+
+    let droplets = info_dict(&p);
+
+    assert_eq!(droplets[&id2].location, yx(0, 3));
+    assert_eq!(p.ticks(), 5);
+}
+
+#[test]
+fn footprint_connected_by_topology() {
+    // same wiring as above, so (0, 0) and (0, 1) aren't neighbors
+    let board_str = r#"
+        board: [[0, 1, 2, 3]]
+        topology:
+          type: Explicit
+          neighbors:
+            - location: {y: 0, x: 0}
+              neighbors: [{y: 0, x: 2}]
+            - location: {y: 0, x: 2}
+              neighbors: [{y: 0, x: 1}]
+            - location: {y: 0, x: 1}
+              neighbors: [{y: 0, x: 3}]
+    "#;
+
+    let man = manager_from_str(board_str);
+    let p = man.get_new_process("test");
+
+    let side_by_side = Footprint::rectangle(yx(1, 2));
+    assert_matches!(
+        p.create_with_footprint(Some(yx(0, 0)), 1.0, side_by_side),
+        Err(PuddleError::DisconnectedFootprint(_))
+    );
+
+    let wired = Footprint::from_offsets(vec![yx(0, 0), yx(0, 2)]);
+    let id = p
+        .create_with_footprint(Some(yx(0, 1)), 1.0, wired.clone())
+        .unwrap();
+
+    let droplets = info_dict(&p);
+    assert_eq!(droplets[&id].footprint, wired);
+}
+
 #[test]
 fn mix_larger_droplets() {
     let man = manager_from_rect(100, 100);
@@ -442,3 +664,1026 @@ fn combine_into() {
     assert_eq!(droplets[&ab].location, loc_a - y1);
     assert_eq!(droplets[&cd].location, loc_d - y1);
 }
+
+#[test]
+fn run_protocol_file() {
+    use indexmap::IndexMap;
+    use puddle_core::protocol::Protocol;
+    use std::fs::File;
+
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../tests/protocols/mix-split.yaml"
+    );
+    let protocol: Protocol = serde_yaml::from_reader(File::open(path).unwrap()).unwrap();
+
+    let man = manager_from_rect(10, 10);
+    let p = man.get_new_process("test");
+    let mut params = IndexMap::new();
+    params.insert("volume".to_string(), 2.0);
+    let env = p.load(&protocol, &params).unwrap();
+
+    let droplets = info_dict(&p);
+    assert_eq!(droplets.len(), 2);
+    assert_eq!(env.len(), 2);
+    assert_eq!(droplets[&env["c"]].location, yx(7, 2));
+    assert_eq!(droplets[&env["c"]].volume, 2.0);
+
+    let stats = man.stats();
+    assert_eq!(stats.ticks, p.ticks());
+    assert!(stats.plan.n_plans > 0);
+}
+
+#[test]
+fn run_program_file() {
+    use indexmap::IndexMap;
+    use std::fs::read_to_string;
+
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../tests/protocols/mix-split.puddle"
+    );
+    let src = read_to_string(path).unwrap();
+
+    let man = manager_from_rect(10, 10);
+    let p = man.get_new_process("test");
+    let env = p.load_program(&src, &IndexMap::new()).unwrap();
+
+    let droplets = info_dict(&p);
+    assert_eq!(droplets.len(), 2);
+    assert_eq!(env.len(), 2);
+    assert_eq!(droplets[&env["c"]].location, yx(7, 2));
+    assert_eq!(droplets[&env["d"]].volume, 1.0);
+}
+
+#[test]
+fn program_that_never_fits() {
+    use indexmap::IndexMap;
+    use puddle_core::lang::ErrorKind;
+    use puddle_core::plan::analyze::Problem;
+
+    let src = "
+let a = create(1.0, (1, 1))
+let b = create(1.0, (1, 1))
+let ab = mix(a, b)
+";
+
+    // there's only room for one droplet at a time
+    let man = manager_from_rect(2, 2);
+    let p = man.get_new_process("test");
+    let err = p.load_program(src, &IndexMap::new()).unwrap_err();
+
+    // the mix is what needs both droplets on the board at once
+    assert_eq!(err.line, 4);
+    assert_matches!(
+        err.kind,
+        ErrorKind::PuddleError(PuddleError::WontFit(ref problems))
+            if problems == &[Problem::TooCrowded { needed: 8, available: 4 }]
+    );
+
+    // none of it was added
+    p.flush().unwrap();
+    assert!(info_dict(&p).is_empty());
+}
+
+#[test]
+fn sense_droplet() {
+    let mut grid = Grid::rectangle(5, 5);
+    grid.get_cell_mut(yx(3, 3)).unwrap().peripheral = Some(Peripheral::Sensor {
+        spi_channel: 0,
+        name: "optical".into(),
+    });
+    let man = Manager::new(false, grid);
+    let p = man.get_new_process("test");
+
+    let d = p.create(Some(yx(0, 0)), 2.0, None).unwrap();
+    // some unrelated work that takes a while
+    let mut e = p.create(Some(yx(0, 4)), 1.0, None).unwrap();
+    for &loc in &[yx(4, 4), yx(0, 4), yx(4, 4)] {
+        e = p.move_droplet(e, loc).unwrap();
+    }
+
+    let (d, reading) = p.sense(d, "optical").unwrap();
+    // the simulator reports the volume
+    assert_eq!(reading.value, 2.0);
+    assert!(reading.simulated);
+
+    // the droplet is already there, so this shouldn't run anything
+    let ticks = p.ticks();
+    let info = p.flush_droplets(&[d]).unwrap();
+    assert_eq!(p.ticks(), ticks);
+    let sensed = info.iter().find(|info| info.id == d).unwrap();
+    assert_eq!(sensed.location, yx(3, 3));
+
+    // sensing shouldn't have waited for everything else
+    let droplets = info_dict(&p);
+    assert!(p.ticks() > ticks);
+    assert_eq!(droplets[&e].location, yx(4, 4));
+}
+
+#[test]
+fn sense_droplet_on_hardware() {
+    let mut grid = Grid::rectangle(5, 5);
+    grid.get_cell_mut(yx(3, 3)).unwrap().peripheral = Some(Peripheral::Sensor {
+        spi_channel: 0,
+        name: "optical".into(),
+    });
+    let man = Manager::new(false, grid);
+    let board = FakeBoard(Arc::new(Mutex::new(Vec::new())));
+    man.set_hardware(Box::new(board)).unwrap();
+    let p = man.get_new_process("test");
+
+    // the board's reading, not the volume
+    let d = p.create(Some(yx(0, 0)), 2.0, None).unwrap();
+    let (_, reading) = p.sense(d, "optical").unwrap();
+    assert_eq!(reading.value, 42.0);
+    assert!(!reading.simulated);
+}
+
+#[test]
+fn flush_on_dead_hardware() {
+    let man = manager_from_rect(5, 5);
+    man.set_hardware(Box::new(DeadBoard)).unwrap();
+    let p = man.get_new_process("test");
+
+    p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+    assert_matches!(
+        p.flush(),
+        Err(PuddleError::HardwareUnavailable(ref err)) if err == "no pi here"
+    );
+}
+
+#[test]
+fn flush_only_own_process() {
+    let man = manager_from_rect(10, 10);
+    let p1 = man.get_new_process("slow");
+    let p2 = man.get_new_process("fast");
+
+    let d1 = p1.create(Some(yx(0, 0)), 1.0, None).unwrap();
+    let d1 = p1.move_droplet(d1, yx(9, 9)).unwrap();
+    let d2 = p2.create(Some(yx(0, 9)), 1.0, None).unwrap();
+
+    // p2 shouldn't have to wait for p1's long move
+    let info2 = p2.flush().unwrap();
+    assert_eq!(info2.len(), 1);
+    assert_eq!(info2[0].id, d2);
+    let ticks = p2.ticks();
+    assert!(ticks < 5);
+
+    let droplets = info_dict(&p1);
+    assert_eq!(droplets[&d1].location, yx(9, 9));
+    assert!(p1.ticks() >= ticks + 9);
+}
+
+#[test]
+fn process_quota() {
+    let man = manager_from_rect(10, 10);
+    let p = man.get_new_process("test");
+    // a 1x1 droplet takes up 4 electrodes, counting the space around it,
+    // so only two of these fit
+    p.set_quota(Some(10));
+
+    let a = p.create(None, 1.0, None).unwrap();
+    let b = p.create(None, 1.0, None).unwrap();
+    assert_eq!(info_dict(&p).len(), 2);
+    p.create(None, 1.0, None).unwrap();
+    assert_matches!(p.flush(), Err(PuddleError::QuotaExceeded(_)));
+
+    // a mixed droplet only takes up 6, which leaves room for the last one
+    p.mix(a, b).unwrap();
+    assert_eq!(info_dict(&p).len(), 2);
+
+    p.set_quota(None);
+    p.create(None, 1.0, None).unwrap();
+    assert_eq!(info_dict(&p).len(), 3);
+}
+
+#[test]
+fn failure_only_stops_own_process() {
+    let man = manager_from_rect(10, 10);
+    let p1 = man.get_new_process("bad");
+    let p2 = man.get_new_process("good");
+
+    // way too big to ever fit
+    p1.create(None, 1.0, Some(yx(20, 20))).unwrap();
+    let d2 = p2.create(None, 1.0, None).unwrap();
+
+    assert_matches!(p1.flush(), Err(PuddleError::ProcessFailed(_, _)));
+    assert_matches!(p1.flush(), Err(PuddleError::ProcessFailed(_, _)));
+
+    let d2 = p2.move_droplet(d2, yx(5, 5)).unwrap();
+    let droplets = info_dict(&p2);
+    assert_eq!(droplets[&d2].location, yx(5, 5));
+}
+
+#[test]
+fn kill_process() {
+    let board_str = r#"
+        board: [
+          [  0,  1,  2,  3,  4 ],
+          [  5,  6,  7,  8,  9 ],
+          [ 10, 11, 12, 13, 14 ],
+        ]
+        peripherals:
+          - location: {y: 0, x: 4}
+            type: Output
+            name: waste
+            pwm_channel: 0
+    "#;
+
+    let man = manager_from_str(board_str);
+    let pid1 = man.new_process("doomed").unwrap();
+    let p2 = man.get_new_process("survivor");
+
+    {
+        let p1 = man.get_process(pid1).unwrap();
+        let d = p1.create(Some(yx(0, 0)), 1.0, None).unwrap();
+        p1.flush().unwrap();
+        // this never gets to run
+        p1.move_droplet(d, yx(2, 0)).unwrap();
+    }
+    let d2 = p2.create(Some(yx(2, 4)), 1.0, None).unwrap();
+    p2.flush().unwrap();
+
+    man.kill_process(pid1).unwrap();
+    assert_matches!(
+        man.get_process(pid1).err(),
+        Some(PuddleError::NonExistentProcess(_))
+    );
+
+    // the other process is untouched, and the dead one's droplet is gone
+    let p3 = man.get_new_process("newcomer");
+    let d3 = p3.create(Some(yx(0, 0)), 1.0, None).unwrap();
+    assert_eq!(info_dict(&p3)[&d3].location, yx(0, 0));
+    assert_eq!(info_dict(&p2)[&d2].location, yx(2, 4));
+}
+
+#[test]
+fn kill_during_flush() {
+    use std::time::Duration;
+
+    let board_str = r#"
+        board: [
+          [  0,  1,  2,  3,  4,  5 ],
+          [  6,  7,  8,  9, 10, 11 ],
+          [ 12, 13, 14, 15, 16, 17 ],
+          [ 18, 19, 20, 21, 22, 23 ],
+        ]
+        peripherals:
+          - location: {y: 0, x: 5}
+            type: Output
+            name: waste
+            pwm_channel: 0
+    "#;
+
+    let man = Arc::new(manager_from_str(board_str));
+    let pid = man.new_process("doomed").unwrap();
+    {
+        let p = man.get_process(pid).unwrap();
+        let a = p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+        let b = p.create(Some(yx(3, 0)), 1.0, None).unwrap();
+        let ab = p.mix(a, b).unwrap();
+        p.move_droplet(ab, yx(3, 3)).unwrap();
+    }
+
+    man.pause();
+    let flusher = flush_in_background(&man, pid);
+    let killer = {
+        let man = Arc::clone(&man);
+        thread::spawn(move || man.kill_process(pid))
+    };
+
+    // give the kill a chance to get in before anything runs
+    thread::sleep(Duration::from_millis(20));
+    man.resume();
+
+    killer.join().unwrap().unwrap();
+    assert!(flusher.join().unwrap().is_err());
+    assert_matches!(
+        man.kill_process(pid),
+        Err(PuddleError::NonExistentProcess(_))
+    );
+    assert!(man.snapshot().droplets.is_empty());
+}
+
+#[test]
+fn kill_without_waste_port() {
+    use puddle_core::grid::parse::ParsedGrid;
+
+    let man = manager_from_rect(3, 5);
+    let pid = man.new_process("doomed").unwrap();
+    {
+        let p = man.get_process(pid).unwrap();
+        p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+        p.flush().unwrap();
+    }
+
+    // nowhere to put the droplet, so the kill has to be tried again
+    assert_matches!(
+        man.kill_process(pid),
+        Err(PuddleError::NoWastePort(p)) if p == pid
+    );
+    assert!(man.get_process(pid).is_ok());
+    assert_eq!(man.snapshot().droplets.len(), 1);
+
+    let board_str = r#"
+        board: [
+          [  0,  1,  2,  3,  4 ],
+          [  5,  6,  7,  8,  9 ],
+          [ 10, 11, 12, 13, 14 ],
+        ]
+        peripherals:
+          - location: {y: 0, x: 4}
+            type: Output
+            name: waste
+            pwm_channel: 0
+    "#;
+    let grid: ParsedGrid = serde_yaml::from_str(board_str).unwrap();
+    man.load_grid(grid).unwrap();
+
+    man.kill_process(pid).unwrap();
+    assert!(man.get_process(pid).is_err());
+    assert!(man.snapshot().droplets.is_empty());
+}
+
+#[test]
+fn pause_and_step() {
+    use puddle_core::exec::RunState;
+    use std::time::Duration;
+
+    let (man, pid, _) = manager_with_long_move();
+    let man = Arc::new(man);
+
+    man.pause();
+    let flusher = flush_in_background(&man, pid);
+
+    let wait_for_pause = || {
+        while man.run_state() != RunState::Paused {
+            thread::sleep(Duration::from_millis(1));
+        }
+    };
+
+    // nothing happens until we let it
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(man.stats().ticks, 0);
+
+    // and the system isn't tied up in the meantime
+    let bystander = man.new_process("bystander").unwrap();
+    man.close_process(bystander).unwrap();
+    let doomed = man.new_process("doomed").unwrap();
+    man.kill_process(doomed).unwrap();
+
+    man.step(3);
+    wait_for_pause();
+    assert_eq!(man.stats().ticks, 3);
+    man.step(1);
+    wait_for_pause();
+    assert_eq!(man.stats().ticks, 4);
+
+    man.resume();
+    flusher.join().unwrap().unwrap();
+    assert!(man.stats().ticks > 4);
+}
+
+#[test]
+fn checkpoint_and_restore() {
+    use puddle_core::checkpoint::{Checkpoint, RestoreError};
+
+    let man = manager_from_rect(10, 10);
+    let p = man.get_new_process("long experiment");
+    let pid = p.id();
+
+    let a = p.create(Some(yx(1, 1)), 1.0, None).unwrap();
+    let b = p.create(Some(yx(1, 7)), 1.0, None).unwrap();
+    p.flush().unwrap();
+    // this hasn't run yet when the checkpoint is made
+    let ab = p.mix(a, b).unwrap();
+    drop(p);
+
+    let json = serde_json::to_string(&man.checkpoint()).unwrap();
+    drop(man);
+    let load = || serde_json::from_str::<Checkpoint>(&json).unwrap();
+
+    let man = Manager::restore(false, Grid::rectangle(10, 10), load()).unwrap();
+    let p = man.get_process(pid).unwrap();
+    let c = p.create(Some(yx(8, 8)), 1.0, None).unwrap();
+    assert!(![a, b, ab].contains(&c));
+
+    let droplets = info_dict(&p);
+    assert_eq!(droplets.len(), 2);
+    assert!(float_epsilon_equal(droplets[&ab].volume, 2.0));
+    assert_eq!(droplets[&c].location, yx(8, 8));
+
+    let other_grid = Manager::restore(false, Grid::rectangle(5, 5), load());
+    assert_matches!(other_grid.err(), Some(RestoreError::DifferentGrid));
+}
+
+#[test]
+fn record_and_replay() {
+    use puddle_core::record::{self, Event, ReplayError};
+
+    let path = env::temp_dir().join(format!("puddle-record-{}.ndjson", std::process::id()));
+    let man = manager_from_rect(10, 10);
+    man.record(&path).unwrap();
+
+    let p = man.get_new_process("recorded");
+    let a = p.create(Some(yx(1, 1)), 1.0, None).unwrap();
+    let b = p.create(Some(yx(1, 7)), 1.0, None).unwrap();
+    let ab = p.mix(a, b).unwrap();
+    // this doesn't make it in, so it isn't recorded
+    assert!(p.move_droplet(a, yx(0, 0)).is_err());
+    p.split(ab).unwrap();
+    p.flush().unwrap();
+    drop(p);
+    drop(man);
+
+    let events = record::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(events.iter().any(|e| e.is_outcome()));
+    assert!(record::replay(events.clone()).unwrap() > 0);
+
+    // a run that doesn't do the same thing is caught
+    let mut tampered = events;
+    let last_step = tampered
+        .iter()
+        .rposition(|e| matches!(e, Event::Step { .. }));
+    tampered.remove(last_step.unwrap());
+    assert_matches!(record::replay(tampered), Err(ReplayError::Diverged { .. }));
+}
+
+#[test]
+fn replay_starts_with_recorded_wear() {
+    use puddle_core::record::{self, Event, ReplayError};
+
+    let mut grid = Grid::rectangle(3, 5);
+    grid.endurance = Some(10);
+    let path = env::temp_dir().join(format!("puddle-wear-{}.ndjson", std::process::id()));
+    let man = Manager::offline(false, grid);
+    man.record(&path).unwrap();
+
+    let p = man.get_new_process("recorded");
+    let a = p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+    p.move_droplet(a, yx(0, 4)).unwrap();
+    p.flush().unwrap();
+    drop(p);
+    drop(man);
+
+    let events = record::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_matches!(
+        &events[0],
+        Event::Start { health, .. } if health.endurance == Some(10)
+    );
+    assert!(record::replay(events.clone()).unwrap() > 0);
+
+    // if the board had started out worn, the route would have gone around
+    let mut worn = events;
+    if let Event::Start { health, .. } = &mut worn[0] {
+        for _ in 0..10 {
+            health.actuate(yx(0, 2));
+        }
+    }
+    assert_matches!(record::replay(worn), Err(ReplayError::Diverged { .. }));
+}
+
+#[test]
+fn record_heat_and_sense() {
+    use puddle_core::command::Action;
+    use puddle_core::record::{self, Event, ReplayError};
+
+    let mut grid = Grid::rectangle(5, 5);
+    grid.get_cell_mut(yx(4, 0)).unwrap().peripheral = Some(Peripheral::Heater {
+        pwm_channel: 0,
+        spi_channel: 0,
+    });
+    grid.get_cell_mut(yx(3, 3)).unwrap().peripheral = Some(Peripheral::Sensor {
+        spi_channel: 0,
+        name: "optical".into(),
+    });
+
+    let path = env::temp_dir().join(format!("puddle-actions-{}.ndjson", std::process::id()));
+    let man = Manager::new(false, grid);
+    man.record(&path).unwrap();
+
+    let p = man.get_new_process("recorded");
+    let d = p.create(Some(yx(0, 0)), 2.0, None).unwrap();
+    let d = p.heat(d, 60.0, 1.0).unwrap();
+    let (_, reading) = p.sense(d, "optical").unwrap();
+    drop(p);
+    drop(man);
+
+    let events = record::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let actions: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            Event::Action { action } => Some(action.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(actions.len(), 2);
+    assert_matches!(actions[0], Action::Heat { temperature, .. } if temperature == 60.0);
+    assert_matches!(&actions[1], Action::Sense { reading: r, .. } if *r == reading);
+    assert!(record::replay(events.clone()).unwrap() > 0);
+
+    // a different reading is caught
+    let mut tampered = events;
+    for event in &mut tampered {
+        if let Event::Action {
+            action: Action::Sense { reading, .. },
+        } = event
+        {
+            reading.value += 1.0;
+        }
+    }
+    assert_matches!(record::replay(tampered), Err(ReplayError::Diverged { .. }));
+}
+
+#[test]
+fn event_log() {
+    use puddle_core::eventlog::{Entry, ExecEvent};
+    use std::io::{BufRead, BufReader};
+
+    let board_str = r#"
+        board: [
+          [  0,  1,  2,  3,  4 ],
+          [  5,  6,  7,  8,  9 ],
+          [  _,  _, 10,  _,  _ ],
+          [  _,  _, 11,  _,  _ ],
+        ]
+        peripherals:
+          - location: {y: 3, x: 2}
+            type: Heater
+            pwm_channel: 0
+            spi_channel: 0
+    "#;
+
+    let path = env::temp_dir().join(format!("puddle-events-{}.ndjson", std::process::id()));
+    let man = manager_from_str(board_str);
+    man.log_events(&path).unwrap();
+
+    let p = man.get_new_process("logged");
+    let d = p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+    let d = p.move_droplet(d, yx(0, 4)).unwrap();
+    p.heat(d, 60.0, 1.0).unwrap();
+    p.flush().unwrap();
+    let bad = man.get_new_process("bad");
+    bad.create(None, 1.0, Some(yx(20, 20))).unwrap();
+    assert!(bad.flush().is_err());
+    drop((p, bad));
+
+    let file = std::fs::File::open(&path).unwrap();
+    let entries: Vec<Entry> = BufReader::new(file)
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    std::fs::remove_file(&path).unwrap();
+
+    let count = |f: &dyn Fn(&ExecEvent) -> bool| entries.iter().filter(|e| f(&e.event)).count();
+    let starts = count(&|e| matches!(e, ExecEvent::CommandStart { .. }));
+    assert_eq!(starts, 3);
+    assert_eq!(count(&|e| matches!(e, ExecEvent::Placed { .. })), starts);
+    assert_eq!(
+        count(&|e| matches!(e, ExecEvent::CommandFinish { .. })),
+        starts
+    );
+    assert!(count(&|e| matches!(e, ExecEvent::Route { .. })) > 0);
+    assert_eq!(
+        count(&|e| matches!(e, ExecEvent::Step { .. })),
+        man.stats().ticks
+    );
+    assert_eq!(count(&|e| matches!(e, ExecEvent::Error { .. })), 1);
+
+    let peripheral = entries.iter().find_map(|e| match &e.event {
+        ExecEvent::Peripheral { location, .. } => Some(*location),
+        _ => None,
+    });
+    assert_eq!(peripheral, Some(yx(3, 2)));
+
+    let ticks: Vec<_> = entries.iter().map(|e| e.tick).collect();
+    assert!(ticks.windows(2).all(|w| w[0] <= w[1]));
+}
+
+#[test]
+fn subscribe_to_steps() {
+    let man = manager_from_rect(10, 10);
+    let steps = man.subscribe();
+    man.keep_steps();
+
+    let p = man.get_new_process("watched");
+    let d = p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+    p.move_droplet(d, yx(5, 5)).unwrap();
+    p.flush().unwrap();
+
+    let live: Vec<_> = steps.try_iter().collect();
+    assert_eq!(live.len(), man.stats().ticks);
+    let ticks: Vec<_> = live.iter().map(|s| s.tick).collect();
+    assert!(ticks.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(man.get_logs().len(), live.len());
+    assert_eq!(live.last().unwrap().step, *man.get_logs().last().unwrap());
+}
+
+#[test]
+fn snapshot_during_flush() {
+    use std::time::Duration;
+
+    let (man, pid, _) = manager_with_long_move();
+    let man = Arc::new(man);
+
+    // nothing has run, so nothing is on the board yet
+    let snapshot = man.snapshot();
+    assert_eq!(snapshot.pending, 2);
+    assert!(snapshot.droplets.is_empty());
+
+    man.pause();
+    let flusher = flush_in_background(&man, pid);
+
+    man.step(2);
+    while man.snapshot().tick < 2 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let snapshot = man.snapshot();
+    assert_eq!(snapshot.droplets.len(), 1);
+    assert!(snapshot.pending > 0);
+    assert_ne!(snapshot.droplets[0].location, yx(9, 9));
+
+    man.resume();
+    flusher.join().unwrap().unwrap();
+    let snapshot = man.snapshot();
+    assert_eq!(snapshot.pending, 0);
+    assert_eq!(snapshot.droplets[0].location, yx(9, 9));
+    assert_eq!(man.visualizer_droplet_info().unwrap(), snapshot.droplets);
+}
+
+#[test]
+fn processes_cant_use_each_others_droplets() {
+    use indexmap::IndexMap;
+    use puddle_core::protocol::{Protocol, ProtocolError};
+
+    let man = manager_from_rect(10, 10);
+    let alice = man.get_new_process("alice");
+    let bob = man.get_new_process("bob");
+    let a = alice.create(Some(yx(1, 1)), 1.0, None).unwrap();
+    let b = bob.create(Some(yx(8, 8)), 1.0, None).unwrap();
+
+    let foreign = |result: Result<_, PuddleError>| match result {
+        Err(PuddleError::ForeignDroplet(d)) => assert_eq!(d, a),
+        other => panic!("bob got to use alice's droplet: {:?}", other.map(|_| ())),
+    };
+    foreign(bob.move_droplet(a, yx(5, 5)).map(|_| ()));
+    foreign(bob.combine(b, a).map(|_| ()));
+    foreign(bob.mix(a, b).map(|_| ()));
+    foreign(bob.split(a).map(|_| ()));
+    foreign(bob.output("out", a));
+    foreign(bob.heat(a, 50.0, 1.0).map(|_| ()));
+    foreign(bob.sense(a, "sensor").map(|_| ()));
+    foreign(bob.flush_droplets(&[a]).map(|_| ()));
+
+    let protocol: Protocol = serde_json::from_str(
+        r#"{"steps": [{"op": "move", "droplet": "a", "to": {"y": 5, "x": 5}, "out": "moved"}]}"#,
+    )
+    .unwrap();
+    let mut stolen = IndexMap::new();
+    stolen.insert("a".to_string(), a);
+    let err = bob.submit(&protocol, stolen).unwrap_err();
+    assert_matches!(err, ProtocolError::ForeignDroplet { id, .. } if id == a);
+
+    // alice's droplet is right where she left it, and bob only has his own
+    assert_eq!(info_dict(&alice)[&a].location, yx(1, 1));
+    let bobs = info_dict(&bob);
+    assert_eq!(bobs.len(), 1);
+    assert!(bobs.contains_key(&b));
+}
+
+#[test]
+fn submit_all_or_nothing() {
+    use indexmap::IndexMap;
+    use puddle_core::protocol::{Protocol, ProtocolError};
+
+    let man = manager_from_rect(10, 10);
+    let p = man.get_new_process("test");
+    let a = p.create(Some(yx(1, 1)), 1.0, None).unwrap();
+
+    let protocol: Protocol = serde_json::from_str(
+        r#"{"steps": [
+            {"op": "create", "out": "b", "location": {"y": 1, "x": 7}, "volume": 1.0},
+            {"op": "mix", "droplets": ["a", "b"], "out": "ab"}
+        ]}"#,
+    )
+    .unwrap();
+
+    // a droplet that was never made can't be used, so nothing is added
+    let mut bogus = IndexMap::new();
+    bogus.insert(
+        "a".to_string(),
+        DropletId {
+            id: 42,
+            process_id: p.id(),
+        },
+    );
+    let err = p.submit(&protocol, bogus).unwrap_err();
+    assert_matches!(
+        err,
+        ProtocolError::PuddleError {
+            step: 1,
+            err: PuddleError::GraphError(_)
+        }
+    );
+    assert_eq!(info_dict(&p).len(), 1);
+
+    let mut existing = IndexMap::new();
+    existing.insert("a".to_string(), a);
+    let env = p.submit(&protocol, existing).unwrap();
+    let names: Vec<_> = env.keys().cloned().collect();
+    assert_eq!(names, vec!["ab"]);
+
+    let droplets = info_dict(&p);
+    assert_eq!(droplets.len(), 1);
+    assert!(float_epsilon_equal(droplets[&env["ab"]].volume, 2.0));
+}
+
+#[test]
+fn flush_as_a_job() {
+    use puddle_core::process::JobStatus;
+    use std::time::Duration;
+
+    let (man, pid, d) = manager_with_long_move();
+    let jobs = man.subscribe_jobs();
+
+    man.pause();
+    let job = man.flush_async(pid).unwrap();
+    assert_eq!(man.job_status(job).unwrap(), JobStatus::Running);
+    let status = man.wait(job, Duration::from_millis(10)).unwrap();
+    assert_eq!(status, JobStatus::Running);
+
+    man.resume();
+    let status = man.wait(job, Duration::from_secs(10)).unwrap();
+    let result = match status {
+        JobStatus::Done { result } => result,
+        status => panic!("job didn't finish: {:?}", status),
+    };
+    let droplets: Vec<DropletInfo> = serde_json::from_value(result).unwrap();
+    assert_eq!(droplets.len(), 1);
+    assert_eq!(droplets[0].id, d);
+    assert_eq!(droplets[0].location, yx(9, 9));
+
+    let finished = jobs.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(finished.job, job);
+
+    assert_matches!(
+        man.flush_async(1000),
+        Err(PuddleError::NonExistentProcess(_))
+    );
+    assert_matches!(man.job_status(1000), Err(PuddleError::NonExistentJob(_)));
+}
+
+#[test]
+fn apply_operations_and_swap_grids() {
+    use puddle_core::grid::parse::ParsedGrid;
+    use puddle_core::process::Operation;
+
+    let man = manager_from_rect(10, 10);
+    let p = man.get_new_process("test");
+    let op = |json: &str| serde_json::from_str::<Operation>(json).unwrap();
+
+    let a = p
+        .apply(op(
+            r#"{"op": "create", "location": {"y": 1, "x": 1}, "volume": 1.0}"#,
+        ))
+        .unwrap();
+    let a: DropletId = serde_json::from_value(a).unwrap();
+    let moved = format!(
+        r#"{{"op": "move", "droplet": {}, "to": {{"y": 2, "x": 2}}}}"#,
+        serde_json::to_string(&a).unwrap()
+    );
+    let b: DropletId = serde_json::from_value(p.apply(op(&moved)).unwrap()).unwrap();
+
+    // can't swap with the move still pending
+    let small = ParsedGrid::from(Grid::rectangle(2, 2));
+    assert_matches!(man.load_grid(small.clone()), Err(PuddleError::NotIdle));
+
+    p.apply(op(r#"{"op": "flush"}"#)).unwrap();
+    assert_matches!(man.load_grid(small), Err(PuddleError::DropletOffGrid(id)) if id == b);
+
+    let big = ParsedGrid::from(Grid::rectangle(12, 12));
+    man.load_grid(big.clone()).unwrap();
+    assert_eq!(man.get_grid(), big);
+
+    let c = p.move_droplet(b, yx(11, 11)).unwrap();
+    assert_eq!(info_dict(&p)[&c].location, yx(11, 11));
+}
+
+#[test]
+fn swapping_grids_refreshes_hardware() {
+    use puddle_core::grid::parse::ParsedGrid;
+
+    let man = manager_from_rect(3, 3);
+    let p = man.get_new_process("test");
+    p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+    p.flush().unwrap();
+
+    let outputs = Arc::new(Mutex::new(Vec::new()));
+    man.set_hardware(Box::new(FakeBoard(Arc::clone(&outputs))))
+        .unwrap();
+    assert_eq!(outputs.lock().unwrap().last(), Some(&vec![0]));
+
+    // same shape, but the pins go the other way
+    let mut grid = Grid::rectangle(3, 3);
+    let locs: Vec<_> = grid.locations().map(|(loc, _)| loc).collect();
+    for loc in locs {
+        let electrode = grid.get_cell_mut(loc).unwrap();
+        electrode.pin = 8 - electrode.pin;
+    }
+    man.load_grid(ParsedGrid::from(grid)).unwrap();
+    assert_eq!(outputs.lock().unwrap().last(), Some(&vec![8]));
+}
+
+#[test]
+fn status_checks_the_hardware() {
+    use puddle_core::status::Backend;
+
+    let (man, pid, _) = manager_with_long_move();
+    assert_eq!(man.status().backend, Backend::Sim);
+    assert_eq!(man.status().hardware, None);
+
+    let outputs = Arc::new(Mutex::new(Vec::new()));
+    man.set_hardware(Box::new(FakeBoard(Arc::clone(&outputs))))
+        .unwrap();
+    let status = man.status();
+    assert_eq!(status.backend, Backend::Hardware);
+    assert!(status.hardware.unwrap().hv507_initialized);
+
+    // every tick goes out to the board
+    man.get_process(pid).unwrap().flush().unwrap();
+    let ticks = man.stats().ticks;
+    assert_eq!(outputs.lock().unwrap().len(), ticks + 1);
+}
+
+#[test]
+fn hardware_needs_pins_it_can_drive() {
+    use puddle_core::grid::parse::ParsedGrid;
+    use puddle_core::status::Backend;
+
+    let board = || FakeBoard(Arc::new(Mutex::new(Vec::new())));
+
+    // past the HV507's outputs
+    let mut grid = Grid::rectangle(3, 3);
+    grid.get_cell_mut(yx(1, 1)).unwrap().pin = 200;
+    let man = Manager::new(false, grid);
+    assert_matches!(
+        man.set_hardware(Box::new(board())),
+        Err(PuddleError::InvalidGrid(_))
+    );
+    assert_eq!(man.status().backend, Backend::Sim);
+
+    // fine in simulation, but not once the hardware is there
+    let man = manager_from_rect(3, 3);
+    let mut grid = Grid::rectangle(3, 3);
+    grid.get_cell_mut(yx(0, 0)).unwrap().pin = 8;
+    man.load_grid(ParsedGrid::from(grid.clone())).unwrap();
+    man.load_grid(ParsedGrid::from(Grid::rectangle(3, 3)))
+        .unwrap();
+    man.set_hardware(Box::new(board())).unwrap();
+    assert_matches!(
+        man.load_grid(ParsedGrid::from(grid)),
+        Err(PuddleError::InvalidGrid(_))
+    );
+}
+
+#[test]
+fn list_peripherals() {
+    let board_str = r#"
+        board: [
+          [  0,  1,  2 ],
+          [  3,  4,  5 ],
+        ]
+        peripherals:
+          - location: {y: 1, x: 2}
+            type: Heater
+            pwm_channel: 3
+            spi_channel: 4
+    "#;
+
+    let man = manager_from_str(board_str);
+    let peripherals = man.list_peripherals();
+    assert_eq!(peripherals.len(), 1);
+    assert_eq!(peripherals[0].location, yx(1, 2));
+    assert_eq!(
+        peripherals[0].peripheral,
+        Peripheral::Heater {
+            pwm_channel: 3,
+            spi_channel: 4
+        }
+    );
+}
+
+#[test]
+fn processes_have_owners() {
+    let man = manager_from_rect(3, 3);
+    let alice = man
+        .new_owned_process("alice's", Some("alice".into()))
+        .unwrap();
+    let anyone = man.new_process("anyone's").unwrap();
+
+    assert!(man.check_owner(alice, "alice").is_ok());
+    assert_matches!(
+        man.check_owner(alice, "bob"),
+        Err(PuddleError::NotOwner(pid)) if pid == alice
+    );
+    assert!(man.check_owner(anyone, "bob").is_ok());
+    assert_eq!(man.get_process(alice).unwrap().owner(), Some("alice"));
+
+    // owners come back with the checkpoint
+    let checkpoint = man.checkpoint();
+    assert_eq!(
+        checkpoint.processes[&alice].owner,
+        Some("alice".to_string())
+    );
+    let restored = Manager::restore(false, Grid::rectangle(3, 3), checkpoint).unwrap();
+    assert_matches!(
+        restored.check_owner(alice, "bob"),
+        Err(PuddleError::NotOwner(_))
+    );
+    assert!(restored.check_owner(anyone, "bob").is_ok());
+}
+
+#[test]
+fn metrics_after_a_flush() {
+    let man = manager_from_rect(5, 5);
+    let p = man.get_new_process("test");
+    let a = p.create(Some(yx(0, 0)), 1.0, None).unwrap();
+    p.move_droplet(a, yx(4, 4)).unwrap();
+    p.flush().unwrap();
+
+    let metrics = man.metrics();
+    assert!(metrics.ticks() > 0);
+    assert_eq!(metrics.errors(), 0);
+
+    let text = metrics.render();
+    let value = |name: &str| -> f64 {
+        let line = text.lines().find(|l| l.starts_with(name)).unwrap();
+        line.rsplit(' ').next().unwrap().parse().unwrap()
+    };
+    assert_eq!(value("puddle_droplets "), 1.0);
+    assert_eq!(value("puddle_processes "), 1.0);
+    assert!(value("puddle_route_nodes_expanded_total ") > 0.0);
+    assert!(value("puddle_plans_total ") > 0.0);
+}
+
+#[test]
+fn jobs_belong_to_their_owners() {
+    use std::time::Duration;
+
+    let man = manager_from_rect(10, 10);
+    let jobs = man.subscribe_jobs();
+    let pid = man.new_owned_process("mine", Some("alice".into())).unwrap();
+    let job = man.flush_async(pid).unwrap();
+
+    man.check_job_owner(job, "alice").unwrap();
+    assert_matches!(
+        man.check_job_owner(job, "bob"),
+        Err(PuddleError::NonExistentJob(j)) if j == job
+    );
+
+    let finished = jobs.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(finished.job, job);
+    assert_eq!(finished.owner.as_deref(), Some("alice"));
+
+    // processes without an owner have jobs anyone can see
+    let open = man.new_process("open").unwrap();
+    let job = man.flush_async(open).unwrap();
+    man.check_job_owner(job, "bob").unwrap();
+}
+
+#[test]
+fn status_while_flushing() {
+    use puddle_core::status::{Activity, Backend};
+    use std::time::Duration;
+
+    let (man, pid, _) = manager_with_long_move();
+    let man = Arc::new(man);
+
+    let status = man.status();
+    assert_eq!(status.backend, Backend::Sim);
+    assert_eq!(status.activity, Activity::Idle);
+    assert_eq!(status.last_error, None);
+
+    // a paused flush is still a flush
+    man.pause();
+    let flusher = flush_in_background(&man, pid);
+    while man.status().activity != Activity::Flushing {
+        thread::sleep(Duration::from_millis(1));
+    }
+    man.resume();
+    flusher.join().unwrap().unwrap();
+
+    let status = man.status();
+    assert_eq!(status.activity, Activity::Idle);
+    assert!(status.tick > 0);
+
+    // way too big to ever fit
+    let p = man.get_new_process("bad");
+    p.create(None, 1.0, Some(yx(20, 20))).unwrap();
+    assert_matches!(p.flush(), Err(PuddleError::ProcessFailed(_, _)));
+    assert!(man.status().last_error.is_some());
+}
@@ -2,11 +2,26 @@ import m from "mithril";
 
 const CELL_SIZE = 30;
 
+// how many live steps to hold on to before dropping the oldest
+const MAX_LIVE_STEPS = 1000;
+
 var Droplet = {
     view: function(vnode) {
         let d = vnode.attrs.droplet;
         let loc = d.location;
         let dim = d.dimensions;
+        // irregular droplets get drawn one electrode at a time
+        if (d.footprint && d.footprint.length != dim.x * dim.y) {
+            return m("div", d.footprint.map((off, i) => m(".droplet", {
+                key: i,
+                style: {
+                    left: `${(loc.x + off.x) * CELL_SIZE}px`,
+                    top: `${(loc.y + off.y) * CELL_SIZE}px`,
+                    width: `${CELL_SIZE}px`,
+                    height: `${CELL_SIZE}px`,
+                },
+            })));
+        }
         // nest in a div to avoid confusing css transitions
         return m("div", m(".droplet", {
             key: d.id.id,
@@ -68,6 +83,10 @@ function Board() {
             if (!data || data.length == 0) {
                 return m("div", "no data yet");
             }
+            // stay on the newest step as they come in
+            if (vnode.attrs.follow) {
+                i = data.length - 1;
+            }
             console.log(data[i]);
             console.log("Droplets: " + data[i].droplets.map(d => d.id.id));
             let droplets = data[i].droplets.map(d => m(Droplet, {droplet: d}));
@@ -95,4 +114,48 @@ function Board() {
     }
 }
 
-export { Board as default };
+// Watches a server's step stream, calling onStep with each step as the
+// board takes it. Returns the EventSource, so the caller can close it.
+function watchSteps(url, onStep) {
+    let source = new EventSource(url);
+    source.onmessage = function(event) {
+        onStep(JSON.parse(event.data));
+        m.redraw();
+    };
+    source.onerror = function(event) {
+        // the browser reconnects on its own
+        console.warn("lost the step stream, retrying", event);
+    };
+    return source;
+}
+
+// A board that follows a running server, like `puddle-server`'s /steps.
+// If the server checks tokens, pass one in as the `token` attribute; an
+// EventSource can't set headers, so it goes in the query string.
+function LiveBoard() {
+    let steps = [];
+    let source;
+
+    return {
+        oncreate: function(vnode) {
+            let url = vnode.attrs.url || "/steps";
+            if (vnode.attrs.token) {
+                url += "?token=" + encodeURIComponent(vnode.attrs.token);
+            }
+            source = watchSteps(url, step => {
+                steps.push(step);
+                if (steps.length > MAX_LIVE_STEPS) {
+                    steps.shift();
+                }
+            });
+        },
+        onremove: function() {
+            source.close();
+        },
+        view: function() {
+            return m(Board, {data: steps, follow: true});
+        }
+    }
+}
+
+export { Board as default, LiveBoard, watchSteps };
@@ -3,9 +3,11 @@ use web_sys::console;
 
 use serde::Deserialize;
 
+use std::convert::TryFrom;
+
 use puddle_core::{
-    grid::{DropletId, Grid, Location},
-    process::{Manager, ProcessHandle, ProcessId},
+    grid::{parse::ParsedGrid, DropletId, Grid, Location},
+    process::{Manager, Operation, ProcessHandle, ProcessId},
 };
 
 #[wasm_bindgen]
@@ -33,9 +35,23 @@ pub struct CreateArgs {
 impl System {
     #[wasm_bindgen]
     pub fn new() -> System {
+        System::with_grid(Grid::rectangle(10, 10))
+    }
+
+    /// Makes a system on a real board, given in the same form as a grid
+    /// file.
+    #[wasm_bindgen(js_name = fromGrid)]
+    pub fn from_grid(grid: JsValue) -> Result<System, JsValue> {
+        let grid: ParsedGrid = grid.into_serde().map_err(stringify)?;
+        let grid = Grid::try_from(grid).map_err(stringify)?;
+        Ok(System::with_grid(grid))
+    }
+
+    fn with_grid(grid: Grid) -> System {
         let blocking = false;
-        let grid = Grid::rectangle(10, 10);
         let manager = Manager::new(blocking, grid);
+        // the page draws from getLogs
+        manager.keep_steps();
         let pid = manager.new_process("js").unwrap();
         System { manager, pid }
     }
@@ -44,36 +60,124 @@ impl System {
         self.manager.get_process(self.pid).map_err(stringify)
     }
 
+    fn droplet(d: JsValue) -> Result<DropletId, JsValue> {
+        d.into_serde().map_err(stringify)
+    }
+
+    /// Any operation from the table in `puddle_core::process::ops`, like
+    /// `{op: "mix", droplets: [a, b]}`. The named methods all go through
+    /// here.
+    #[wasm_bindgen]
+    pub fn apply(&mut self, op: JsValue) -> Result<JsValue, JsValue> {
+        let op: Operation = op.into_serde().map_err(stringify)?;
+        self.apply_op(op)
+    }
+
+    fn apply_op(&mut self, op: Operation) -> Result<JsValue, JsValue> {
+        let p = self.get_process()?;
+        p.apply(op).map(serialize).map_err(stringify)
+    }
+
     #[wasm_bindgen]
     pub fn create(&mut self, args: JsValue) -> Result<JsValue, JsValue> {
         let args: CreateArgs = args.into_serde().map_err(stringify)?;
-        let p = self.get_process()?;
-        p.create(args.location, args.vol, args.dim)
-            .map(serialize)
-            .map_err(stringify)
+        self.apply_op(Operation::Create {
+            location: args.location,
+            volume: args.vol,
+            dimensions: args.dim,
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn input(&mut self, port: String, volume: f64, dim: JsValue) -> Result<JsValue, JsValue> {
+        let dimensions: Location = dim.into_serde().map_err(stringify)?;
+        self.apply_op(Operation::Input {
+            port,
+            volume,
+            dimensions,
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn output(&mut self, port: String, d: JsValue) -> Result<JsValue, JsValue> {
+        let droplet = System::droplet(d)?;
+        self.apply_op(Operation::Output { port, droplet })
+    }
+
+    #[wasm_bindgen(js_name = moveDroplet)]
+    pub fn move_droplet(&mut self, d: JsValue, to: JsValue) -> Result<JsValue, JsValue> {
+        let droplet = System::droplet(d)?;
+        let to: Location = to.into_serde().map_err(stringify)?;
+        self.apply_op(Operation::Move { droplet, to })
     }
 
     #[wasm_bindgen]
     pub fn mix(&mut self, d1: JsValue, d2: JsValue) -> Result<JsValue, JsValue> {
-        let d1: DropletId = d1.into_serde().map_err(stringify)?;
-        let d2: DropletId = d2.into_serde().map_err(stringify)?;
-        let p = self.get_process()?;
-        p.mix(d1, d2).map(serialize).map_err(stringify)
+        let droplets = (System::droplet(d1)?, System::droplet(d2)?);
+        self.apply_op(Operation::Mix { droplets })
+    }
+
+    #[wasm_bindgen]
+    pub fn combine(&mut self, d1: JsValue, d2: JsValue) -> Result<JsValue, JsValue> {
+        let droplets = (System::droplet(d1)?, System::droplet(d2)?);
+        self.apply_op(Operation::Combine { droplets })
+    }
+
+    #[wasm_bindgen(js_name = combineInto)]
+    pub fn combine_into(&mut self, d1: JsValue, d2: JsValue) -> Result<JsValue, JsValue> {
+        let droplets = (System::droplet(d1)?, System::droplet(d2)?);
+        self.apply_op(Operation::CombineInto { droplets })
+    }
+
+    #[wasm_bindgen]
+    pub fn agitate(&mut self, d: JsValue) -> Result<JsValue, JsValue> {
+        let droplet = System::droplet(d)?;
+        self.apply_op(Operation::Agitate { droplet })
     }
 
     #[wasm_bindgen]
     pub fn split(&mut self, d: JsValue) -> Result<JsValue, JsValue> {
-        let d: DropletId = d.into_serde().map_err(stringify)?;
-        let p = self.get_process()?;
-        p.split(d).map(serialize).map_err(stringify)
+        let droplet = System::droplet(d)?;
+        self.apply_op(Operation::Split { droplet })
+    }
+
+    #[wasm_bindgen]
+    pub fn heat(&mut self, d: JsValue, temperature: f32, seconds: f64) -> Result<JsValue, JsValue> {
+        let droplet = System::droplet(d)?;
+        self.apply_op(Operation::Heat {
+            droplet,
+            temperature,
+            seconds,
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn sense(&mut self, d: JsValue, sensor: String) -> Result<JsValue, JsValue> {
+        let droplet = System::droplet(d)?;
+        self.apply_op(Operation::Sense { droplet, sensor })
     }
 
     #[wasm_bindgen]
     pub fn flush(&mut self) -> Result<JsValue, JsValue> {
-        let p = self.get_process()?;
-        p.flush()
-            .map_err(stringify)
-            .map(|info| JsValue::from_serde(&info).unwrap())
+        self.apply_op(Operation::Flush)
+    }
+
+    #[wasm_bindgen(js_name = setQuota)]
+    pub fn set_quota(&mut self, quota: Option<u32>) -> Result<JsValue, JsValue> {
+        let quota = quota.map(|q| q as usize);
+        self.apply_op(Operation::SetQuota { quota })
+    }
+
+    #[wasm_bindgen(js_name = getGrid)]
+    pub fn get_grid(&self) -> JsValue {
+        serialize(self.manager.get_grid())
+    }
+
+    /// Swaps in a new board. Only works when nothing is left to run.
+    #[wasm_bindgen(js_name = loadGrid)]
+    pub fn load_grid(&mut self, grid: JsValue) -> Result<(), JsValue> {
+        let grid: ParsedGrid = grid.into_serde().map_err(stringify)?;
+        self.manager.load_grid(grid).map_err(stringify)
     }
 
     #[wasm_bindgen(js_name = getLogs)]
@@ -82,3 +186,32 @@ impl System {
         JsValue::from_serde(&logs).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use puddle_core::process::OPERATIONS;
+
+    /// The JS method for each operation, which wasm_bindgen can't list for
+    /// us. Keep it in step with the methods above.
+    const METHODS: &[(&str, &str)] = &[
+        ("create", "create"),
+        ("input", "input"),
+        ("output", "output"),
+        ("move", "moveDroplet"),
+        ("mix", "mix"),
+        ("combine", "combine"),
+        ("combine_into", "combineInto"),
+        ("agitate", "agitate"),
+        ("split", "split"),
+        ("heat", "heat"),
+        ("sense", "sense"),
+        ("flush", "flush"),
+        ("set_quota", "setQuota"),
+    ];
+
+    #[test]
+    fn every_operation_has_a_method() {
+        let ops: Vec<_> = METHODS.iter().map(|&(op, _)| op).collect();
+        assert_eq!(ops, OPERATIONS);
+    }
+}
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::error::Error;
 use std::time::Instant;
 
@@ -9,7 +10,7 @@ use puddle_core::{
     grid::gridview::GridView,
     grid::location::yx,
     grid::parse::ParsedGrid,
-    grid::{Grid, Location},
+    grid::{Footprint, Grid, Location},
     util::seconds_duration,
 };
 use puddle_pi::{RaspberryPi, Settings};
@@ -117,7 +118,10 @@ fn main() -> RunResult<()> {
     debug!("Pi made!");
 
     let parsed_grid: ParsedGrid = conf.try_into()?;
-    let grid = parsed_grid.into();
+    for d in parsed_grid.validate() {
+        warn!("{}", d);
+    }
+    let grid = Grid::try_from(parsed_grid)?;
     debug!("Grid made!");
 
     use SubCommand::*;
@@ -154,7 +158,7 @@ fn mk_gridview(grid: Grid, blobs: &[SimpleBlob]) -> GridView {
 fn blob(location: Location, dimensions: Location) -> SimpleBlob {
     SimpleBlob {
         location,
-        dimensions,
+        footprint: Footprint::rectangle(dimensions),
         volume: 0.0,
     }
 }
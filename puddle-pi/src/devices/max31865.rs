@@ -97,6 +97,7 @@ impl Settings {
 
         let mut max = Max31865 {
             spi,
+            select: self.select,
             n_samples: self.n_samples,
             resist_ref: self.resist_ref,
             resist_zero: self.resist_zero,
@@ -110,6 +111,8 @@ impl Settings {
 
 pub struct Max31865 {
     spi: Spi,
+    /// The chip select it's on, which is how the grid names it.
+    pub select: u8,
     n_samples: u32,
     resist_ref: f32,
     resist_zero: f32,
@@ -4,7 +4,9 @@ use log::*;
 use serde::Deserialize;
 
 use puddle_core::grid::gridview::GridView;
-use puddle_core::grid::{location::yx, Peripheral};
+use puddle_core::grid::Peripheral;
+use puddle_core::hardware::Hardware;
+use puddle_core::status::HardwareStatus;
 
 pub mod devices;
 mod error;
@@ -40,6 +42,15 @@ impl Settings {
         info!("{:#?}", settings);
         Ok(settings)
     }
+
+    /// Reads the settings from a config file, letting environment
+    /// variables like `PI__HV507__DUTY_CYCLE` override it.
+    pub fn load(path: &str) -> Result<Self> {
+        let mut conf = config::Config::new();
+        conf.merge(config::File::with_name(path))?;
+        conf.merge(config::Environment::new().separator("__"))?;
+        Settings::from_config(&mut conf)
+    }
 }
 
 pub struct RaspberryPi {
@@ -63,6 +74,50 @@ impl RaspberryPi {
         Ok(pi)
     }
 
+    /// Checks on the devices. The HV507 is set up when the pi is made, so
+    /// that only fails by not getting this far; see `PiHardware` for that.
+    pub fn status(&mut self) -> HardwareStatus {
+        let temperature = match &mut self.max31865 {
+            Some(max31865) => match max31865.read_one_temperature() {
+                Ok(t) => Some(t),
+                Err(err) => {
+                    warn!("Couldn't read the heater temperature: {}", err);
+                    None
+                }
+            },
+            None => None,
+        };
+        HardwareStatus {
+            hv507_initialized: true,
+            error: None,
+            heater_readable: temperature.is_some(),
+            heater_temperature: temperature,
+        }
+    }
+
+    /// Reads a sensor from the grid. The MAX31865 is the only SPI device, so
+    /// the sensor's channel has to be its chip select.
+    pub fn read_sensor(&mut self, sensor: &Peripheral) -> Option<f64> {
+        let channel = match sensor {
+            Peripheral::Sensor { spi_channel, .. } => *spi_channel,
+            _ => return None,
+        };
+        let max31865 = match &mut self.max31865 {
+            Some(max31865) if max31865.select == channel => max31865,
+            _ => {
+                warn!("No sensor on SPI channel {}", channel);
+                return None;
+            }
+        };
+        match max31865.read_temperature() {
+            Ok(t) => Some(t.into()),
+            Err(err) => {
+                warn!("Couldn't read sensor on SPI channel {}: {}", channel, err);
+                None
+            }
+        }
+    }
+
     pub fn heat(
         &mut self,
         _heater: &Peripheral,
@@ -170,22 +225,14 @@ impl RaspberryPi {
 
         // set pins to high if there's a droplet on that electrode
         for d in gv.droplets.values() {
-            for i in 0..d.dimensions.y {
-                for j in 0..d.dimensions.x {
-                    let loc = yx(d.location.y + i, d.location.x + j);
-                    let electrode = gv
-                        .grid
-                        .get_cell(loc)
-                        .unwrap_or_else(|| panic!("Couldn't find electrode for {}", loc));
-                    pins[electrode.pin as usize] = 1;
-                    self.hv507.set_pin_hi(electrode.pin as usize);
-                    trace!(
-                        "Setting pin {} at ({}, {})",
-                        electrode.pin,
-                        d.location.y + i,
-                        d.location.x + j,
-                    );
-                }
+            for loc in d.locations() {
+                let electrode = gv
+                    .grid
+                    .get_cell(loc)
+                    .unwrap_or_else(|| panic!("Couldn't find electrode for {}", loc));
+                pins[electrode.pin as usize] = 1;
+                self.hv507.set_pin_hi(electrode.pin as usize);
+                trace!("Setting pin {} at {}", electrode.pin, loc);
             }
         }
 
@@ -241,6 +288,52 @@ impl RaspberryPi {
     }
 }
 
+/// The pi as the manager sees it. Making the pi can fail, and that
+/// shouldn't take the server down with it, so this holds on to the error
+/// and reports it in the status instead.
+pub struct PiHardware {
+    pi: std::result::Result<RaspberryPi, String>,
+}
+
+impl PiHardware {
+    pub fn new(settings: Settings) -> PiHardware {
+        let pi = RaspberryPi::new(settings).map_err(|err| {
+            error!("Couldn't initialize the pi: {}", err);
+            err.to_string()
+        });
+        PiHardware { pi }
+    }
+}
+
+impl Hardware for PiHardware {
+    fn output_pins(&mut self, gv: &GridView) {
+        // without a pi there's nothing to drive, and the status keeps
+        // flushes from getting this far
+        if let Ok(pi) = &mut self.pi {
+            pi.output_pins(gv)
+        }
+    }
+
+    fn status(&mut self) -> HardwareStatus {
+        match &mut self.pi {
+            Ok(pi) => pi.status(),
+            Err(err) => HardwareStatus {
+                hv507_initialized: false,
+                error: Some(err.clone()),
+                heater_readable: false,
+                heater_temperature: None,
+            },
+        }
+    }
+
+    fn read_sensor(&mut self, sensor: &Peripheral) -> Option<f64> {
+        match &mut self.pi {
+            Ok(pi) => pi.read_sensor(sensor),
+            Err(_) => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -50,12 +50,23 @@ class Droplet:
                                       other._use())
         return self._new(result_id)
 
+    def combine(self, other):
+        assert isinstance(other, type(self))
+        result_id = self.session._rpc("combine", self.session.pid,
+                                      self._use(), other._use())
+        return self._new(result_id)
+
     def combine_into(self, other):
         assert isinstance(other, type(self))
         result_id = self.session._rpc("combine_into", self.session.pid,
                                       self._use(), other._use())
         return self._new(result_id)
 
+    def agitate(self):
+        result_id = self.session._rpc("agitate", self.session.pid,
+                                      self._use())
+        self._renew(result_id)
+
     def split(self):
         id1, id2 = self.session._rpc("split", self.session.pid, self._use())
         return (self._new(id1), self._new(id2))
@@ -92,10 +103,16 @@ class Session:
 
     json_headers = {'content-type': 'application/json'}
 
-    def __init__(self, endpoint, name):
+    def __init__(self, endpoint, name, token=None):
         self.endpoint = endpoint
         self.next_id = 0
 
+        # servers started with --tokens want one of them on every call
+        token = token or os.environ.get('PUDDLE_TOKEN')
+        self.headers = dict(Session.json_headers)
+        if token:
+            self.headers['Authorization'] = 'Bearer ' + token
+
         status_check = endpoint + '/status'
 
         max_attempts = 10
@@ -137,7 +154,7 @@ class Session:
         try:
             response = requests.post(
                 self.endpoint + '/rpc',
-                headers=Session.json_headers,
+                headers=self.headers,
                 data=json.dumps(data),
             )
         except requests.RequestException as exn:
@@ -174,12 +191,86 @@ class Session:
         dlist = self._rpc("droplet_info", self.pid)
         return {d['id']['id']: d for d in dlist}
 
+    # add a whole protocol in one request, either all of it or none of it.
+    # steps are protocol steps, like {'op': 'mix', 'droplets': ['a', 'b'],
+    # 'out': 'c'}, and can use the given droplets by name. returns the
+    # droplets left at the end by name
+    def submit(self, steps, droplets=None):
+        droplets = droplets or {}
+        ids = {name: d._use() for name, d in droplets.items()}
+        try:
+            result = self._rpc("submit", self.pid, steps, ids)
+        except RPCError:
+            # nothing was added, so the droplets are still good
+            for d in droplets.values():
+                d.valid = True
+            raise
+        return {
+            name: Droplet(self, id, i_know_what_im_doing=True)
+            for name, id in result.items()
+        }
+
+    # start flushing without waiting for it, returns a job id for wait
+    def flush_async(self):
+        return self._rpc("flush_async", self.pid)
+
+    def job_status(self, job):
+        return self._rpc("job_status", job)
+
+    # waits up to timeout seconds, returning the job's status either way
+    def wait(self, job, timeout=10.0):
+        return self._rpc("wait", job, timeout)
+
+    # the board in the same form as a grid file
+    def get_grid(self):
+        return self._rpc("get_grid")
+
+    # swap in a new grid, only works when nothing is left to run; the
+    # server's status calls it by name from then on
+    def load_grid(self, grid, name):
+        self._rpc("load_grid", grid, name)
+
+    def list_peripherals(self):
+        return self._rpc("list_peripherals")
+
+    # have the server read its grid file again, returns any warnings
+    def reload_grid(self):
+        return self._rpc("reload_grid")
+
+    # the whole board as of the last tick, without flushing
+    def snapshot(self):
+        return self._rpc("snapshot")
+
+    def status(self):
+        resp = requests.get(self.endpoint + '/status')
+        resp.raise_for_status()
+        return resp.json()
+
     def _flush(self):
         self._rpc("flush", self.pid)
 
     def close(self):
         self._rpc("close_process", self.pid)
 
+    # these pause the whole board, not just this session
+    def pause(self):
+        self._rpc("pause")
+
+    def resume(self):
+        self._rpc("resume")
+
+    def step(self, ticks=1):
+        self._rpc("step", ticks)
+
+    # stop without finishing, throwing away this session's droplets
+    def kill(self):
+        self._rpc("kill_process", self.pid)
+
+    # limit how many electrodes this session's droplets can take up, None
+    # lifts the limit
+    def set_quota(self, quota):
+        self._rpc("set_quota", self.pid, quota)
+
     def create(self, location, volume=1.0, dimensions=(1, 1), **kwargs):
         droplet_class = kwargs.pop('droplet_class', Droplet)
         result_id = self._rpc("create", self.pid,
@@ -197,6 +288,13 @@ class Session:
         result_id = self._rpc("heat", self.pid, droplet._use(), temp, seconds)
         return Droplet(self, result_id, **kwargs, i_know_what_im_doing=True)
 
+    # returns the droplet (with a new id) and the sensor's reading, a dict
+    # with the 'value' and whether it's 'simulated', in which case the value
+    # is just the droplet's volume
+    def sense(self, droplet, sensor, **kwargs):
+        result_id, reading = self._rpc("sense", self.pid, droplet._use(), sensor)
+        return Droplet(self, result_id, **kwargs, i_know_what_im_doing=True), reading
+
     # just call the droplet methods
     def move(self, droplet, *args, **kwargs):
         return droplet.move(*args, **kwargs)
@@ -204,9 +302,15 @@ class Session:
     def mix(self, droplet, *args, **kwargs):
         return droplet.mix(*args, **kwargs)
 
+    def combine(self, droplet, *args, **kwargs):
+        return droplet.combine(*args, **kwargs)
+
     def combine_into(self, droplet, *args, **kwargs):
         return droplet.combine_into(*args, **kwargs)
 
+    def agitate(self, droplet, *args, **kwargs):
+        return droplet.agitate(*args, **kwargs)
+
     def split(self, droplet, *args, **kwargs):
         return droplet.split(*args, **kwargs)
 
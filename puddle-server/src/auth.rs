@@ -0,0 +1,166 @@
+//! Who is calling the RPC server.
+//!
+//! The tokens file is YAML mapping each token to the user it belongs to,
+//! along with the admins, who can do things that affect everyone, like
+//! pausing the board or swapping the grid:
+//!
+//! ```yaml
+//! tokens:
+//!   6b1f0c2e9a: alice
+//!   d47a93bb10: bob
+//! admins: [alice]
+//! ```
+//!
+//! A file with just the tokens, and no admins, works too. Clients send
+//! theirs as `Authorization: Bearer <token>`, or as a `token` query
+//! parameter for things like `EventSource` that can't set headers.
+
+use std::error::Error;
+use std::fs::File;
+
+use indexmap::{IndexMap, IndexSet};
+use jsonrpc_http_server::hyper::{header::AUTHORIZATION, Body, Request};
+use serde::Deserialize;
+
+/// What each RPC call knows about who made it.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// None when the server doesn't check tokens.
+    pub user: Option<String>,
+    pub admin: bool,
+}
+
+impl Session {
+    /// Whether the caller can do things that affect everyone. Without a
+    /// tokens file, anyone can.
+    pub fn is_admin(&self) -> bool {
+        self.user.is_none() || self.admin
+    }
+}
+
+impl jsonrpc_core::Metadata for Session {}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokensFile {
+    WithAdmins {
+        tokens: IndexMap<String, String>,
+        #[serde(default)]
+        admins: IndexSet<String>,
+    },
+    Plain(IndexMap<String, String>),
+}
+
+pub struct Tokens {
+    users: IndexMap<String, String>,
+    admins: IndexSet<String>,
+}
+
+impl From<TokensFile> for Tokens {
+    fn from(file: TokensFile) -> Tokens {
+        match file {
+            TokensFile::WithAdmins { tokens, admins } => Tokens {
+                users: tokens,
+                admins,
+            },
+            TokensFile::Plain(users) => Tokens {
+                users,
+                admins: IndexSet::new(),
+            },
+        }
+    }
+}
+
+impl Tokens {
+    pub fn load(path: &str) -> Result<Tokens, Box<dyn Error>> {
+        let reader = File::open(path)?;
+        let file: TokensFile = serde_yaml::from_reader(reader)?;
+        Ok(Tokens::from(file))
+    }
+
+    /// The user whose token the request carries, if it has a good one.
+    pub fn user(&self, req: &Request<Body>) -> Option<&str> {
+        let token = bearer_token(req).or_else(|| query_token(req))?;
+        self.users.get(token).map(String::as_str)
+    }
+
+    pub fn session(&self, req: &Request<Body>) -> Session {
+        let user = self.user(req);
+        Session {
+            user: user.map(String::from),
+            admin: match user {
+                Some(user) => self.admins.contains(user),
+                None => false,
+            },
+        }
+    }
+}
+
+fn bearer_token(req: &Request<Body>) -> Option<&str> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = header.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some("Bearer"), Some(token)) => Some(token.trim()),
+        _ => None,
+    }
+}
+
+fn query_token(req: &Request<Body>) -> Option<&str> {
+    let query = req.uri().query()?;
+    query.split('&').find_map(|pair| {
+        let mut parts = pair.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("token"), Some(token)) => Some(token),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(auth: &str) -> Request<Body> {
+        Request::builder()
+            .header(AUTHORIZATION, auth)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bearer_tokens() {
+        let users = vec![("secret".to_string(), "alice".to_string())];
+        let tokens = Tokens {
+            users: users.into_iter().collect(),
+            admins: IndexSet::new(),
+        };
+
+        assert_eq!(tokens.user(&request("Bearer secret")), Some("alice"));
+        assert_eq!(tokens.user(&request("Bearer wrong")), None);
+        assert_eq!(tokens.user(&request("Basic secret")), None);
+        assert_eq!(tokens.user(&Request::new(Body::empty())), None);
+
+        let query = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+        assert_eq!(tokens.user(&query("/steps?token=secret")), Some("alice"));
+        assert_eq!(
+            tokens.user(&query("/steps?a=b&token=secret")),
+            Some("alice")
+        );
+        assert_eq!(tokens.user(&query("/steps?token=wrong")), None);
+        assert_eq!(tokens.user(&query("/steps?tokens=secret")), None);
+    }
+
+    #[test]
+    fn test_admins() {
+        let yaml = "tokens: {secret: alice, other: bob}\nadmins: [alice]";
+        let tokens = Tokens::from(serde_yaml::from_str::<TokensFile>(yaml).unwrap());
+
+        assert!(tokens.session(&request("Bearer secret")).is_admin());
+        assert!(!tokens.session(&request("Bearer other")).is_admin());
+
+        // the old format is still just tokens
+        let plain = Tokens::from(serde_yaml::from_str::<TokensFile>("secret: alice").unwrap());
+        assert_eq!(plain.user(&request("Bearer secret")), Some("alice"));
+        assert!(!plain.session(&request("Bearer secret")).is_admin());
+    }
+}
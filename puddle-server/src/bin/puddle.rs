@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::time::Instant;
+
+use log::*;
+use structopt::StructOpt;
+
+use puddle_core::{
+    plan::analyze::Analysis, prelude::*, protocol::Protocol, util::duration_seconds,
+};
+use puddle_server::load_grid;
+
+/// Runs a protocol on a simulated board, without starting a server.
+#[derive(StructOpt, Debug)]
+struct Args {
+    /// The grid to run on, "-" means stdin
+    #[structopt(long = "grid")]
+    grid_file: String,
+    /// Write a JSON log of every step to this file
+    #[structopt(long = "log")]
+    log_file: Option<String>,
+    /// Record everything to this file, so it can be checked with replay
+    #[structopt(long = "record")]
+    record_file: Option<String>,
+    /// Set a protocol parameter, overriding its default
+    #[structopt(
+        long = "param",
+        number_of_values = 1,
+        parse(try_from_str = "parse_param")
+    )]
+    params: Vec<(String, f64)>,
+    /// Just check that the protocol fits on the grid, without running it
+    #[structopt(long = "check")]
+    check: bool,
+    /// The protocol to run, in YAML or JSON, or a program if it ends in
+    /// ".puddle"
+    protocol_file: String,
+}
+
+fn parse_param(s: &str) -> Result<(String, f64), String> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(value)) => {
+            let value = value
+                .parse()
+                .map_err(|e| format!("bad value for '{}': {}", name, e))?;
+            Ok((name.to_string(), value))
+        }
+        _ => Err(format!("expected name=value, got '{}'", s)),
+    }
+}
+
+fn print_analysis(analysis: &Analysis, names: &HashMap<DropletId, &str>) {
+    let name = |id: &DropletId| match names.get(id) {
+        Some(name) => name.to_string(),
+        None => format!("{:?}", id),
+    };
+    let unconsumed: Vec<_> = analysis.unconsumed.iter().map(name).collect();
+    println!("left on the board: {}", unconsumed.join(", "));
+    println!(
+        "electrodes needed: {} at least, {} at peak, {} usable",
+        analysis.min_occupancy, analysis.peak_occupancy, analysis.usable_electrodes
+    );
+    for demand in &analysis.peripherals {
+        println!(
+            "{:?}: {} uses, {} at once, {} available",
+            demand.peripheral, demand.uses, demand.peak, demand.available
+        );
+    }
+    for problem in &analysis.problems {
+        println!("error: {}", problem);
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let _ = env_logger::try_init();
+    let args = Args::from_args();
+
+    let grid = load_grid(&args.grid_file)?;
+    let params = args.params.iter().cloned().collect();
+    let is_program = args.protocol_file.ends_with(".puddle");
+
+    let blocking = false;
+    let manager = Manager::offline(blocking, grid);
+    if args.log_file.is_some() {
+        manager.keep_steps();
+    }
+    if let Some(record_file) = &args.record_file {
+        manager.record(record_file)?;
+    }
+
+    let p = manager.get_new_process(args.protocol_file.as_str());
+    let env = if is_program {
+        let src = std::fs::read_to_string(&args.protocol_file)?;
+        p.load_program(&src, &params)?
+    } else {
+        let protocol: Protocol = serde_yaml::from_reader(File::open(&args.protocol_file)?)?;
+        debug!("Protocol '{}' parsed.", protocol.name);
+        p.load(&protocol, &params)?
+    };
+
+    if args.check {
+        let analysis = manager.analyze();
+        let names = env.iter().map(|(name, id)| (*id, name.as_str())).collect();
+        print_analysis(&analysis, &names);
+        if !analysis.fits() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let start = Instant::now();
+    let info = p.flush()?;
+    let wall_time = start.elapsed();
+
+    println!("Ran {}", args.protocol_file);
+    for (name, id) in &env {
+        if let Some(d) = info.iter().find(|d| d.id == *id) {
+            println!(
+                "  {:10} at {}, volume {}, dimensions {}",
+                name, d.location, d.volume, d.dimensions
+            );
+        }
+    }
+
+    let stats = manager.stats();
+    println!("ticks:    {:10}", stats.ticks);
+    println!("plans:    {:10}", stats.plan.n_plans);
+    let times = [
+        ("schedule", stats.plan.schedule),
+        ("place", stats.plan.place),
+        ("route", stats.plan.route),
+        ("execute", stats.execute),
+        ("total", wall_time),
+    ];
+    for (phase, duration) in times.iter() {
+        println!(
+            "{:9} {:10.6} s",
+            format!("{}:", phase),
+            duration_seconds(duration)
+        );
+    }
+
+    if let Some(log_file) = &args.log_file {
+        let file = File::create(log_file)?;
+        serde_json::to_writer_pretty(file, &manager.get_logs())?;
+        println!("Logged {} steps to {}", manager.get_logs().len(), log_file);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use std::process::exit;
+
+use puddle_core::record;
+use structopt::StructOpt;
+
+/// Runs a recording again, exiting with an error if anything the system did
+/// comes out different.
+#[derive(StructOpt, Debug)]
+struct Args {
+    /// The recording, made with --record or PUDDLE_RECORD
+    recording: String,
+}
+
+fn main() {
+    let _ = env_logger::try_init();
+    let args = Args::from_args();
+
+    let events = match record::load(&args.recording) {
+        Ok(events) => events,
+        Err(err) => {
+            eprintln!("{}: failed to load: {}", args.recording, err);
+            exit(1)
+        }
+    };
+
+    match record::replay(events) {
+        Ok(n) => println!("{}: ok, {} outcomes matched", args.recording, n),
+        Err(err) => {
+            eprintln!("{}: {}", args.recording, err);
+            exit(1)
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::process::exit;
+
+use puddle_server::parse_grid;
+use structopt::StructOpt;
+
+/// Checks grid files for problems, exiting with an error if any are found.
+#[derive(StructOpt, Debug)]
+struct Args {
+    /// Grid files to check, "-" means stdin
+    #[structopt(required = true)]
+    grid_files: Vec<String>,
+    /// Treat warnings as errors
+    #[structopt(long = "strict")]
+    strict: bool,
+}
+
+fn main() {
+    let _ = env_logger::try_init();
+    let args = Args::from_args();
+
+    let mut failed = false;
+    for grid_file in &args.grid_files {
+        let diagnostics = match parse_grid(grid_file) {
+            Ok((_, diagnostics)) => diagnostics,
+            Err(err) => {
+                println!("{}: failed to parse: {}", grid_file, err);
+                failed = true;
+                continue;
+            }
+        };
+
+        for d in &diagnostics {
+            println!("{}: {}", grid_file, d);
+            failed |= d.is_error() || args.strict;
+        }
+
+        if diagnostics.is_empty() {
+            println!("{}: ok", grid_file);
+        }
+    }
+
+    if failed {
+        exit(1)
+    }
+}
@@ -1,22 +1,32 @@
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fs::File;
-use std::sync::Arc;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
-use jsonrpc_core::IoHandler;
+use jsonrpc_core::MetaIoHandler;
 use jsonrpc_http_server::{
     hyper::{Body, Method, Request, Response},
     RequestMiddlewareAction, ServerBuilder,
 };
 
+use puddle_core::grid::{parse::ParsedGrid, validate::Diagnostic};
 use puddle_core::prelude::{Grid, Manager};
+use puddle_core::status::Status;
 
 use hyper_staticfile::Static;
+use serde::Serialize;
 use structopt::StructOpt;
 
-use futures::Future;
+use futures::{sync::mpsc, Future, Stream};
 
+mod auth;
 mod rpc;
-use rpc::Rpc;
+use auth::{Session, Tokens};
+use rpc::{Context, Rpc};
 
 use log::*;
 
@@ -32,9 +42,100 @@ pub struct Server {
     grid_file: String,
     #[structopt(long = "sync")]
     should_sync: bool,
+    /// YAML file mapping RPC tokens to users, see `auth`. Without one,
+    /// anyone can use any process.
+    #[structopt(long = "tokens")]
+    tokens_file: Option<String>,
+    /// Config file for the Raspberry Pi, see `puddle_pi::Settings`. With
+    /// one, the server drives the board and `/status` reports on it.
+    #[cfg(feature = "pi")]
+    #[structopt(long = "pi-config")]
+    pi_config: Option<String>,
 }
 
-fn serve(req: Request<Body>, statik: &Static) -> RequestMiddlewareAction {
+/// What `/status` says: the manager's status, and which grid it's on. The
+/// hardware is only there when the server was started with `--pi-config`.
+#[derive(Serialize)]
+struct ServerStatus<'a> {
+    grid: &'a str,
+    #[serde(flatten)]
+    status: Status,
+}
+
+/// The grid's name is its file name, without the extension.
+fn grid_name(grid_file: &str) -> &str {
+    Path::new(grid_file)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(grid_file)
+}
+
+fn to_json(event: &impl Serialize) -> String {
+    serde_json::to_string(event).expect("Failed to serialize event")
+}
+
+/// How often an idle event stream sends a comment, so a client that's gone
+/// away gets noticed even when nothing is happening.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// How many events a client can fall behind by before it starts missing
+/// them, so a slow client doesn't pile them up.
+const STREAM_BUFFER: usize = 64;
+
+/// Streams JSON events as Server-Sent Events, so a browser can watch with
+/// an `EventSource`. Events that `render` gives None for are left out.
+fn stream_events<T: Send + 'static>(
+    events: Receiver<T>,
+    render: impl Fn(T) -> Option<String> + Send + 'static,
+) -> RequestMiddlewareAction {
+    let (mut tx, rx) = mpsc::channel(STREAM_BUFFER);
+
+    // the events block, so forward them from another thread until the
+    // client goes away
+    thread::spawn(move || loop {
+        let chunk = match events.recv_timeout(KEEPALIVE) {
+            Ok(event) => match render(event) {
+                Some(json) => format!("data: {}\n\n", json),
+                None => continue,
+            },
+            Err(RecvTimeoutError::Timeout) => ": keepalive\n\n".to_string(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        match tx.try_send(chunk) {
+            Ok(()) => (),
+            Err(ref err) if err.is_full() => warn!("event stream is behind, dropping an event"),
+            Err(_) => {
+                debug!("event stream closed");
+                break;
+            }
+        }
+    });
+
+    let body = Body::wrap_stream(rx.map_err(|()| "event stream failed"));
+    Response::builder()
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(body)
+        .unwrap()
+        .into()
+}
+
+fn unauthorized() -> RequestMiddlewareAction {
+    Response::builder()
+        .status(401)
+        .header("WWW-Authenticate", "Bearer")
+        .body("missing or unknown token".into())
+        .unwrap()
+        .into()
+}
+
+fn serve(
+    req: Request<Body>,
+    statik: &Static,
+    manager: &Manager,
+    current_grid: &Mutex<String>,
+    tokens: Option<&Tokens>,
+) -> RequestMiddlewareAction {
     let path = req.uri().path();
 
     trace!("{:?}", req);
@@ -48,16 +149,61 @@ fn serve(req: Request<Body>, statik: &Static) -> RequestMiddlewareAction {
             .into();
     }
 
+    // everything but the status and the static files needs a good token,
+    // if the server checks them
+    let user = tokens
+        .and_then(|tokens| tokens.user(&req))
+        .map(String::from);
+    let needs_token = match path {
+        "/rpc" | "/metrics" | "/steps" | "/jobs" => tokens.is_some(),
+        _ => false,
+    };
+    if needs_token && user.is_none() {
+        warn!("{} without a good token", path);
+        return unauthorized();
+    }
+
     let method = req.method();
     match (path, method) {
         ("/status", _) => {
-            debug!("returning status ok");
-            Response::new("Server running OK.".into()).into()
+            debug!("returning status");
+            let grid = current_grid.lock().unwrap();
+            let status = ServerStatus {
+                grid: &grid,
+                status: manager.status(),
+            };
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(to_json(&status).into())
+                .unwrap()
+                .into()
+        }
+        ("/metrics", &Method::GET) => {
+            debug!("returning metrics");
+            Response::builder()
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(manager.metrics().render().into())
+                .unwrap()
+                .into()
         }
         ("/rpc", _) => {
             debug!("rpc");
             req.into()
         }
+        ("/steps", &Method::GET) => {
+            debug!("streaming steps");
+            stream_events(manager.subscribe(), |step| Some(to_json(&*step)))
+        }
+        ("/jobs", &Method::GET) => {
+            debug!("streaming finished jobs");
+            // only the caller's jobs, and the ones no one owns
+            stream_events(manager.subscribe_jobs(), move |finished| {
+                match (&finished.owner, &user) {
+                    (Some(owner), Some(user)) if owner != user => None,
+                    _ => Some(to_json(&finished)),
+                }
+            })
+        }
         (_, &Method::GET) => match statik.serve(req).wait() {
             Ok(resp) => {
                 debug!("returning static file");
@@ -90,29 +236,66 @@ impl Server {
         debug!("threads: {}", self.threads);
         debug!("address: {}", self.address);
 
-        let grid: Grid = if self.grid_file == "-" {
-            serde_yaml::from_reader(std::io::stdin())?
-        } else {
-            let reader = File::open(&self.grid_file)?;
-            serde_yaml::from_reader(reader)?
+        let tokens = match &self.tokens_file {
+            Some(path) => {
+                let tokens = Tokens::load(path)?;
+                info!("Loaded tokens from {}", path);
+                Some(Arc::new(tokens))
+            }
+            None => {
+                warn!("No tokens file given, so the RPC server is open to anyone");
+                None
+            }
         };
 
+        let grid = load_grid(&self.grid_file)?;
+
         debug!("Grid parsed.");
 
         let manager = Arc::new(Manager::new(self.should_sync, grid));
 
         debug!("Manager created.");
 
-        let mut io = IoHandler::default();
-        io.extend_with(manager.to_delegate());
+        #[cfg(feature = "pi")]
+        {
+            if let Some(path) = &self.pi_config {
+                let settings = puddle_pi::Settings::load(path)?;
+                manager.set_hardware(Box::new(puddle_pi::PiHardware::new(settings)))?;
+                info!("Driving the pi from {}", path);
+            }
+        }
+
+        let mut io = MetaIoHandler::default();
+        let context = Context::new(Arc::clone(&manager), self.grid_file.as_str());
+        let current_grid = context.grid_name();
+        io.extend_with(context.to_delegate());
 
         debug!("IoHandler created.");
 
+        // the server never stops on its own, so a signal is the only way
+        // out, and it doesn't drop the manager
+        let on_signal = Arc::clone(&manager);
+        ctrlc::set_handler(move || {
+            info!("Caught a signal, saving before exiting");
+            on_signal.shut_down();
+            std::process::exit(0);
+        })?;
+
         let statik = Static::new(&self.static_dir);
 
-        let server = ServerBuilder::new(io)
+        // the middleware turns away bad tokens, this tells the methods who
+        // the good ones belong to
+        let session_tokens = tokens.clone();
+        let sessions = move |req: &Request<Body>| match &session_tokens {
+            Some(tokens) => tokens.session(req),
+            None => Session::default(),
+        };
+
+        let server = ServerBuilder::with_meta_extractor(io, sessions)
             .threads(self.threads)
-            .request_middleware(move |req| serve(req, &statik))
+            .request_middleware(move |req| {
+                serve(req, &statik, &manager, &current_grid, tokens.as_deref())
+            })
             .start_http(&self.address)
             .expect("Unable to start RPC server");
 
@@ -125,6 +308,34 @@ impl Server {
     }
 }
 
+/// Parses a grid file and checks it, returning the grid and any warnings.
+/// The file name "-" means stdin.
+pub fn parse_grid(grid_file: &str) -> Result<(ParsedGrid, Vec<Diagnostic>), Box<dyn Error>> {
+    let pg: ParsedGrid = if grid_file == "-" {
+        serde_yaml::from_reader(std::io::stdin())?
+    } else {
+        let reader = File::open(grid_file)?;
+        serde_yaml::from_reader(reader)?
+    };
+    let diagnostics = pg.validate();
+    Ok((pg, diagnostics))
+}
+
+/// Loads a grid file, logging any problems with it. Fails if the grid has
+/// any errors.
+pub fn load_grid(grid_file: &str) -> Result<Grid, Box<dyn Error>> {
+    let (pg, diagnostics) = parse_grid(grid_file)?;
+    for d in &diagnostics {
+        if d.is_error() {
+            error!("{}: {}", grid_file, d);
+        } else {
+            warn!("{}: {}", grid_file, d);
+        }
+    }
+    let grid = Grid::try_from(pg)?;
+    Ok(grid)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -134,5 +345,24 @@ mod tests {
     fn test_parse() {
         let args = "progname --static dir/ --address 1.2.3.4:9999 --grid dir/file.ext --threads 12";
         Server::from_iter(args.split_whitespace());
+
+        let args = "progname --grid dir/file.ext --tokens dir/tokens.yaml";
+        let server = Server::from_iter(args.split_whitespace());
+        assert_eq!(server.tokens_file, Some("dir/tokens.yaml".into()));
+    }
+
+    #[test]
+    fn test_status_json() {
+        let manager = Manager::new(false, Grid::rectangle(3, 3));
+        let status = ServerStatus {
+            grid: grid_name("tests/arches/purpledrop.yaml"),
+            status: manager.status(),
+        };
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["grid"], "purpledrop");
+        assert_eq!(json["backend"], "sim");
+        assert_eq!(json["hardware"], serde_json::Value::Null);
+        assert_eq!(json["activity"], "idle");
+        assert_eq!(json["tick"], 0);
     }
 }
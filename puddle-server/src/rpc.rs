@@ -1,19 +1,44 @@
-use std::sync::Arc;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
 
 use jsonrpc_core::{Error, ErrorCode};
 use jsonrpc_derive::rpc;
 
 use log::*;
 
+use crate::auth::Session;
+use crate::{grid_name, parse_grid};
+
+use indexmap::IndexMap;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use puddle_core::command::SensorReading;
+use puddle_core::exec::{RunState, Snapshot};
+use puddle_core::grid::parse::{LocatedPeripheral, ParsedGrid};
 use puddle_core::prelude::*;
+use puddle_core::process::{JobId, JobStatus, Operation, ProcessHandle};
+use puddle_core::protocol::{Protocol, ProtocolError, Step};
+use puddle_core::util::seconds_duration;
+
+/// The longest `wait` will hold a request open, so a client can't tie up
+/// a server thread forever.
+const MAX_WAIT_SECONDS: f64 = 60.0;
 
-pub struct RpcError(PuddleError);
+pub struct RpcError(String);
 
 type RpcResult<T> = std::result::Result<T, RpcError>;
 
 impl From<PuddleError> for RpcError {
     fn from(p_err: PuddleError) -> Self {
-        Self(p_err)
+        Self(format!("PuddleError: {:?}", p_err))
+    }
+}
+
+impl From<ProtocolError> for RpcError {
+    fn from(p_err: ProtocolError) -> Self {
+        Self(format!("ProtocolError: {}", p_err))
     }
 }
 
@@ -21,91 +46,348 @@ impl From<RpcError> for Error {
     fn from(p_err: RpcError) -> Self {
         let code = ErrorCode::ServerError(0);
         let mut err = Error::new(code);
-        err.message = format!("PuddleError: {:?}", p_err.0);
+        err.message = p_err.0;
         err
     }
 }
 
 #[rpc]
 pub trait Rpc {
-    #[rpc(name = "new_process")]
-    fn new_process(&self, name: String) -> RpcResult<ProcessId>;
+    type Metadata;
 
-    #[rpc(name = "close_process")]
-    fn close_process(&self, pid: ProcessId) -> RpcResult<()>;
+    #[rpc(meta, name = "new_process")]
+    fn new_process(&self, session: Self::Metadata, name: String) -> RpcResult<ProcessId>;
 
-    #[rpc(name = "droplet_info")]
-    fn droplet_info(&self, pid: ProcessId) -> RpcResult<Vec<DropletInfo>>;
+    #[rpc(meta, name = "close_process")]
+    fn close_process(&self, session: Self::Metadata, pid: ProcessId) -> RpcResult<()>;
+
+    #[rpc(meta, name = "pause")]
+    fn pause(&self, session: Self::Metadata) -> RpcResult<()>;
+
+    #[rpc(meta, name = "resume")]
+    fn resume(&self, session: Self::Metadata) -> RpcResult<()>;
+
+    #[rpc(meta, name = "step")]
+    fn step(&self, session: Self::Metadata, ticks: usize) -> RpcResult<()>;
+
+    #[rpc(name = "run_state")]
+    fn run_state(&self) -> RpcResult<RunState>;
+
+    #[rpc(meta, name = "kill_process")]
+    fn kill_process(&self, session: Self::Metadata, pid: ProcessId) -> RpcResult<()>;
+
+    #[rpc(meta, name = "set_quota")]
+    fn set_quota(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        quota: Option<usize>,
+    ) -> RpcResult<()>;
+
+    #[rpc(meta, name = "droplet_info")]
+    fn droplet_info(&self, session: Self::Metadata, pid: ProcessId) -> RpcResult<Vec<DropletInfo>>;
+
+    #[rpc(name = "get_grid")]
+    fn get_grid(&self) -> RpcResult<ParsedGrid>;
+
+    #[rpc(meta, name = "load_grid")]
+    fn load_grid(&self, session: Self::Metadata, grid: ParsedGrid, name: String) -> RpcResult<()>;
+
+    #[rpc(name = "list_peripherals")]
+    fn list_peripherals(&self) -> RpcResult<Vec<LocatedPeripheral>>;
+
+    #[rpc(meta, name = "reload_grid")]
+    fn reload_grid(&self, session: Self::Metadata) -> RpcResult<Vec<String>>;
 
     #[rpc(name = "visualizer_droplet_info")]
     fn visualizer_droplet_info(&self) -> RpcResult<Vec<DropletInfo>>;
 
-    #[rpc(name = "flush")]
-    fn flush(&self, pid: ProcessId) -> RpcResult<()>;
+    #[rpc(name = "snapshot")]
+    fn snapshot(&self) -> RpcResult<Snapshot>;
+
+    #[rpc(meta, name = "flush")]
+    fn flush(&self, session: Self::Metadata, pid: ProcessId) -> RpcResult<()>;
 
-    #[rpc(name = "create")]
+    #[rpc(meta, name = "flush_async")]
+    fn flush_async(&self, session: Self::Metadata, pid: ProcessId) -> RpcResult<JobId>;
+
+    #[rpc(meta, name = "droplet_info_async")]
+    fn droplet_info_async(&self, session: Self::Metadata, pid: ProcessId) -> RpcResult<JobId>;
+
+    #[rpc(meta, name = "job_status")]
+    fn job_status(&self, session: Self::Metadata, job: JobId) -> RpcResult<JobStatus>;
+
+    #[rpc(meta, name = "wait")]
+    fn wait(
+        &self,
+        session: Self::Metadata,
+        job: JobId,
+        timeout_seconds: f64,
+    ) -> RpcResult<JobStatus>;
+
+    #[rpc(meta, name = "submit")]
+    fn submit(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        steps: Vec<Step>,
+        droplets: IndexMap<String, DropletId>,
+    ) -> RpcResult<IndexMap<String, DropletId>>;
+
+    #[rpc(meta, name = "create")]
     fn create(
         &self,
+        session: Self::Metadata,
         pid: ProcessId,
         loc: Option<Location>,
         vol: f64,
         dim: Option<Location>,
     ) -> RpcResult<DropletId>;
 
-    #[rpc(name = "input")]
-    fn input(&self, pid: ProcessId, name: String, vol: f64, dim: Location) -> RpcResult<DropletId>;
+    #[rpc(meta, name = "input")]
+    fn input(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        name: String,
+        vol: f64,
+        dim: Location,
+    ) -> RpcResult<DropletId>;
 
-    #[rpc(name = "output")]
-    fn output(&self, pid: ProcessId, name: String, d: DropletId) -> RpcResult<()>;
+    #[rpc(meta, name = "output")]
+    fn output(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        name: String,
+        d: DropletId,
+    ) -> RpcResult<()>;
 
-    #[rpc(name = "move")]
-    fn move_droplet(&self, pid: ProcessId, d: DropletId, loc: Location) -> RpcResult<DropletId>;
+    #[rpc(meta, name = "move")]
+    fn move_droplet(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        d: DropletId,
+        loc: Location,
+    ) -> RpcResult<DropletId>;
 
-    #[rpc(name = "mix")]
-    fn mix(&self, pid: ProcessId, d1: DropletId, d2: DropletId) -> RpcResult<DropletId>;
+    #[rpc(meta, name = "mix")]
+    fn mix(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        d1: DropletId,
+        d2: DropletId,
+    ) -> RpcResult<DropletId>;
 
-    #[rpc(name = "combine_into")]
-    fn combine_into(&self, pid: ProcessId, d1: DropletId, d2: DropletId) -> RpcResult<DropletId>;
+    #[rpc(meta, name = "combine")]
+    fn combine(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        d1: DropletId,
+        d2: DropletId,
+    ) -> RpcResult<DropletId>;
 
-    #[rpc(name = "split")]
-    fn split(&self, pid: ProcessId, d: DropletId) -> RpcResult<(DropletId, DropletId)>;
+    #[rpc(meta, name = "combine_into")]
+    fn combine_into(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        d1: DropletId,
+        d2: DropletId,
+    ) -> RpcResult<DropletId>;
 
-    #[rpc(name = "heat")]
+    #[rpc(meta, name = "agitate")]
+    fn agitate(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        d: DropletId,
+    ) -> RpcResult<DropletId>;
+
+    #[rpc(meta, name = "split")]
+    fn split(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        d: DropletId,
+    ) -> RpcResult<(DropletId, DropletId)>;
+
+    #[rpc(meta, name = "heat")]
     fn heat(
         &self,
+        session: Self::Metadata,
         pid: ProcessId,
         d: DropletId,
         temperature: f32,
         seconds: f64,
     ) -> RpcResult<DropletId>;
+
+    #[rpc(meta, name = "sense")]
+    fn sense(
+        &self,
+        session: Self::Metadata,
+        pid: ProcessId,
+        d: DropletId,
+        sensor: String,
+    ) -> RpcResult<(DropletId, SensorReading)>;
+
+    #[rpc(meta, name = "apply")]
+    fn apply(&self, session: Self::Metadata, pid: ProcessId, op: Operation) -> RpcResult<Value>;
+}
+
+/// What the RPC methods have to work with.
+pub struct Context {
+    manager: Arc<Manager>,
+    grid_file: String,
+    /// The name of the grid that's loaded now, which is only the grid
+    /// file's until another one is loaded.
+    grid_name: Arc<Mutex<String>>,
+}
+
+impl Context {
+    pub fn new(manager: Arc<Manager>, grid_file: impl Into<String>) -> Context {
+        let grid_file = grid_file.into();
+        let name = grid_name(&grid_file).to_string();
+        Context {
+            manager,
+            grid_file,
+            grid_name: Arc::new(Mutex::new(name)),
+        }
+    }
+
+    /// The loaded grid's name, kept up as grids are loaded, for `/status`.
+    pub fn grid_name(&self) -> Arc<Mutex<String>> {
+        Arc::clone(&self.grid_name)
+    }
+}
+
+impl Context {
+    /// Fails if the process belongs to someone other than the caller.
+    fn authorize(&self, session: &Session, pid: ProcessId) -> RpcResult<()> {
+        if let Some(user) = &session.user {
+            self.manager.check_owner(pid, user)?;
+        }
+        Ok(())
+    }
+
+    /// Fails if the job belongs to someone other than the caller.
+    fn authorize_job(&self, session: &Session, job: JobId) -> RpcResult<()> {
+        if let Some(user) = &session.user {
+            self.manager.check_job_owner(job, user)?;
+        }
+        Ok(())
+    }
+
+    /// Fails unless the caller is an admin, for things that affect everyone.
+    fn require_admin(&self, session: &Session) -> RpcResult<()> {
+        if session.is_admin() {
+            return Ok(());
+        }
+        let user = session.user.as_deref().unwrap_or_default();
+        Err(RpcError(format!("{} is not an admin", user)))
+    }
+
+    /// Does the operation through `Process::apply`, just like the `apply`
+    /// method, and reads the result back as what the named method returns.
+    /// That way the named methods can't do anything `apply` can't.
+    fn apply_as<T: DeserializeOwned>(
+        &self,
+        session: &Session,
+        pid: ProcessId,
+        op: Operation,
+    ) -> RpcResult<T> {
+        let p = self.process(session, pid)?;
+        let result = p.apply(op)?;
+        serde_json::from_value(result)
+            .map_err(|err| RpcError(format!("Unexpected result: {}", err)))
+    }
+
+    /// Gets the process, as long as it's the caller's to use.
+    fn process(&self, session: &Session, pid: ProcessId) -> RpcResult<ProcessHandle> {
+        self.authorize(session, pid)?;
+        let p = self.get_process(pid)?;
+        Ok(p)
+    }
 }
 
-impl Rpc for Arc<Manager> {
+impl Deref for Context {
+    type Target = Manager;
+    fn deref(&self) -> &Manager {
+        &self.manager
+    }
+}
+
+impl Rpc for Context {
+    type Metadata = Session;
+
     //
     // process management commands
     //
 
-    fn new_process(&self, name: String) -> RpcResult<ProcessId> {
+    fn new_process(&self, session: Session, name: String) -> RpcResult<ProcessId> {
         // can't the function being implemented, use fully qualified name
-        debug!("new_process(name={})", name);
-        let pid = Manager::new_process(&self, name)?;
+        debug!("new_process(name={}, user={:?})", name, session.user);
+        let pid = Manager::new_owned_process(&self, name, session.user)?;
         Ok(pid)
     }
 
-    fn close_process(&self, pid: ProcessId) -> RpcResult<()> {
+    fn close_process(&self, session: Session, pid: ProcessId) -> RpcResult<()> {
         // can't the call function being implemented, use fully qualified name
         debug!("close_process(pid={})", pid);
+        self.authorize(&session, pid)?;
         Manager::close_process(&self, pid)?;
         Ok(())
     }
 
+    fn pause(&self, session: Session) -> RpcResult<()> {
+        debug!("pause()");
+        self.require_admin(&session)?;
+        Manager::pause(&self);
+        Ok(())
+    }
+
+    fn resume(&self, session: Session) -> RpcResult<()> {
+        debug!("resume()");
+        self.require_admin(&session)?;
+        Manager::resume(&self);
+        Ok(())
+    }
+
+    fn step(&self, session: Session, ticks: usize) -> RpcResult<()> {
+        debug!("step(ticks={})", ticks);
+        self.require_admin(&session)?;
+        Manager::step(&self, ticks);
+        Ok(())
+    }
+
+    fn run_state(&self) -> RpcResult<RunState> {
+        debug!("run_state()");
+        Ok(Manager::run_state(&self))
+    }
+
+    fn kill_process(&self, session: Session, pid: ProcessId) -> RpcResult<()> {
+        // can't the call function being implemented, use fully qualified name
+        debug!("kill_process(pid={})", pid);
+        self.authorize(&session, pid)?;
+        Manager::kill_process(&self, pid)?;
+        Ok(())
+    }
+
+    fn set_quota(&self, session: Session, pid: ProcessId, quota: Option<usize>) -> RpcResult<()> {
+        debug!("set_quota(pid={}, quota={:?})", pid, quota);
+        self.apply_as(&session, pid, Operation::SetQuota { quota })
+    }
+
     //
     // status commands
     //
 
-    fn droplet_info(&self, pid: ProcessId) -> RpcResult<Vec<DropletInfo>> {
+    fn droplet_info(&self, session: Session, pid: ProcessId) -> RpcResult<Vec<DropletInfo>> {
         debug!("droplet_info(pid={})", pid);
-        let p = self.get_process(pid)?;
+        let p = self.process(&session, pid)?;
         let info = p.flush()?;
         Ok(info)
     }
@@ -117,20 +399,116 @@ impl Rpc for Arc<Manager> {
         Ok(info)
     }
 
+    fn get_grid(&self) -> RpcResult<ParsedGrid> {
+        debug!("get_grid()");
+        Ok(Manager::get_grid(&self))
+    }
+
+    fn load_grid(&self, session: Session, grid: ParsedGrid, name: String) -> RpcResult<()> {
+        debug!("load_grid(..., name={})", name);
+        self.require_admin(&session)?;
+        Manager::load_grid(&self, grid)?;
+        *self.grid_name.lock().unwrap() = name;
+        Ok(())
+    }
+
+    fn list_peripherals(&self) -> RpcResult<Vec<LocatedPeripheral>> {
+        debug!("list_peripherals()");
+        Ok(Manager::list_peripherals(&self))
+    }
+
+    /// Reads the server's grid file again and swaps it in, returning any
+    /// warnings about it.
+    fn reload_grid(&self, session: Session) -> RpcResult<Vec<String>> {
+        info!("reload_grid() from {}", self.grid_file);
+        self.require_admin(&session)?;
+        let (grid, diagnostics) = parse_grid(&self.grid_file)
+            .map_err(|err| RpcError(format!("Failed to read {}: {}", self.grid_file, err)))?;
+        // load_grid checks for errors too, but these are more helpful
+        if let Some(d) = diagnostics.iter().find(|d| d.is_error()) {
+            return Err(RpcError(format!("{}: {}", self.grid_file, d)));
+        }
+        Manager::load_grid(&self, grid)?;
+        *self.grid_name.lock().unwrap() = grid_name(&self.grid_file).to_string();
+        Ok(diagnostics.iter().map(|d| d.to_string()).collect())
+    }
+
+    fn snapshot(&self) -> RpcResult<Snapshot> {
+        debug!("snapshot()");
+        Ok(Manager::snapshot(&self))
+    }
+
     //
     // Droplet manipulation
     // delegate to process
     //
 
-    fn flush(&self, pid: ProcessId) -> RpcResult<()> {
+    fn flush(&self, session: Session, pid: ProcessId) -> RpcResult<()> {
         debug!("flush(pid={})", pid);
-        let p = self.get_process(pid)?;
-        let _info = p.flush()?;
+        let _info: Value = self.apply_as(&session, pid, Operation::Flush)?;
         Ok(())
     }
 
+    fn flush_async(&self, session: Session, pid: ProcessId) -> RpcResult<JobId> {
+        debug!("flush_async(pid={})", pid);
+        self.authorize(&session, pid)?;
+        let job = Manager::flush_async(&self, pid)?;
+        Ok(job)
+    }
+
+    fn droplet_info_async(&self, session: Session, pid: ProcessId) -> RpcResult<JobId> {
+        debug!("droplet_info_async(pid={})", pid);
+        // flushing already gives back the droplet info
+        self.authorize(&session, pid)?;
+        let job = Manager::flush_async(&self, pid)?;
+        Ok(job)
+    }
+
+    fn job_status(&self, session: Session, job: JobId) -> RpcResult<JobStatus> {
+        debug!("job_status(job={})", job);
+        self.authorize_job(&session, job)?;
+        let status = Manager::job_status(&self, job)?;
+        Ok(status)
+    }
+
+    fn wait(&self, session: Session, job: JobId, timeout_seconds: f64) -> RpcResult<JobStatus> {
+        debug!("wait(job={}, timeout_seconds={})", job, timeout_seconds);
+        self.authorize_job(&session, job)?;
+        if timeout_seconds.is_nan() || timeout_seconds < 0.0 {
+            let msg = format!("Timeout can't be {} seconds", timeout_seconds);
+            return Err(RpcError(msg));
+        }
+        let timeout = seconds_duration(timeout_seconds.min(MAX_WAIT_SECONDS));
+        let status = Manager::wait(&self, job, timeout)?;
+        Ok(status)
+    }
+
+    fn submit(
+        &self,
+        session: Session,
+        pid: ProcessId,
+        steps: Vec<Step>,
+        droplets: IndexMap<String, DropletId>,
+    ) -> RpcResult<IndexMap<String, DropletId>> {
+        debug!(
+            "submit(pid={}, {} steps, droplets={:?})",
+            pid,
+            steps.len(),
+            droplets
+        );
+        let p = self.process(&session, pid)?;
+        let protocol = Protocol {
+            name: format!("submitted to {}", pid),
+            params: IndexMap::new(),
+            steps,
+        };
+        let env = p.submit(&protocol, droplets)?;
+        Ok(env)
+    }
+
     fn create(
         &self,
+        session: Session,
         pid: ProcessId,
         loc: Option<Location>,
         vol: f64,
@@ -140,58 +518,118 @@ impl Rpc for Arc<Manager> {
             "create(pid={}, loc={:?}, vol={}, dim={:?})",
             pid, loc, vol, dim
         );
-        let p = self.get_process(pid)?;
-        let id = p.create(loc, vol, dim)?;
-        Ok(id)
+        let op = Operation::Create {
+            location: loc,
+            volume: vol,
+            dimensions: dim,
+        };
+        self.apply_as(&session, pid, op)
     }
 
-    fn input(&self, pid: ProcessId, name: String, vol: f64, dim: Location) -> RpcResult<DropletId> {
+    fn input(
+        &self,
+        session: Session,
+        pid: ProcessId,
+        name: String,
+        vol: f64,
+        dim: Location,
+    ) -> RpcResult<DropletId> {
         debug!(
             "input(pid={}, name={}, vol={}, dim={:?})",
             pid, name, vol, dim
         );
-        let p = self.get_process(pid)?;
-        let id = p.input(name, vol, dim)?;
-        Ok(id)
+        let op = Operation::Input {
+            port: name,
+            volume: vol,
+            dimensions: dim,
+        };
+        self.apply_as(&session, pid, op)
     }
 
-    fn output(&self, pid: ProcessId, name: String, d: DropletId) -> RpcResult<()> {
+    fn output(
+        &self,
+        session: Session,
+        pid: ProcessId,
+        name: String,
+        d: DropletId,
+    ) -> RpcResult<()> {
         debug!("output(pid={}, name={}, d={:?})", pid, name, d);
-        let p = self.get_process(pid)?;
-        p.output(name, d)?;
-        Ok(())
+        let op = Operation::Output {
+            port: name,
+            droplet: d,
+        };
+        self.apply_as(&session, pid, op)
     }
 
-    fn move_droplet(&self, pid: ProcessId, d: DropletId, loc: Location) -> RpcResult<DropletId> {
+    fn move_droplet(
+        &self,
+        session: Session,
+        pid: ProcessId,
+        d: DropletId,
+        loc: Location,
+    ) -> RpcResult<DropletId> {
         debug!("move_droplet(pid={}, d={:?}, loc={:?})", pid, d, loc);
-        let p = self.get_process(pid)?;
-        let id = p.move_droplet(d, loc)?;
-        Ok(id)
+        let op = Operation::Move {
+            droplet: d,
+            to: loc,
+        };
+        self.apply_as(&session, pid, op)
     }
 
-    fn mix(&self, pid: ProcessId, d1: DropletId, d2: DropletId) -> RpcResult<DropletId> {
+    fn mix(
+        &self,
+        session: Session,
+        pid: ProcessId,
+        d1: DropletId,
+        d2: DropletId,
+    ) -> RpcResult<DropletId> {
         debug!("mix(pid={}, d1={:?}, d2={:?})", pid, d1, d2);
-        let p = self.get_process(pid)?;
-        let id = p.mix(d1, d2)?;
-        Ok(id)
+        let op = Operation::Mix { droplets: (d1, d2) };
+        self.apply_as(&session, pid, op)
+    }
+
+    fn combine(
+        &self,
+        session: Session,
+        pid: ProcessId,
+        d1: DropletId,
+        d2: DropletId,
+    ) -> RpcResult<DropletId> {
+        debug!("combine(pid={}, d1={:?}, d2={:?})", pid, d1, d2);
+        let op = Operation::Combine { droplets: (d1, d2) };
+        self.apply_as(&session, pid, op)
+    }
+
+    fn agitate(&self, session: Session, pid: ProcessId, d: DropletId) -> RpcResult<DropletId> {
+        debug!("agitate(pid={}, d={:?})", pid, d);
+        self.apply_as(&session, pid, Operation::Agitate { droplet: d })
     }
 
-    fn combine_into(&self, pid: ProcessId, d1: DropletId, d2: DropletId) -> RpcResult<DropletId> {
+    fn combine_into(
+        &self,
+        session: Session,
+        pid: ProcessId,
+        d1: DropletId,
+        d2: DropletId,
+    ) -> RpcResult<DropletId> {
         debug!("combine_into(pid={}, d1={:?}, d2={:?})", pid, d1, d2);
-        let p = self.get_process(pid)?;
-        let id = p.combine_into(d1, d2)?;
-        Ok(id)
+        let op = Operation::CombineInto { droplets: (d1, d2) };
+        self.apply_as(&session, pid, op)
     }
 
-    fn split(&self, pid: ProcessId, d: DropletId) -> RpcResult<(DropletId, DropletId)> {
+    fn split(
+        &self,
+        session: Session,
+        pid: ProcessId,
+        d: DropletId,
+    ) -> RpcResult<(DropletId, DropletId)> {
         debug!("split(pid={}, d={:?})", pid, d);
-        let p = self.get_process(pid)?;
-        let id = p.split(d)?;
-        Ok(id)
+        self.apply_as(&session, pid, Operation::Split { droplet: d })
     }
 
     fn heat(
         &self,
+        session: Session,
         pid: ProcessId,
         d: DropletId,
         temperature: f32,
@@ -201,8 +639,210 @@ impl Rpc for Arc<Manager> {
             "heat(pid={}, d={:?}, temp={}, seconds={})",
             pid, d, temperature, seconds
         );
-        let p = self.get_process(pid)?;
-        let id = p.heat(d, temperature, seconds)?;
-        Ok(id)
+        let op = Operation::Heat {
+            droplet: d,
+            temperature,
+            seconds,
+        };
+        self.apply_as(&session, pid, op)
+    }
+
+    fn sense(
+        &self,
+        session: Session,
+        pid: ProcessId,
+        d: DropletId,
+        sensor: String,
+    ) -> RpcResult<(DropletId, SensorReading)> {
+        debug!("sense(pid={}, d={:?}, sensor={})", pid, d, sensor);
+        let op = Operation::Sense { droplet: d, sensor };
+        self.apply_as(&session, pid, op)
+    }
+
+    /// Any operation from the table in `process::ops`.
+    fn apply(&self, session: Session, pid: ProcessId, op: Operation) -> RpcResult<Value> {
+        debug!("apply(pid={}, op={:?})", pid, op);
+        let p = self.process(&session, pid)?;
+        let result = p.apply(op)?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use jsonrpc_core::MetaIoHandler;
+    use puddle_core::process::OPERATIONS;
+
+    fn handler() -> MetaIoHandler<Session> {
+        handler_with_grid_file("grid.yaml")
+    }
+
+    fn handler_with_grid_file(grid_file: &str) -> MetaIoHandler<Session> {
+        let manager = Arc::new(Manager::new(false, Grid::rectangle(5, 5)));
+        let mut io = MetaIoHandler::default();
+        io.extend_with(Context::new(manager, grid_file).to_delegate());
+        io
+    }
+
+    /// Only the user called "admin" is one.
+    fn session(user: &str) -> Session {
+        Session {
+            user: Some(user.into()),
+            admin: user == "admin",
+        }
+    }
+
+    fn call(io: &MetaIoHandler<Session>, method: &str, params: &str, user: &str) -> Value {
+        let request = format!(
+            r#"{{"jsonrpc": "2.0", "method": "{}", "params": {}, "id": 1}}"#,
+            method, params
+        );
+        let response = io.handle_request_sync(&request, session(user)).unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[test]
+    fn every_operation_has_a_method() {
+        let io = handler();
+
+        for name in OPERATIONS {
+            // bad params are fine, we just want the method to be there
+            let request = format!(
+                r#"{{"jsonrpc": "2.0", "method": "{}", "params": [], "id": 1}}"#,
+                name
+            );
+            let response = io
+                .handle_request_sync(&request, Session::default())
+                .unwrap();
+            assert!(!response.contains("-32601"), "no method for {}", name);
+        }
+    }
+
+    #[test]
+    fn only_owners_can_use_processes() {
+        let io = handler();
+        let call = |method: &str, params: &str, user: &str| call(&io, method, params, user);
+
+        let pid = call("new_process", r#"["mine"]"#, "alice")["result"].clone();
+        let params = format!("[{}]", pid);
+
+        let stolen = call("flush", &params, "bob");
+        let message = stolen["error"]["message"].as_str().unwrap();
+        assert!(message.contains("NotOwner"), "{}", message);
+
+        let flushed = call("flush", &params, "alice");
+        assert_eq!(flushed["result"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn named_methods_match_apply() {
+        let io = handler();
+        let call = |method: &str, params: String| {
+            let response = call(&io, method, &params, "alice");
+            assert!(response["error"].is_null(), "{}: {}", method, response);
+            response["result"].clone()
+        };
+
+        let pid = call("new_process", r#"["mine"]"#.into());
+        let a = call(
+            "create",
+            format!(r#"[{}, {{"y": 0, "x": 0}}, 1.0, null]"#, pid),
+        );
+        let op = r#"{"op": "create", "location": {"y": 4, "x": 4}, "volume": 1.0}"#;
+        let b = call("apply", format!("[{}, {}]", pid, op));
+
+        let a = call("move", format!(r#"[{}, {}, {{"y": 2, "x": 0}}]"#, pid, a));
+        let op = format!(
+            r#"{{"op": "move", "droplet": {}, "to": {{"y": 2, "x": 4}}}}"#,
+            b
+        );
+        let b = call("apply", format!("[{}, {}]", pid, op));
+
+        let ab = call("mix", format!("[{}, {}, {}]", pid, a, b));
+        let halves = call("split", format!("[{}, {}]", pid, ab));
+        assert_eq!(halves.as_array().unwrap().len(), 2);
+
+        assert_eq!(call("flush", format!("[{}]", pid)), Value::Null);
+        let info = call("droplet_info", format!("[{}]", pid));
+        assert_eq!(info.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reload_grid() {
+        use std::fs;
+
+        let path = std::env::temp_dir().join(format!("puddle-reload-{}.yaml", std::process::id()));
+        let grid_file = path.to_str().unwrap();
+        let manager = Arc::new(Manager::new(false, Grid::rectangle(5, 5)));
+        let context = Context::new(manager, grid_file);
+        let name = context.grid_name();
+        let mut io = MetaIoHandler::default();
+        io.extend_with(context.to_delegate());
+        let file_name = format!("puddle-reload-{}", std::process::id());
+        assert_eq!(*name.lock().unwrap(), file_name);
+
+        let bigger = ParsedGrid::from(Grid::rectangle(6, 6));
+        fs::write(&path, serde_yaml::to_string(&bigger).unwrap()).unwrap();
+
+        let denied = call(&io, "reload_grid", "[]", "bob");
+        let message = denied["error"]["message"].as_str().unwrap();
+        assert!(message.contains("not an admin"), "{}", message);
+        let load_params = format!("[{}, \"bigger\"]", serde_json::to_string(&bigger).unwrap());
+        let denied = call(&io, "load_grid", &load_params, "bob");
+        let message = denied["error"]["message"].as_str().unwrap();
+        assert!(message.contains("not an admin"), "{}", message);
+
+        // the status goes by the grid that's loaded, not the file
+        let loaded = call(&io, "load_grid", &load_params, "admin");
+        assert!(loaded["error"].is_null(), "{}", loaded);
+        assert_eq!(*name.lock().unwrap(), "bigger");
+
+        let reloaded = call(&io, "reload_grid", "[]", "admin");
+        assert!(reloaded["result"].is_array(), "{}", reloaded);
+        assert_eq!(*name.lock().unwrap(), file_name);
+        let grid = call(&io, "get_grid", "[]", "bob");
+        assert_eq!(grid["result"], serde_json::to_value(&bigger).unwrap());
+
+        // a broken file leaves the grid alone
+        fs::write(&path, "board: [[").unwrap();
+        let broken = call(&io, "reload_grid", "[]", "admin");
+        let message = broken["error"]["message"].as_str().unwrap();
+        assert!(message.contains("Failed to read"), "{}", message);
+        let grid = call(&io, "get_grid", "[]", "bob");
+        assert_eq!(grid["result"], serde_json::to_value(&bigger).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn only_admins_run_the_board() {
+        let io = handler();
+        for (method, params) in &[("pause", "[]"), ("step", "[1]"), ("resume", "[]")] {
+            let denied = call(&io, method, params, "bob");
+            let message = denied["error"]["message"].as_str().unwrap();
+            assert!(message.contains("not an admin"), "{}: {}", method, message);
+
+            let allowed = call(&io, method, params, "admin");
+            assert!(allowed["error"].is_null(), "{}: {}", method, allowed);
+        }
+    }
+
+    #[test]
+    fn jobs_are_only_seen_by_their_owners() {
+        let io = handler();
+        let pid = call(&io, "new_process", r#"["mine"]"#, "alice")["result"].clone();
+        let job = call(&io, "flush_async", &format!("[{}]", pid), "alice")["result"].clone();
+
+        let params = format!("[{}, 10.0]", job);
+        let snooped = call(&io, "wait", &params, "bob");
+        let message = snooped["error"]["message"].as_str().unwrap();
+        assert!(message.contains("NonExistentJob"), "{}", message);
+        let snooped = call(&io, "job_status", &format!("[{}]", job), "bob");
+        assert!(snooped["error"].is_object());
+
+        let waited = call(&io, "wait", &params, "alice");
+        assert_eq!(waited["result"]["state"], "done");
     }
 }